@@ -12,7 +12,7 @@ struct TableAttr {
 }
 
 struct AttrInput {
-    paren_token: syn::token::Paren,
+    _paren_token: syn::token::Paren,
     attrs: syn::punctuated::Punctuated<KeyValue, syn::Token![,]>,
 }
 
@@ -20,14 +20,14 @@ impl Parse for AttrInput {
     fn parse(input: ParseStream) -> Result<Self> {
         let content;
         Ok(AttrInput {
-            paren_token: syn::parenthesized!(content in input),
+            _paren_token: syn::parenthesized!(content in input),
             attrs: content.parse_terminated(KeyValue::parse)?,
         })
     }
 }
 
 impl AttrInput {
-    fn to_table_attr(self, table_name: String) -> TableAttr {
+    fn into_table_attr(self, table_name: String) -> TableAttr {
         let mut table = TableAttr {
             table_name,
             primary_key: vec![],
@@ -58,7 +58,52 @@ impl AttrInput {
         table
     }
 
-    fn to_attr_map(self) -> HashMap<String, Universe> {
+    fn is_relation(&self) -> bool {
+        self.attrs.iter().any(|attr| {
+            let key = format!("{}", attr.key);
+            key == "belongs_to" || key == "has_many" || key == "has_many_through"
+        })
+    }
+
+    fn into_relation(self) -> Relation {
+        let mut target = None;
+        let mut through = None;
+        let mut foreign_key = None;
+        let mut target_key = None;
+
+        for attr in self.attrs.into_iter() {
+            let key = format!("{}", attr.key);
+            match key.as_str() {
+                "belongs_to" | "has_many" | "has_many_through" => {
+                    let ty = syn::parse_str::<syn::Type>(&attr.value.as_str().unwrap()).unwrap();
+                    target = Some((key, ty));
+                }
+                "through" => {
+                    let ty = syn::parse_str::<syn::Type>(&attr.value.as_str().unwrap()).unwrap();
+                    through = Some(ty);
+                }
+                "foreign_key" => foreign_key = attr.value.as_str(),
+                "target_key" => target_key = attr.value.as_str(),
+                d => panic!("unsupported attribute: {}", d),
+            }
+        }
+
+        let (kind, target) = target.unwrap();
+        let foreign_key =
+            foreign_key.unwrap_or_else(|| panic!("{}: foreign_key must be specified", kind));
+        match kind.as_str() {
+            "belongs_to" => Relation::BelongsTo(target, foreign_key),
+            "has_many" => Relation::HasMany(target, foreign_key),
+            _ => Relation::HasManyThrough(
+                target,
+                through.unwrap_or_else(|| panic!("{}: through must be specified", kind)),
+                foreign_key,
+                target_key.unwrap_or_else(|| panic!("{}: target_key must be specified", kind)),
+            ),
+        }
+    }
+
+    fn into_attr_map(self) -> HashMap<String, Universe> {
         let mut result = HashMap::new();
 
         for attr in self.attrs.into_iter() {
@@ -69,12 +114,21 @@ impl AttrInput {
     }
 }
 
+enum Relation {
+    // parent type and the foreign key column of this table
+    BelongsTo(syn::Type, String),
+    // child type and the foreign key column of the child table
+    HasMany(syn::Type, String),
+    // target type, join table type, and the columns of the join table referring to
+    // this table and to the target table
+    HasManyThrough(syn::Type, syn::Type, String, String),
+}
+
 #[derive(Clone)]
 enum Universe {
     VStr(String),
     VI32(i32),
     VBool(bool),
-    VType(syn::Type),
 }
 
 impl Parse for Universe {
@@ -99,26 +153,26 @@ impl Parse for Universe {
 }
 
 impl Universe {
-    fn as_str(self) -> Option<String> {
+    fn as_str(&self) -> Option<String> {
         use Universe::*;
         match self {
-            VStr(s) => Some(s),
+            VStr(s) => Some(s.clone()),
             _ => None,
         }
     }
 
-    fn as_i32(self) -> Option<i32> {
+    fn as_i32(&self) -> Option<i32> {
         use Universe::*;
         match self {
-            VI32(i) => Some(i),
+            VI32(i) => Some(*i),
             _ => None,
         }
     }
 
-    fn as_bool(self) -> Option<bool> {
+    fn as_bool(&self) -> Option<bool> {
         use Universe::*;
         match self {
-            VBool(b) => Some(b),
+            VBool(b) => Some(*b),
             _ => None,
         }
     }
@@ -126,7 +180,7 @@ impl Universe {
 
 struct KeyValue {
     key: proc_macro2::Ident,
-    punct: syn::Token![=],
+    _punct: syn::Token![=],
     value: Universe,
 }
 
@@ -134,7 +188,7 @@ impl Parse for KeyValue {
     fn parse(input: ParseStream) -> Result<Self> {
         Ok(KeyValue {
             key: input.parse()?,
-            punct: input.parse()?,
+            _punct: input.parse()?,
             value: input.parse()?,
         })
     }
@@ -152,13 +206,13 @@ fn get_fields_from_datastruct(
                     result.push((
                         name.ident.as_ref().unwrap().clone(),
                         name.ty.clone(),
-                        if name.attrs.len() == 0 {
+                        if name.attrs.is_empty() {
                             HashMap::new()
                         } else {
                             // TODO: Only first FieldAttr will be effective
                             syn::parse2::<AttrInput>(name.attrs[0].tokens.clone())
                                 .unwrap()
-                                .to_attr_map()
+                                .into_attr_map()
                         },
                     ));
                 }
@@ -171,6 +225,26 @@ fn get_fields_from_datastruct(
     result
 }
 
+// Column name of a field. A raw identifier such as `r#type` is the column `type`.
+fn column_name(ident: &proc_macro2::Ident) -> String {
+    ident.to_string().trim_start_matches("r#").to_string()
+}
+
+// Table names are interpolated into queries, so only plain identifiers are allowed,
+// optionally qualified with a schema name such as `app.user`.
+fn is_valid_table_name(table_name: &str) -> bool {
+    let parts = table_name.split('.').collect::<Vec<_>>();
+
+    parts.len() <= 2
+        && parts.iter().all(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
 fn option_to_quote<T: quote::ToTokens>(opt: Option<T>) -> proc_macro2::TokenStream {
     if opt.is_some() {
         quote! { Some(#opt) }
@@ -184,27 +258,36 @@ pub fn derive_record(input: proc_macro::TokenStream) -> proc_macro::TokenStream
     let input = parse_macro_input!(input as DeriveInput);
 
     let ident = input.ident;
-    if input.attrs.is_empty() {
-        panic!("Currently, sql(table_name),sql(sql_type) and primary_key_columns(comma_separated_string) are required.");
-    }
 
-    let attr_stream = input.attrs[0].tokens.clone();
-    let table_attr = syn::parse2::<AttrInput>(attr_stream)
-        .unwrap()
-        .to_table_attr(format!("{}", ident));
+    let mut table_attr = None;
+    let mut relations = vec![];
+    for attr in input.attrs.iter().filter(|attr| attr.path.is_ident("sql")) {
+        let attr_input = syn::parse2::<AttrInput>(attr.tokens.clone()).unwrap();
+        if attr_input.is_relation() {
+            relations.push(attr_input.into_relation());
+        } else {
+            table_attr = Some(attr_input.into_table_attr(format!("{}", ident)));
+        }
+    }
+    let table_attr = table_attr.unwrap_or_else(|| {
+        panic!("Currently, sql(table_name),sql(sql_type) and primary_key_columns(comma_separated_string) are required.")
+    });
     let table_name = table_attr.table_name;
+    if !is_valid_table_name(&table_name) {
+        panic!("table_name: {} is not a valid identifier", table_name)
+    }
 
     let field_struct = get_fields_from_datastruct(input.data);
 
     let primary_key_columns = table_attr.primary_key;
-    if primary_key_columns.len() == 0 {
+    if primary_key_columns.is_empty() {
         panic!("At least one primary key must be specified")
     }
     // checking existence of keys specified as primary key
     for pk_column_name in primary_key_columns.iter() {
         if !field_struct
             .iter()
-            .map(|(ident, _, _)| ident.to_string())
+            .map(|(ident, _, _)| column_name(ident))
             .collect::<Vec<String>>()
             .contains(pk_column_name)
         {
@@ -221,22 +304,28 @@ pub fn derive_record(input: proc_macro::TokenStream) -> proc_macro::TokenStream
         .collect::<Vec<_>>();
     let push_field_names = field_struct
         .iter()
-        .map(|(ident, _, _)| quote! { result.push((stringify!(#ident).to_string(), SqlValue::serialize(self.#ident))); })
+        .map(|(ident, _, _)| {
+            let name = column_name(ident);
+            quote! { result.push((#name.to_string(), SqlValue::serialize(self.#ident))); }
+        })
         .collect::<Vec<_>>();
     let push_column_schema = field_struct
         .iter()
         .map(move |(ident, ty, attr_map)| {
-            let size_opt = attr_map.get("size").map(|v| v.clone().as_i32().unwrap());
+            let size_opt = attr_map.get("size").map(|v| v.as_i32().unwrap());
             let size = option_to_quote(size_opt);
-            let unique = option_to_quote(attr_map.get("unique").map(|v| v.clone().as_bool().unwrap()));
-            let not_null = option_to_quote(attr_map.get("not_null").map(|v| v.clone().as_bool().unwrap()));
+            let unique = option_to_quote(attr_map.get("unique").map(|v| v.as_bool().unwrap()));
+            let not_null = option_to_quote(attr_map.get("not_null").map(|v| v.as_bool().unwrap()));
+            let auto_increment = option_to_quote(attr_map.get("auto_increment").map(|v| v.as_bool().unwrap()));
             let size_unopt = size_opt.unwrap_or(0);
+            let name = column_name(ident);
 
             quote! {
-                result.push((stringify!(#ident).to_string(), <Self::ValueType as SqlValue<_>>::column_type(std::marker::PhantomData::<#ty>, #size_unopt), FieldAttribute {
+                result.push((#name.to_string(), <Self::ValueType as SqlValue<_>>::column_type(std::marker::PhantomData::<#ty>, #size_unopt), FieldAttribute {
                     size: #size,
                     unique: #unique,
                     not_null: #not_null,
+                    auto_increment: #auto_increment,
                 }));
             }
         })
@@ -244,14 +333,114 @@ pub fn derive_record(input: proc_macro::TokenStream) -> proc_macro::TokenStream
     let record_fields = field_struct
         .iter()
         .map(|(ident, _, _)| {
+            let name = column_name(ident);
             quote! {
-                #ident: <Self::ValueType as SqlValue<_>>::deserialize(values.get(stringify!(#ident)).unwrap().clone()),
+                #ident: <Self::ValueType as SqlValue<_>>::deserialize(values.get(#name).unwrap().clone()),
+            }
+        })
+        .collect::<Vec<_>>();
+    let row_fields = field_struct
+        .iter()
+        .map(|(ident, _, _)| {
+            let name = column_name(ident);
+            let missing = format!("map_from_row: column {} was not found", name);
+            quote! {
+                #ident: <Self::ValueType as SqlValue<_>>::deserialize(row.take_by_name(#name).expect(#missing)),
             }
         })
         .collect::<Vec<_>>();
 
     let sql_type = table_attr.sql_type;
 
+    let find_field = |name: &str| {
+        field_struct
+            .iter()
+            .find(|(ident, _, _)| column_name(ident) == name)
+            .map(|(ident, ty, _)| (ident.clone(), ty.clone()))
+    };
+    let (primary_key_idents, primary_key_types): (Vec<_>, Vec<_>) = primary_key_columns
+        .iter()
+        .map(|name| find_field(name).unwrap())
+        .unzip();
+    let primary_key_impl = if primary_key_idents.len() == 1 {
+        let (pk_ident, pk_type) = (&primary_key_idents[0], &primary_key_types[0]);
+        quote! {
+            impl debil::HasPrimaryKey for #ident {
+                type PrimaryKey = #pk_type;
+
+                fn primary_key(&self) -> Self::PrimaryKey {
+                    self.#pk_ident.clone()
+                }
+            }
+        }
+    } else {
+        quote! {
+            impl debil::HasPrimaryKey for #ident {
+                type PrimaryKey = (#( #primary_key_types ),*);
+
+                fn primary_key(&self) -> Self::PrimaryKey {
+                    (#( self.#primary_key_idents.clone() ),*)
+                }
+            }
+        }
+    };
+
+    let relation_impls = relations
+        .iter()
+        .map(|relation| match relation {
+            Relation::BelongsTo(parent, foreign_key) => {
+                let (fk_ident, _) = find_field(foreign_key).unwrap_or_else(|| {
+                    panic!(
+                        "belongs_to: {} was not found in this table struct",
+                        foreign_key
+                    )
+                });
+
+                quote! {
+                    impl debil::BelongsTo<#parent> for #ident {
+                        fn foreign_key() -> &'static str {
+                            #foreign_key
+                        }
+
+                        fn parent_key(&self) -> <#parent as debil::HasPrimaryKey>::PrimaryKey {
+                            self.#fk_ident.clone()
+                        }
+                    }
+                }
+            }
+            Relation::HasMany(child, foreign_key) => {
+                let fk_ident = syn::Ident::new(foreign_key, proc_macro2::Span::call_site());
+
+                quote! {
+                    impl debil::HasMany<#child> for #ident {
+                        fn foreign_key() -> &'static str {
+                            #foreign_key
+                        }
+
+                        fn child_key(child: &#child) -> Self::PrimaryKey {
+                            child.#fk_ident.clone()
+                        }
+                    }
+                }
+            }
+            Relation::HasManyThrough(target, through, foreign_key, target_key) => {
+                quote! {
+                    impl debil::HasManyThrough<#target> for #ident {
+                        type Through = #through;
+
+                        fn foreign_key() -> &'static str {
+                            #foreign_key
+                        }
+
+                        fn target_key() -> &'static str {
+                            #target_key
+                        }
+                    }
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
     let expanded = quote! {
         impl SqlMapper for #ident {
             type ValueType = #sql_type;
@@ -261,6 +450,12 @@ pub fn derive_record(input: proc_macro::TokenStream) -> proc_macro::TokenStream
                     #( #record_fields )*
                 }
             }
+
+            fn map_from_row(mut row: debil::Row<Self::ValueType>) -> Self {
+                #ident {
+                    #( #row_fields )*
+                }
+            }
         }
 
         impl SqlTable for #ident {
@@ -287,6 +482,10 @@ pub fn derive_record(input: proc_macro::TokenStream) -> proc_macro::TokenStream
                 result
             }
         }
+
+        #primary_key_impl
+
+        #( #relation_impls )*
     };
 
     proc_macro::TokenStream::from(expanded)
@@ -303,10 +502,11 @@ pub fn derive_accessor(input: proc_macro::TokenStream) -> proc_macro::TokenStrea
         .into_iter()
         .map(|item| {
             let field_name = item.0;
+            let name = column_name(&field_name);
 
             quote! {
                  pub fn #field_name() -> &'static str {
-                     stringify!(#field_name)
+                     #name
                  }
             }
         })
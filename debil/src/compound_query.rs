@@ -0,0 +1,192 @@
+use crate::query_builder::{bind_placeholders, merge_binds};
+use crate::{Dialect, GenericDialect, Ordering, Params, QueryBuilder};
+
+#[derive(Clone, Debug)]
+pub enum CompoundOperator {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl std::fmt::Display for CompoundOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use CompoundOperator::*;
+
+        f.write_str(match self {
+            Union => "UNION",
+            UnionAll => "UNION ALL",
+            Intersect => "INTERSECT",
+            Except => "EXCEPT",
+        })
+    }
+}
+
+/// Several QueryBuilders combined with UNION, UNION ALL, INTERSECT or EXCEPT.
+///
+/// Each member is rendered without parentheses, which SQLite requires, so members themselves
+/// must not have ORDER BY or LIMIT. ORDER BY and LIMIT given here apply to the combined result.
+#[derive(Clone, Debug)]
+pub struct CompoundQuery<V> {
+    members: Vec<(CompoundOperator, QueryBuilder<V>)>,
+    orders: Vec<(String, Ordering)>,
+    limit: Option<i32>,
+}
+
+impl<V> CompoundQuery<V> {
+    pub fn new(first: QueryBuilder<V>) -> CompoundQuery<V> {
+        CompoundQuery {
+            // the operator of the first member is never rendered
+            members: vec![(CompoundOperator::Union, first)],
+            orders: vec![],
+            limit: None,
+        }
+    }
+
+    pub fn combine(mut self, operator: CompoundOperator, other: QueryBuilder<V>) -> Self {
+        self.members.push((operator, other));
+
+        self
+    }
+
+    pub fn union(self, other: QueryBuilder<V>) -> Self {
+        self.combine(CompoundOperator::Union, other)
+    }
+
+    pub fn union_all(self, other: QueryBuilder<V>) -> Self {
+        self.combine(CompoundOperator::UnionAll, other)
+    }
+
+    pub fn intersect(self, other: QueryBuilder<V>) -> Self {
+        self.combine(CompoundOperator::Intersect, other)
+    }
+
+    pub fn except(self, other: QueryBuilder<V>) -> Self {
+        self.combine(CompoundOperator::Except, other)
+    }
+
+    pub fn order_by(mut self, column_name: impl Into<String>, ordering: Ordering) -> Self {
+        self.orders.push((column_name.into(), ordering));
+
+        self
+    }
+
+    pub fn limit(mut self, n: i32) -> Self {
+        self.limit = Some(n);
+
+        self
+    }
+
+    pub fn build(self) -> (String, Params<V>) {
+        self.build_for(&GenericDialect)
+    }
+
+    pub fn build_for(self, dialect: &dyn Dialect) -> (String, Params<V>) {
+        let (query, binds) = self.render(dialect);
+
+        bind_placeholders(dialect, query, binds)
+    }
+
+    pub(crate) fn render(self, dialect: &dyn Dialect) -> (String, Vec<(String, V)>) {
+        let mut binds = vec![];
+        let mut query = self
+            .members
+            .into_iter()
+            .enumerate()
+            .map(|(i, (operator, member))| {
+                let (member, params) = member.render(dialect);
+                let member = merge_binds(dialect, &mut binds, vec![member], params)
+                    .pop()
+                    .unwrap();
+
+                if i == 0 {
+                    member
+                } else {
+                    format!("{} {}", operator, member)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if !self.orders.is_empty() {
+            query = format!(
+                "{} ORDER BY {}",
+                query,
+                self.orders
+                    .into_iter()
+                    .map(|(k, o)| format!("{} {}", k, o))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if self.limit.is_some() {
+            query = format!("{} {}", query, dialect.limit_offset(self.limit, None));
+        }
+
+        (query, binds)
+    }
+}
+
+impl<V> QueryBuilder<V> {
+    pub fn union(self, other: QueryBuilder<V>) -> CompoundQuery<V> {
+        CompoundQuery::new(self).union(other)
+    }
+
+    pub fn union_all(self, other: QueryBuilder<V>) -> CompoundQuery<V> {
+        CompoundQuery::new(self).union_all(other)
+    }
+
+    pub fn intersect(self, other: QueryBuilder<V>) -> CompoundQuery<V> {
+        CompoundQuery::new(self).intersect(other)
+    }
+
+    pub fn except(self, other: QueryBuilder<V>) -> CompoundQuery<V> {
+        CompoundQuery::new(self).except(other)
+    }
+}
+
+#[test]
+fn compound_query_with_build() {
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .table("a")
+            .selects(vec!["id"])
+            .union(QueryBuilder::new().table("b").selects(vec!["id"]))
+            .build()
+            .0,
+        "SELECT id FROM a UNION SELECT id FROM b"
+    );
+
+    let (query, params) = QueryBuilder::<i32>::new()
+        .table("a")
+        .selects(vec!["id"])
+        .wheres((vec!["a.n = :n"], vec![("n".to_string(), 1)]))
+        .union_all(
+            QueryBuilder::new()
+                .table("b")
+                .selects(vec!["id"])
+                .wheres((vec!["b.n = :n"], vec![("n".to_string(), 2)])),
+        )
+        .except(
+            QueryBuilder::new()
+                .table("c")
+                .selects(vec!["id"])
+                .wheres((vec!["c.n = :n"], vec![("n".to_string(), 3)])),
+        )
+        .intersect(QueryBuilder::new().table("d").selects(vec!["id"]))
+        .order_by("id", Ordering::Descending)
+        .limit(10)
+        .build();
+    assert_eq!(
+        query,
+        "SELECT id FROM a WHERE a.n = :n UNION ALL SELECT id FROM b WHERE b.n = :n_1 EXCEPT SELECT id FROM c WHERE c.n = :n_2 INTERSECT SELECT id FROM d ORDER BY id DESC LIMIT 10"
+    );
+    assert_eq!(
+        params.0,
+        vec![
+            ("n".to_string(), 1),
+            ("n_1".to_string(), 2),
+            ("n_2".to_string(), 3)
+        ]
+    );
+}
@@ -1,5 +1,13 @@
-use crate::{QueryBuilder, SqlMapper, SqlTable};
+use crate::relation::{
+    attach_query, attached_keys_query, detach_query, filter_in_keys, filter_through,
+    single_primary_key, RelatedKey,
+};
+use crate::{
+    BelongsTo, Dialect, EagerLoad, HasMany, HasManyThrough, HasPrimaryKey, JoinedRow, QueryBuilder,
+    SqlMapper, SqlTable, SqlValue, Unsupported,
+};
 use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
 
 pub struct Params<ValueType>(pub Vec<(String, ValueType)>);
 
@@ -9,13 +17,61 @@ impl<V> Params<V> {
     }
 }
 
+impl<V> Default for Params<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A query prepared by `SqlConn::prepare`. Backends keep prepared statements in a per-connection
+/// cache keyed by the query, so running this with `exec_statement` or `query_statement` reuses
+/// the statement prepared on that connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    query: String,
+}
+
+impl Statement {
+    pub fn new(query: impl Into<String>) -> Statement {
+        Statement {
+            query: query.into(),
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+}
+
 pub trait HasNotFound {
     fn not_found() -> Self;
+
+    /// The error for a query which the dialect of the connection cannot express.
+    /// Falls back to `not_found` for error types which don't override this.
+    fn unsupported(_err: Unsupported) -> Self
+    where
+        Self: Sized,
+    {
+        Self::not_found()
+    }
 }
 
 #[async_trait]
 pub trait SqlConn<V: 'static + Sync + Send> {
-    type Error: HasNotFound;
+    type Error: HasNotFound + Send;
+
+    /// SQL dialect which queries for this connection are generated in
+    fn dialect(&self) -> &dyn Dialect;
+
+    /// Upper bound of bind parameters in a single statement, used to chunk bulk inserts
+    fn max_placeholders(&self) -> usize {
+        self.dialect().max_placeholders()
+    }
+
+    /// Upper bound of rows in a single bulk INSERT statement, to stay under the packet size limit
+    fn max_rows_per_statement(&self) -> usize {
+        1000
+    }
 
     async fn sql_exec(&mut self, query: String, params: Params<V>) -> Result<u64, Self::Error>;
 
@@ -31,11 +87,56 @@ pub trait SqlConn<V: 'static + Sync + Send> {
         params: Vec<Params<V>>,
     ) -> Result<(), Self::Error>;
 
+    /// Prepares the query ahead of time, which reports a syntax error before running it.
+    /// The default only wraps the query, for backends without prepared statements.
+    async fn prepare(&mut self, query: String) -> Result<Statement, Self::Error> {
+        Ok(Statement::new(query))
+    }
+
+    async fn exec_statement(
+        &mut self,
+        statement: &Statement,
+        params: Params<V>,
+    ) -> Result<u64, Self::Error> {
+        self.sql_exec(statement.query().to_string(), params).await
+    }
+
+    async fn query_statement<T: SqlMapper<ValueType = V> + Sync + Send>(
+        &mut self,
+        statement: &Statement,
+        params: Params<V>,
+    ) -> Result<Vec<T>, Self::Error> {
+        self.sql_query::<T>(statement.query().to_string(), params)
+            .await
+    }
+
+    /// Streams the records of the query instead of collecting them into a Vec.
+    /// Backends which can read rows on demand override this; the default runs `sql_query`
+    /// and streams its result.
+    fn sql_query_stream<'a, T: SqlMapper<ValueType = V> + Sync + Send + 'a>(
+        &'a mut self,
+        query: String,
+        params: Params<V>,
+    ) -> BoxStream<'a, Result<T, Self::Error>> {
+        futures::stream::once(self.sql_query::<T>(query, params))
+            .flat_map(|result| match result {
+                Ok(vs) => futures::stream::iter(vs.into_iter().map(Ok)).left_stream(),
+                Err(err) => futures::stream::once(async { Err(err) }).right_stream(),
+            })
+            .boxed()
+    }
+
+    async fn start_transaction(&mut self) -> Result<(), Self::Error>;
+
+    async fn commit(&mut self) -> Result<(), Self::Error>;
+
+    async fn rollback(&mut self) -> Result<(), Self::Error>;
+
     async fn create_table<T: SqlTable<ValueType = V> + Sync + Send>(
         &mut self,
     ) -> Result<(), Self::Error> {
         self.sql_exec(
-            SqlTable::create_table_query(std::marker::PhantomData::<T>),
+            SqlTable::create_table_query_for(std::marker::PhantomData::<T>, self.dialect()),
             Params::<V>(Vec::new()),
         )
         .await?;
@@ -47,40 +148,167 @@ pub trait SqlConn<V: 'static + Sync + Send> {
         &mut self,
         data: T,
     ) -> Result<u64, Self::Error> {
-        let (query, ps) = data.insert_query_with_params();
+        let (query, ps) = data.insert_query_with_params_for(self.dialect());
 
         self.sql_exec(query, Params::<V>(ps)).await
     }
 
-    async fn save<T: SqlTable<ValueType = V> + Sync + Send + Clone>(
+    /// Deletes the record with the same primary key
+    async fn delete<T: SqlTable<ValueType = V> + Sync + Send>(
         &mut self,
         data: T,
     ) -> Result<u64, Self::Error> {
-        let (query, ps) = data.clone().update_query_with_params();
-        let affected_rows = self.sql_exec(query, Params::<V>(ps)).await?;
+        let (query, ps) = data.delete_query_with_params(self.dialect());
+
+        self.sql_exec(query, Params::<V>(ps)).await
+    }
+
+    /// Inserts the record and returns it as stored, with values of auto increment columns
+    /// generated by the database. Without RETURNING, the record is selected after the INSERT.
+    async fn create_returning<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<T, Self::Error> {
+        if self.dialect().supports_returning() {
+            let (query, ps) = data
+                .insert_returning_query_with_params(self.dialect())
+                .map_err(HasNotFound::unsupported)?;
+
+            return self
+                .sql_query::<T>(query, Params::<V>(ps))
+                .await
+                .and_then(|mut vs| vs.pop().ok_or(HasNotFound::not_found()));
+        }
+
+        let (query, ps) = data.insert_generated_query_with_params(self.dialect());
+        let builder =
+            find_by_primary_key::<T>(self.dialect(), &ps).map_err(HasNotFound::unsupported)?;
+        self.sql_exec(query, Params::<V>(ps)).await?;
+
+        self.first::<T>(builder).await
+    }
+
+    /// Updates the record and returns it as stored.
+    /// Without RETURNING, the record is selected after the UPDATE.
+    async fn update_returning<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<T, Self::Error> {
+        if self.dialect().supports_returning() {
+            let (query, ps) = data
+                .update_returning_query_with_params(self.dialect())
+                .map_err(HasNotFound::unsupported)?;
+
+            return self
+                .sql_query::<T>(query, Params::<V>(ps))
+                .await
+                .and_then(|mut vs| vs.pop().ok_or(HasNotFound::not_found()));
+        }
+
+        let (query, ps) = data.update_query_with_params_for(self.dialect());
+        let builder =
+            find_by_primary_key::<T>(self.dialect(), &ps).map_err(HasNotFound::unsupported)?;
+        self.sql_exec(query, Params::<V>(ps)).await?;
+
+        self.first::<T>(builder).await
+    }
+
+    /// Deletes the record with the same primary key and returns it as it was stored.
+    /// Without RETURNING, the record is selected before the DELETE in a transaction.
+    async fn delete_returning<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<T, Self::Error> {
+        if self.dialect().supports_returning() {
+            let (query, ps) = data
+                .delete_returning_query_with_params(self.dialect())
+                .map_err(HasNotFound::unsupported)?;
+
+            return self
+                .sql_query::<T>(query, Params::<V>(ps))
+                .await
+                .and_then(|mut vs| vs.pop().ok_or(HasNotFound::not_found()));
+        }
 
-        if affected_rows == 0 {
-            self.create(data).await
-        } else {
-            Ok(affected_rows)
+        let (query, ps) = data.delete_query_with_params(self.dialect());
+        let builder =
+            find_by_primary_key::<T>(self.dialect(), &ps).map_err(HasNotFound::unsupported)?;
+
+        self.start_transaction().await?;
+        let deleted = match self.first::<T>(builder).await {
+            Ok(deleted) => deleted,
+            Err(err) => {
+                let _ = self.rollback().await;
+                return Err(err);
+            }
+        };
+        if let Err(err) = self.sql_exec(query, Params::<V>(ps)).await {
+            let _ = self.rollback().await;
+            return Err(err);
         }
+        self.commit().await?;
+
+        Ok(deleted)
+    }
+
+    /// Inserts the record, or updates every non-primary-key column if the primary key already exists
+    async fn save<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<u64, Self::Error> {
+        self.upsert(data, crate::non_primary_key_columns::<T>())
+            .await
+    }
+
+    /// Inserts the record, or updates only `update_columns` if the primary key already exists.
+    /// An empty `update_columns` leaves the existing row untouched.
+    async fn upsert<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+        update_columns: Vec<String>,
+    ) -> Result<u64, Self::Error> {
+        let (query, ps) = data.upsert_query_with_params(self.dialect(), update_columns);
+
+        self.sql_exec(query, Params::<V>(ps)).await
     }
 
+    /// Inserts all records with multi-row INSERT statements inside a transaction.
+    /// This starts its own transaction, so it must not be called in another one.
+    async fn create_all<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        datas: Vec<T>,
+    ) -> Result<u64, Self::Error> {
+        exec_in_chunks(self, datas, SqlTable::insert_all_query_with_params).await
+    }
+
+    /// Bulk version of `save`
+    async fn save_all<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        datas: Vec<T>,
+    ) -> Result<u64, Self::Error> {
+        self.upsert_all(datas, crate::non_primary_key_columns::<T>())
+            .await
+    }
+
+    /// Bulk version of `upsert`
+    async fn upsert_all<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        datas: Vec<T>,
+        update_columns: Vec<String>,
+    ) -> Result<u64, Self::Error> {
+        exec_in_chunks(self, datas, move |chunk, dialect| {
+            SqlTable::upsert_all_query_with_params(chunk, dialect, update_columns.clone())
+        })
+        .await
+    }
+
+    /// Selects columns of `T`, qualified with the alias if the builder has one from `table_as`
     async fn load2<T: SqlTable, U: SqlMapper<ValueType = V> + Sync + Send>(
         &mut self,
         builder: QueryBuilder<V>,
     ) -> Result<Vec<U>, Self::Error> {
-        let schema = SqlTable::schema_of(std::marker::PhantomData::<T>);
-        let table_name = SqlTable::table_name(std::marker::PhantomData::<T>);
-        let (query, params) = builder
-            .table(table_name.clone())
-            .append_selects(
-                schema
-                    .iter()
-                    .map(|(k, _, _)| format!("{}.{}", table_name, k))
-                    .collect::<Vec<_>>(),
-            )
-            .build();
+        let (query, params) =
+            select_columns_of::<T, V>(builder, self.dialect()).build_for(self.dialect());
         self.sql_query::<U>(query, params).await
     }
 
@@ -91,25 +319,307 @@ pub trait SqlConn<V: 'static + Sync + Send> {
         self.load2::<T, T>(builder).await
     }
 
+    /// Loads each row of a joined query into a tuple of tables, e.g.
+    /// `conn.load_joined::<(User, Post)>(builder)`. Every column is selected with a table-prefixed
+    /// alias, so that columns of the same name don't clash. Use `Option<Post>` for a LEFT JOIN.
+    async fn load_joined<R: JoinedRow<ValueType = V> + Sync + Send>(
+        &mut self,
+        builder: QueryBuilder<V>,
+    ) -> Result<Vec<R>, Self::Error> {
+        let (query, params) = R::select_joined(builder, self.dialect()).build_for(self.dialect());
+
+        self.sql_query::<R>(query, params).await
+    }
+
+    /// Streaming version of `load`
+    fn load_stream<'a, T: SqlTable<ValueType = V> + Sync + Send + 'a>(
+        &'a mut self,
+        builder: QueryBuilder<V>,
+    ) -> BoxStream<'a, Result<T, Self::Error>> {
+        let (query, params) =
+            select_columns_of::<T, V>(builder, self.dialect()).build_for(self.dialect());
+
+        self.sql_query_stream::<T>(query, params)
+    }
+
     async fn first<T: SqlTable<ValueType = V> + Sync + Send>(
         &mut self,
         builder: QueryBuilder<V>,
     ) -> Result<T, Self::Error> {
-        let schema = SqlTable::schema_of(std::marker::PhantomData::<T>);
-        let table_name = SqlTable::table_name(std::marker::PhantomData::<T>);
-        let (query, params) = builder
-            .table(table_name.clone())
-            .append_selects(
-                schema
-                    .iter()
-                    .map(|(k, _, _)| format!("{}.{}", table_name, k))
-                    .collect::<Vec<_>>(),
-            )
+        let (query, params) = select_columns_of::<T, V>(builder, self.dialect())
             .limit(1)
-            .build();
+            .build_for(self.dialect());
 
         self.sql_query::<T>(query, params)
             .await
             .and_then(|mut vs| vs.pop().ok_or(HasNotFound::not_found()))
     }
+
+    /// Loads the records together with related ones, e.g.
+    /// `conn.eager_load::<User>(builder).with::<Post>().await`
+    fn eager_load<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        builder: QueryBuilder<V>,
+    ) -> EagerLoad<'_, Self, V, T>
+    where
+        Self: Send,
+    {
+        EagerLoad::new(self, builder)
+    }
+
+    /// Loads the record which `child` belongs to
+    async fn load_parent<T, P>(&mut self, child: &T) -> Result<P, Self::Error>
+    where
+        T: BelongsTo<P> + Sync,
+        P: HasPrimaryKey<ValueType = V> + Sync + Send,
+        V: SqlValue<P::PrimaryKey>,
+    {
+        let builder = filter_in_keys::<P, _>(
+            self.dialect(),
+            &single_primary_key::<P>(),
+            vec![child.parent_key()],
+        );
+
+        self.first::<P>(builder).await
+    }
+
+    /// Loads the records which `parent` has
+    async fn load_children<T, C>(&mut self, parent: &T) -> Result<Vec<C>, Self::Error>
+    where
+        T: HasMany<C> + Sync,
+        C: SqlTable<ValueType = V> + Sync + Send,
+        V: SqlValue<T::PrimaryKey>,
+    {
+        let builder = filter_in_keys::<C, _>(
+            self.dialect(),
+            <T as HasMany<C>>::foreign_key(),
+            vec![parent.primary_key()],
+        );
+
+        self.load::<C>(builder).await
+    }
+
+    /// Loads the records related to `owner` through the join table
+    async fn load_through<T, U>(&mut self, owner: &T) -> Result<Vec<U>, Self::Error>
+    where
+        T: HasManyThrough<U> + Sync,
+        U: HasPrimaryKey<ValueType = V> + Sync + Send,
+        V: SqlValue<T::PrimaryKey>,
+    {
+        let builder = filter_through::<T, U, V>(self.dialect(), vec![owner.primary_key()]);
+
+        self.load::<U>(builder).await
+    }
+
+    /// Relates `owner` to the records with `keys` by inserting rows into the join table
+    async fn attach<T, U>(
+        &mut self,
+        owner: &T,
+        keys: Vec<U::PrimaryKey>,
+    ) -> Result<u64, Self::Error>
+    where
+        T: HasManyThrough<U> + Sync,
+        U: HasPrimaryKey,
+        U::PrimaryKey: Send,
+        V: SqlValue<T::PrimaryKey> + SqlValue<U::PrimaryKey>,
+    {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let (query, params) = attach_query::<T, U, V>(self.dialect(), owner.primary_key(), keys);
+        self.sql_exec(query, params).await
+    }
+
+    /// Unrelates `owner` from the records with `keys` by deleting rows from the join table
+    async fn detach<T, U>(
+        &mut self,
+        owner: &T,
+        keys: Vec<U::PrimaryKey>,
+    ) -> Result<u64, Self::Error>
+    where
+        T: HasManyThrough<U> + Sync,
+        U: HasPrimaryKey,
+        U::PrimaryKey: Send,
+        V: SqlValue<T::PrimaryKey> + SqlValue<U::PrimaryKey>,
+    {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let (query, params) = detach_query::<T, U, V>(self.dialect(), owner.primary_key(), keys);
+        self.sql_exec(query, params).await
+    }
+
+    /// Makes `owner` related to exactly the records with `keys`, attaching the missing ones and
+    /// detaching the others inside a transaction. Returns the number of inserted and deleted rows.
+    /// This starts its own transaction, so it must not be called in another one.
+    async fn sync<T, U>(&mut self, owner: &T, keys: Vec<U::PrimaryKey>) -> Result<u64, Self::Error>
+    where
+        T: HasManyThrough<U> + Sync,
+        U: HasPrimaryKey,
+        T::PrimaryKey: Clone + Send,
+        U::PrimaryKey: PartialEq + Clone + Send + Sync,
+        V: Clone + SqlValue<T::PrimaryKey> + SqlValue<U::PrimaryKey>,
+    {
+        self.start_transaction().await?;
+
+        match sync_through::<V, Self, T, U>(self, owner.primary_key(), keys).await {
+            Ok(rows) => {
+                self.commit().await?;
+
+                Ok(rows)
+            }
+            Err(err) => {
+                // the original error is more informative than a failure of the rollback itself
+                let _ = self.rollback().await;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn select_columns_of<T: SqlTable, V>(
+    builder: QueryBuilder<V>,
+    dialect: &dyn Dialect,
+) -> QueryBuilder<V> {
+    let schema = SqlTable::schema_of(std::marker::PhantomData::<T>);
+    let (builder, qualifier) =
+        builder.default_table(SqlTable::table_name(std::marker::PhantomData::<T>));
+
+    builder.append_selects(
+        schema
+            .iter()
+            .map(|(k, _, _)| {
+                format!(
+                    "{}.{}",
+                    dialect.quote_qualified(&qualifier),
+                    dialect.quote_identifier(k)
+                )
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+// Selects the record with the primary key in `params`. An auto increment primary key which is
+// not in `params` is the one generated by the last INSERT.
+fn find_by_primary_key<T: SqlTable>(
+    dialect: &dyn Dialect,
+    params: &[(String, T::ValueType)],
+) -> Result<QueryBuilder<T::ValueType>, Unsupported> {
+    let table_name = dialect.quote_qualified(&SqlTable::table_name(std::marker::PhantomData::<T>));
+    let mut conds = vec![];
+    let mut binds = vec![];
+
+    for key in SqlTable::primary_key_columns(std::marker::PhantomData::<T>) {
+        let column = format!("{}.{}", table_name, dialect.quote_identifier(&key));
+
+        match params.iter().find(|(k, _)| k == &key) {
+            Some((_, value)) => {
+                conds.push(format!("{} = :{}", column, key));
+                binds.push((key, value.clone()));
+            }
+            None => match dialect.last_insert_id() {
+                Some(expr) => conds.push(format!("{} = {}", column, expr)),
+                None => {
+                    return Err(Unsupported {
+                        dialect: dialect.name(),
+                        feature: format!("reading back the value generated for {}", key),
+                    })
+                }
+            },
+        }
+    }
+
+    Ok(QueryBuilder::new().wheres((conds, binds)))
+}
+
+async fn sync_through<V, C, T, U>(
+    conn: &mut C,
+    owner_key: T::PrimaryKey,
+    keys: Vec<U::PrimaryKey>,
+) -> Result<u64, C::Error>
+where
+    V: 'static + Clone + Sync + Send + SqlValue<T::PrimaryKey> + SqlValue<U::PrimaryKey>,
+    C: SqlConn<V> + ?Sized + Send,
+    T: HasManyThrough<U>,
+    U: HasPrimaryKey,
+    T::PrimaryKey: Clone + Send,
+    U::PrimaryKey: PartialEq + Clone + Send + Sync,
+{
+    let (query, params) = attached_keys_query::<T, U, V>(conn.dialect(), owner_key.clone());
+    let attached = conn
+        .sql_query::<RelatedKey<U::PrimaryKey, V>>(query, params)
+        .await?
+        .into_iter()
+        .map(|key| key.0)
+        .collect::<Vec<_>>();
+
+    let detached = attached
+        .iter()
+        .filter(|key| !keys.contains(key))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut affected_rows = 0;
+    if !detached.is_empty() {
+        let (query, params) = detach_query::<T, U, V>(conn.dialect(), owner_key.clone(), detached);
+        affected_rows += conn.sql_exec(query, params).await?;
+    }
+
+    let mut missing = vec![];
+    for key in keys {
+        if !attached.contains(&key) && !missing.contains(&key) {
+            missing.push(key);
+        }
+    }
+    if !missing.is_empty() {
+        let (query, params) = attach_query::<T, U, V>(conn.dialect(), owner_key, missing);
+        affected_rows += conn.sql_exec(query, params).await?;
+    }
+
+    Ok(affected_rows)
+}
+
+async fn exec_in_chunks<V, C, T, F>(
+    conn: &mut C,
+    datas: Vec<T>,
+    to_query: F,
+) -> Result<u64, C::Error>
+where
+    V: 'static + Sync + Send,
+    C: SqlConn<V> + ?Sized + Send,
+    T: SqlTable<ValueType = V> + Send,
+    F: Fn(Vec<T>, &dyn Dialect) -> (String, Vec<(String, V)>) + Send,
+{
+    if datas.is_empty() {
+        return Ok(0);
+    }
+
+    let columns = SqlTable::schema_of(std::marker::PhantomData::<T>)
+        .len()
+        .max(1);
+    let chunk_size = (conn.max_placeholders() / columns)
+        .min(conn.max_rows_per_statement())
+        .max(1);
+
+    conn.start_transaction().await?;
+
+    let mut affected_rows = 0;
+    let mut datas = datas.into_iter().peekable();
+    while datas.peek().is_some() {
+        let (query, ps) = to_query(datas.by_ref().take(chunk_size).collect(), conn.dialect());
+
+        match conn.sql_exec(query, Params::<V>(ps)).await {
+            Ok(rows) => affected_rows += rows,
+            Err(err) => {
+                // the original error is more informative than a failure of the rollback itself
+                let _ = conn.rollback().await;
+                return Err(err);
+            }
+        }
+    }
+
+    conn.commit().await?;
+
+    Ok(affected_rows)
 }
@@ -0,0 +1,446 @@
+/// SQL syntax which differs between databases.
+///
+/// Default methods generate the portable SQL debil has always generated, which is what
+/// `GenericDialect` uses. A connection tells which dialect it speaks via `SqlConn::dialect`.
+pub trait Dialect: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn quote_identifier(&self, identifier: &str) -> String {
+        identifier.to_string()
+    }
+
+    /// Quotes each part of a dotted name such as `schema.table`
+    fn quote_qualified(&self, name: &str) -> String {
+        name.split('.')
+            .map(|part| self.quote_identifier(part))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Whether a backslash escapes the next character in string literals, as in MySQL.
+    /// Standard SQL only escapes a quote by doubling it.
+    fn backslash_escapes(&self) -> bool {
+        false
+    }
+
+    /// Placeholder for the bind named `name`, which is the `index`-th (0-origin) placeholder
+    fn placeholder(&self, name: &str, _index: usize) -> String {
+        format!(":{}", name)
+    }
+
+    /// Upper bound of bind parameters in a single statement
+    fn max_placeholders(&self) -> usize {
+        999
+    }
+
+    /// Clause following `INSERT ... VALUES ...` to update `update_columns` on primary key conflict.
+    /// An empty `update_columns` keeps the existing row as it is. An empty clause leaves
+    /// a plain INSERT.
+    fn upsert_clause(&self, primary_keys: &[String], update_columns: &[String]) -> String {
+        let primary_keys = primary_keys
+            .iter()
+            .map(|k| self.quote_identifier(k))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if update_columns.is_empty() {
+            format!("ON CONFLICT({}) DO NOTHING", primary_keys)
+        } else {
+            format!(
+                "ON CONFLICT({}) DO UPDATE SET {}",
+                primary_keys,
+                update_columns
+                    .iter()
+                    .map(|k| format!(
+                        "{} = excluded.{}",
+                        self.quote_identifier(k),
+                        self.quote_identifier(k)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+    }
+
+    fn limit_offset(&self, limit: Option<i32>, offset: Option<i32>) -> String {
+        match (limit, offset) {
+            (Some(limit), Some(offset)) => format!("LIMIT {} OFFSET {}", limit, offset),
+            (Some(limit), None) => format!("LIMIT {}", limit),
+            (None, Some(offset)) => format!("OFFSET {}", offset),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn create_index(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        columns: &[&str],
+        unique: bool,
+    ) -> String {
+        format!(
+            "CREATE {}INDEX IF NOT EXISTS {} ON {} ({})",
+            if unique { "UNIQUE " } else { "" },
+            self.quote_identifier(index_name),
+            self.quote_qualified(table_name),
+            columns
+                .iter()
+                .map(|c| self.quote_identifier(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    fn drop_index(&self, _table_name: &str, index_name: &str) -> String {
+        format!("DROP INDEX IF EXISTS {}", self.quote_identifier(index_name))
+    }
+
+    /// Column type of a column whose value is generated by the database
+    fn auto_increment(&self, column_type: &str) -> String {
+        format!("{} GENERATED BY DEFAULT AS IDENTITY", column_type)
+    }
+
+    fn boolean_literal(&self, value: bool) -> &'static str {
+        if value {
+            "TRUE"
+        } else {
+            "FALSE"
+        }
+    }
+
+    /// Whether FULL OUTER JOIN is available. Building a `full_join` for a dialect without it
+    /// panics.
+    fn supports_full_outer_join(&self) -> bool {
+        true
+    }
+
+    fn supports_returning(&self) -> bool {
+        false
+    }
+
+    /// Expression for the value generated for an auto increment column by the last INSERT
+    /// of this connection, used where RETURNING is not supported
+    fn last_insert_id(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Statement which shows the query plan of `query`
+    fn explain(&self, query: &str) -> String {
+        format!("EXPLAIN {}", query)
+    }
+
+    /// `RETURNING` clause appended to INSERT, UPDATE and DELETE statements
+    fn returning_clause(&self, columns: &[String]) -> String {
+        format!(
+            "RETURNING {}",
+            columns
+                .iter()
+                .map(|c| self.quote_identifier(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+// Wraps an identifier in the quote characters, doubling the ones inside it.
+// An identifier which is already quoted is left as it is.
+fn quote_with(identifier: &str, quote: char) -> String {
+    if identifier.len() >= 2 && identifier.starts_with(quote) && identifier.ends_with(quote) {
+        return identifier.to_string();
+    }
+
+    format!(
+        "{}{}{}",
+        quote,
+        identifier.replace(quote, &format!("{}{}", quote, quote)),
+        quote
+    )
+}
+
+/// SQL which a dialect cannot express, such as RETURNING on MySQL
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unsupported {
+    pub dialect: &'static str,
+    pub feature: String,
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not supported by {}", self.feature, self.dialect)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Dialect-neutral SQL, used by the builders when no dialect is given.
+/// Identifiers are not quoted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GenericDialect;
+
+impl Dialect for GenericDialect {
+    fn name(&self) -> &'static str {
+        "generic"
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SqliteDialect;
+
+impl Dialect for SqliteDialect {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn quote_identifier(&self, identifier: &str) -> String {
+        quote_with(identifier, '"')
+    }
+
+    fn limit_offset(&self, limit: Option<i32>, offset: Option<i32>) -> String {
+        match (limit, offset) {
+            // SQLite cannot have OFFSET without LIMIT, and a negative LIMIT means no limit
+            (None, Some(offset)) => format!("LIMIT -1 OFFSET {}", offset),
+            _ => GenericDialect.limit_offset(limit, offset),
+        }
+    }
+
+    // A single INTEGER primary key column is an alias of ROWID, which SQLite fills in by itself.
+    // AUTOINCREMENT is only allowed in a column constraint, which debil does not use for primary keys.
+    fn auto_increment(&self, column_type: &str) -> String {
+        column_type.to_string()
+    }
+
+    fn supports_returning(&self) -> bool {
+        true
+    }
+
+    fn last_insert_id(&self) -> Option<&'static str> {
+        Some("last_insert_rowid()")
+    }
+
+    // EXPLAIN alone shows the bytecode of the statement
+    fn explain(&self, query: &str) -> String {
+        format!("EXPLAIN QUERY PLAN {}", query)
+    }
+
+    fn boolean_literal(&self, value: bool) -> &'static str {
+        if value {
+            "1"
+        } else {
+            "0"
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MySqlDialect;
+
+impl Dialect for MySqlDialect {
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn quote_identifier(&self, identifier: &str) -> String {
+        quote_with(identifier, '`')
+    }
+
+    fn backslash_escapes(&self) -> bool {
+        true
+    }
+
+    fn max_placeholders(&self) -> usize {
+        65535
+    }
+
+    fn upsert_clause(&self, primary_keys: &[String], update_columns: &[String]) -> String {
+        format!(
+            "ON DUPLICATE KEY UPDATE {}",
+            if update_columns.is_empty() {
+                // MySQL has no DO NOTHING, so assign a primary key to itself instead.
+                // Without a primary key there is nothing to keep, so a plain INSERT will do.
+                let pk = match primary_keys.first() {
+                    Some(pk) => self.quote_identifier(pk),
+                    None => return String::new(),
+                };
+                format!("{} = {}", pk, pk)
+            } else {
+                update_columns
+                    .iter()
+                    .map(|k| {
+                        let k = self.quote_identifier(k);
+                        format!("{} = VALUES({})", k, k)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        )
+    }
+
+    fn limit_offset(&self, limit: Option<i32>, offset: Option<i32>) -> String {
+        match (limit, offset) {
+            // MySQL cannot have OFFSET without LIMIT, so use the largest possible LIMIT
+            (None, Some(offset)) => format!("LIMIT 18446744073709551615 OFFSET {}", offset),
+            _ => GenericDialect.limit_offset(limit, offset),
+        }
+    }
+
+    // MySQL supports IF NOT EXISTS neither for CREATE INDEX nor IF EXISTS for DROP INDEX
+    fn create_index(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        columns: &[&str],
+        unique: bool,
+    ) -> String {
+        format!(
+            "CREATE {}INDEX {} ON {} ({})",
+            if unique { "UNIQUE " } else { "" },
+            self.quote_identifier(index_name),
+            self.quote_qualified(table_name),
+            columns
+                .iter()
+                .map(|c| self.quote_identifier(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    fn drop_index(&self, table_name: &str, index_name: &str) -> String {
+        format!(
+            "DROP INDEX {} ON {}",
+            self.quote_identifier(index_name),
+            self.quote_qualified(table_name)
+        )
+    }
+
+    fn auto_increment(&self, column_type: &str) -> String {
+        format!("{} AUTO_INCREMENT", column_type)
+    }
+
+    fn last_insert_id(&self) -> Option<&'static str> {
+        Some("LAST_INSERT_ID()")
+    }
+
+    fn supports_full_outer_join(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PostgresDialect;
+
+impl Dialect for PostgresDialect {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn quote_identifier(&self, identifier: &str) -> String {
+        quote_with(identifier, '"')
+    }
+
+    fn placeholder(&self, _name: &str, index: usize) -> String {
+        format!("${}", index + 1)
+    }
+
+    fn max_placeholders(&self) -> usize {
+        65535
+    }
+
+    fn supports_returning(&self) -> bool {
+        true
+    }
+}
+
+/// Dialect of `memory::MemoryConn`, which quotes identifiers in the same way as SQLite
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryDialect;
+
+impl Dialect for MemoryDialect {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn quote_identifier(&self, identifier: &str) -> String {
+        quote_with(identifier, '"')
+    }
+
+    fn last_insert_id(&self) -> Option<&'static str> {
+        Some("last_insert_id()")
+    }
+}
+
+#[test]
+fn quote_identifiers() {
+    assert_eq!(GenericDialect.quote_identifier("order"), "order");
+    assert_eq!(SqliteDialect.quote_identifier("order"), "\"order\"");
+    assert_eq!(SqliteDialect.quote_identifier("a\"b"), "\"a\"\"b\"");
+    assert_eq!(SqliteDialect.quote_identifier("\"order\""), "\"order\"");
+    assert_eq!(MySqlDialect.quote_identifier("group"), "`group`");
+    assert_eq!(MySqlDialect.quote_identifier("a`b"), "`a``b`");
+    assert_eq!(MySqlDialect.quote_qualified("db.key"), "`db`.`key`");
+}
+
+#[test]
+fn dialect_differences() {
+    let pks = vec!["id".to_string()];
+    let columns = vec!["name".to_string(), "age".to_string()];
+
+    assert_eq!(
+        SqliteDialect.upsert_clause(&pks, &columns),
+        r#"ON CONFLICT("id") DO UPDATE SET "name" = excluded."name", "age" = excluded."age""#
+    );
+    assert_eq!(
+        MySqlDialect.upsert_clause(&pks, &columns),
+        "ON DUPLICATE KEY UPDATE `name` = VALUES(`name`), `age` = VALUES(`age`)"
+    );
+    assert_eq!(
+        MySqlDialect.upsert_clause(&pks, &[]),
+        "ON DUPLICATE KEY UPDATE `id` = `id`"
+    );
+    assert_eq!(MySqlDialect.upsert_clause(&[], &[]), "");
+
+    assert_eq!(
+        SqliteDialect.limit_offset(Some(10), Some(20)),
+        "LIMIT 10 OFFSET 20"
+    );
+    assert_eq!(
+        SqliteDialect.limit_offset(None, Some(20)),
+        "LIMIT -1 OFFSET 20"
+    );
+    assert_eq!(
+        MySqlDialect.limit_offset(None, Some(20)),
+        "LIMIT 18446744073709551615 OFFSET 20"
+    );
+
+    assert_eq!(
+        SqliteDialect.create_index("person", "name_idx", &["name"], false),
+        r#"CREATE INDEX IF NOT EXISTS "name_idx" ON "person" ("name")"#
+    );
+    assert_eq!(
+        MySqlDialect.create_index("person", "name_idx", &["name", "age"], true),
+        "CREATE UNIQUE INDEX `name_idx` ON `person` (`name`, `age`)"
+    );
+    assert_eq!(
+        SqliteDialect.drop_index("person", "name_idx"),
+        r#"DROP INDEX IF EXISTS "name_idx""#
+    );
+    assert_eq!(
+        MySqlDialect.drop_index("person", "name_idx"),
+        "DROP INDEX `name_idx` ON `person`"
+    );
+
+    assert_eq!(SqliteDialect.auto_increment("INTEGER"), "INTEGER");
+    assert_eq!(
+        MySqlDialect.auto_increment("bigint"),
+        "bigint AUTO_INCREMENT"
+    );
+    assert_eq!(
+        SqliteDialect.explain("SELECT * FROM person"),
+        "EXPLAIN QUERY PLAN SELECT * FROM person"
+    );
+    assert_eq!(
+        MySqlDialect.explain("SELECT * FROM person"),
+        "EXPLAIN SELECT * FROM person"
+    );
+    assert_eq!(SqliteDialect.boolean_literal(true), "1");
+    assert_eq!(MySqlDialect.boolean_literal(false), "FALSE");
+}
@@ -0,0 +1,581 @@
+use crate::{expand_params, Dialect, Params, Row, SqlConn, SqlLiteral, SqlMapper, Statement};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A finished query, passed to `QueryHook::after_query`
+pub struct QueryEvent<'a, V, E> {
+    pub query: &'a str,
+    pub params: &'a Params<V>,
+    pub duration: Duration,
+    /// Rows affected by `sql_exec`, or rows returned by `sql_query` or read from a stream.
+    /// None for batches, transaction control, failed queries and streams dropped before
+    /// their end, whose rows are unknown.
+    pub rows: Option<u64>,
+    pub error: Option<&'a E>,
+}
+
+/// A query which took `Instrumented::slow_query_threshold` or longer, passed to
+/// `QueryHook::slow_query`
+pub struct SlowQuery<'a, V> {
+    pub query: &'a str,
+    pub params: &'a Params<V>,
+    /// The query with its params written inline as literals
+    pub statement: String,
+    pub duration: Duration,
+    /// Rows of EXPLAIN on the query, when `Instrumented::explain_slow_queries` is on
+    /// and EXPLAIN succeeded
+    pub plan: Option<Vec<Row<V>>>,
+}
+
+/// Callbacks around every query run through an `Instrumented` connection,
+/// e.g. for logging or metrics. Both of them do nothing by default.
+pub trait QueryHook<V, E>: Send + Sync {
+    fn before_query(&self, _query: &str, _params: &Params<V>) {}
+
+    fn after_query(&self, _event: &QueryEvent<'_, V, E>) {}
+
+    /// Called after `after_query` for a slow query, which has been logged already
+    /// with the tracing feature
+    fn slow_query(&self, _query: &SlowQuery<'_, V>) {}
+}
+
+struct SlowQueryConfig<V> {
+    threshold: Duration,
+    explain: bool,
+    render: fn(&V) -> String,
+}
+
+/// A connection which calls its hooks before and after each query of the wrapped connection.
+/// Transactions are reported as the queries `BEGIN`, `COMMIT` and `ROLLBACK`.
+pub struct Instrumented<C: SqlConn<V>, V: 'static + Sync + Send> {
+    conn: C,
+    hooks: Vec<Arc<dyn QueryHook<V, C::Error>>>,
+    slow_query: Option<SlowQueryConfig<V>>,
+}
+
+impl<C: SqlConn<V>, V: 'static + Sync + Send> Instrumented<C, V> {
+    pub fn new(conn: C) -> Self {
+        Instrumented {
+            conn,
+            hooks: vec![],
+            slow_query: None,
+        }
+    }
+
+    /// Adds a hook, which is called after the ones added before it
+    pub fn hook(mut self, hook: impl QueryHook<V, C::Error> + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+
+        self
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+
+    pub fn into_inner(self) -> C {
+        self.conn
+    }
+}
+
+impl<C: SqlConn<V>, V: 'static + Sync + Send + SqlLiteral> Instrumented<C, V> {
+    /// Passes queries which take `threshold` or longer with their params to
+    /// `QueryHook::slow_query`, and logs them as `tracing` events with the tracing feature.
+    /// Slow queries are reported only through these two, so without the tracing feature
+    /// nothing is logged unless a hook is added.
+    pub fn slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_query = Some(SlowQueryConfig {
+            threshold,
+            explain: false,
+            render: V::to_sql_literal,
+        });
+
+        self
+    }
+
+    /// Runs EXPLAIN on each slow query on the same connection, and attaches the plan to it.
+    /// Batches and transaction control are not explained.
+    pub fn explain_slow_queries(mut self) -> Self {
+        self.slow_query
+            .as_mut()
+            .expect("explain_slow_queries: slow_query_threshold is not set")
+            .explain = true;
+
+        self
+    }
+}
+
+impl<C, V> Instrumented<C, V>
+where
+    C: SqlConn<V> + Send,
+    V: 'static + Sync + Send + Clone,
+{
+    async fn report_slow_query(&mut self, slow: Observed<V>, explainable: bool) {
+        let (explain, render) = match &self.slow_query {
+            Some(config) => (config.explain && explainable, config.render),
+            None => return,
+        };
+
+        let plan = if explain {
+            let query = self.conn.dialect().explain(&slow.query);
+            self.conn
+                .sql_query::<Row<V>>(query, Params(slow.params.0.clone()))
+                .await
+                .ok()
+        } else {
+            None
+        };
+        let slow = SlowQuery {
+            query: &slow.query,
+            params: &slow.params,
+            statement: expand_params(self.conn.dialect(), &slow.query, &slow.params, render),
+            duration: slow.duration,
+            plan,
+        };
+
+        log_slow_query(&slow, render);
+        for hook in &self.hooks {
+            hook.slow_query(&slow);
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn log_slow_query<V>(slow: &SlowQuery<'_, V>, render: fn(&V) -> String) {
+    // each row of the plan as `column=value ...`
+    let plan = slow
+        .plan
+        .as_ref()
+        .filter(|rows| !rows.is_empty())
+        .map(|rows| {
+            rows.iter()
+                .map(|row| {
+                    row.columns()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, column)| {
+                            format!("{}={}", column, row.get(i).map_or_else(String::new, render))
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect::<Vec<_>>()
+                .join("; ")
+        });
+
+    tracing::warn!(
+        duration = ?slow.duration,
+        statement = %slow.statement,
+        plan = plan.as_deref(),
+        "slow query"
+    );
+}
+
+// Without the tracing feature, slow queries are only passed to the hooks
+#[cfg(not(feature = "tracing"))]
+fn log_slow_query<V>(_slow: &SlowQuery<'_, V>, _render: fn(&V) -> String) {}
+
+// A finished query kept to report it as slow
+struct Observed<V> {
+    query: String,
+    params: Params<V>,
+    duration: Duration,
+}
+
+// Runs a query between the hooks, and returns it back when it took `threshold` or longer.
+// The query and its params are cloned only when there are hooks or a threshold.
+async fn observe<V, E, R, F>(
+    hooks: &[Arc<dyn QueryHook<V, E>>],
+    threshold: Option<Duration>,
+    query: String,
+    params: Params<V>,
+    rows: impl Fn(&R) -> Option<u64>,
+    run: impl FnOnce(String, Params<V>) -> F,
+) -> (Result<R, E>, Option<Observed<V>>)
+where
+    V: Clone,
+    F: Future<Output = Result<R, E>>,
+{
+    if hooks.is_empty() && threshold.is_none() {
+        return (run(query, params).await, None);
+    }
+
+    for hook in hooks {
+        hook.before_query(&query, &params);
+    }
+    let observed = (query.clone(), Params(params.0.clone()));
+
+    let start = Instant::now();
+    let result = run(query, params).await;
+    let duration = start.elapsed();
+    let event = QueryEvent {
+        query: &observed.0,
+        params: &observed.1,
+        duration,
+        rows: result.as_ref().ok().and_then(rows),
+        error: result.as_ref().err(),
+    };
+    for hook in hooks {
+        hook.after_query(&event);
+    }
+
+    let slow = match threshold {
+        Some(threshold) if duration >= threshold => Some(Observed {
+            query: observed.0,
+            params: observed.1,
+            duration,
+        }),
+        _ => None,
+    };
+
+    (result, slow)
+}
+
+// The query of a stream with its params written inline, to report it as slow later
+struct SlowStatement<V> {
+    threshold: Duration,
+    render: fn(&V) -> String,
+    statement: String,
+}
+
+// A stream of rows being read, which is reported once
+struct ObservedStream<V, E> {
+    hooks: Vec<Arc<dyn QueryHook<V, E>>>,
+    slow_query: Option<SlowStatement<V>>,
+    query: String,
+    params: Params<V>,
+    start: Instant,
+    rows: u64,
+    finished: bool,
+}
+
+impl<V, E> ObservedStream<V, E> {
+    // `complete` is false for a stream dropped before its end
+    fn finish(&mut self, error: Option<&E>, complete: bool) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        let duration = self.start.elapsed();
+        let event = QueryEvent {
+            query: &self.query,
+            params: &self.params,
+            duration,
+            rows: if error.is_none() && complete {
+                Some(self.rows)
+            } else {
+                None
+            },
+            error,
+        };
+        for hook in &self.hooks {
+            hook.after_query(&event);
+        }
+
+        if let Some(slow) = self.slow_query.take() {
+            if duration >= slow.threshold {
+                let render = slow.render;
+                let slow = SlowQuery {
+                    query: &self.query,
+                    params: &self.params,
+                    statement: slow.statement,
+                    duration,
+                    plan: None,
+                };
+
+                log_slow_query(&slow, render);
+                for hook in &self.hooks {
+                    hook.slow_query(&slow);
+                }
+            }
+        }
+    }
+}
+
+impl<V, E> Drop for ObservedStream<V, E> {
+    fn drop(&mut self) {
+        self.finish(None, false);
+    }
+}
+
+#[async_trait]
+impl<C, V> SqlConn<V> for Instrumented<C, V>
+where
+    C: SqlConn<V> + Send,
+    V: 'static + Sync + Send + Clone,
+{
+    type Error = C::Error;
+
+    fn dialect(&self) -> &dyn Dialect {
+        self.conn.dialect()
+    }
+
+    fn max_placeholders(&self) -> usize {
+        self.conn.max_placeholders()
+    }
+
+    fn max_rows_per_statement(&self) -> usize {
+        self.conn.max_rows_per_statement()
+    }
+
+    async fn sql_exec(&mut self, query: String, params: Params<V>) -> Result<u64, Self::Error> {
+        let threshold = self.slow_query.as_ref().map(|config| config.threshold);
+        let conn = &mut self.conn;
+        let (result, slow) = observe(
+            &self.hooks,
+            threshold,
+            query,
+            params,
+            |rows| Some(*rows),
+            move |query, params| conn.sql_exec(query, params),
+        )
+        .await;
+        if let Some(slow) = slow {
+            self.report_slow_query(slow, true).await;
+        }
+
+        result
+    }
+
+    async fn sql_query<T: SqlMapper<ValueType = V> + Sync + Send>(
+        &mut self,
+        query: String,
+        params: Params<V>,
+    ) -> Result<Vec<T>, Self::Error> {
+        let threshold = self.slow_query.as_ref().map(|config| config.threshold);
+        let conn = &mut self.conn;
+        let (result, slow) = observe(
+            &self.hooks,
+            threshold,
+            query,
+            params,
+            |vs: &Vec<T>| Some(vs.len() as u64),
+            move |query, params| conn.sql_query::<T>(query, params),
+        )
+        .await;
+        if let Some(slow) = slow {
+            self.report_slow_query(slow, true).await;
+        }
+
+        result
+    }
+
+    // A batch is reported once, without params
+    async fn sql_batch_exec(
+        &mut self,
+        query: String,
+        params: Vec<Params<V>>,
+    ) -> Result<(), Self::Error> {
+        let threshold = self.slow_query.as_ref().map(|config| config.threshold);
+        let conn = &mut self.conn;
+        let (result, slow) = observe(
+            &self.hooks,
+            threshold,
+            query,
+            Params::new(),
+            |_| None,
+            move |query, _| conn.sql_batch_exec(query, params),
+        )
+        .await;
+        if let Some(slow) = slow {
+            self.report_slow_query(slow, false).await;
+        }
+
+        result
+    }
+
+    async fn prepare(&mut self, query: String) -> Result<Statement, Self::Error> {
+        self.conn.prepare(query).await
+    }
+
+    // The stream is reported when it ends, fails or is dropped, with the rows read so far.
+    // A slow stream is not explained, since the connection is borrowed by the stream.
+    fn sql_query_stream<'a, T: SqlMapper<ValueType = V> + Sync + Send + 'a>(
+        &'a mut self,
+        query: String,
+        params: Params<V>,
+    ) -> BoxStream<'a, Result<T, Self::Error>> {
+        use futures::stream::StreamExt;
+        use std::task::Poll;
+
+        if self.hooks.is_empty() && self.slow_query.is_none() {
+            return self.conn.sql_query_stream::<T>(query, params);
+        }
+
+        for hook in &self.hooks {
+            hook.before_query(&query, &params);
+        }
+        let dialect = self.conn.dialect();
+        let mut observed = ObservedStream {
+            hooks: self.hooks.clone(),
+            slow_query: self.slow_query.as_ref().map(|config| SlowStatement {
+                threshold: config.threshold,
+                render: config.render,
+                statement: expand_params(dialect, &query, &params, config.render),
+            }),
+            query: query.clone(),
+            params: Params(params.0.clone()),
+            start: Instant::now(),
+            rows: 0,
+            finished: false,
+        };
+
+        let mut rows = self.conn.sql_query_stream::<T>(query, params);
+        futures::stream::poll_fn(move |cx| {
+            let row = rows.poll_next_unpin(cx);
+            match &row {
+                Poll::Ready(Some(Ok(_))) => observed.rows += 1,
+                Poll::Ready(Some(Err(err))) => observed.finish(Some(err), true),
+                Poll::Ready(None) => observed.finish(None, true),
+                Poll::Pending => {}
+            }
+
+            row
+        })
+        .boxed()
+    }
+
+    async fn start_transaction(&mut self) -> Result<(), Self::Error> {
+        let threshold = self.slow_query.as_ref().map(|config| config.threshold);
+        let conn = &mut self.conn;
+        let (result, slow) = observe(
+            &self.hooks,
+            threshold,
+            "BEGIN".to_string(),
+            Params::new(),
+            |_| None,
+            move |_, _| conn.start_transaction(),
+        )
+        .await;
+        if let Some(slow) = slow {
+            self.report_slow_query(slow, false).await;
+        }
+
+        result
+    }
+
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        let threshold = self.slow_query.as_ref().map(|config| config.threshold);
+        let conn = &mut self.conn;
+        let (result, slow) = observe(
+            &self.hooks,
+            threshold,
+            "COMMIT".to_string(),
+            Params::new(),
+            |_| None,
+            move |_, _| conn.commit(),
+        )
+        .await;
+        if let Some(slow) = slow {
+            self.report_slow_query(slow, false).await;
+        }
+
+        result
+    }
+
+    async fn rollback(&mut self) -> Result<(), Self::Error> {
+        let threshold = self.slow_query.as_ref().map(|config| config.threshold);
+        let conn = &mut self.conn;
+        let (result, slow) = observe(
+            &self.hooks,
+            threshold,
+            "ROLLBACK".to_string(),
+            Params::new(),
+            |_| None,
+            move |_, _| conn.rollback(),
+        )
+        .await;
+        if let Some(slow) = slow {
+            self.report_slow_query(slow, false).await;
+        }
+
+        result
+    }
+}
+
+#[cfg(all(feature = "tracing", any(feature = "sqlite", feature = "mysql")))]
+pub(crate) type QuerySpan = tracing::Span;
+#[cfg(all(not(feature = "tracing"), any(feature = "sqlite", feature = "mysql")))]
+#[derive(Clone)]
+pub(crate) struct QuerySpan;
+
+// Span of a query run by a backend, with the SQL as `db.statement`.
+// Without the tracing feature this and `traced` do nothing.
+#[cfg(all(feature = "tracing", any(feature = "sqlite", feature = "mysql")))]
+pub(crate) fn query_span(system: &'static str, query: &str) -> QuerySpan {
+    tracing::debug_span!(
+        "query",
+        db.system = system,
+        db.statement = query,
+        error = tracing::field::Empty
+    )
+}
+
+#[cfg(all(not(feature = "tracing"), any(feature = "sqlite", feature = "mysql")))]
+pub(crate) fn query_span(_system: &'static str, _query: &str) -> QuerySpan {
+    QuerySpan
+}
+
+#[cfg(all(feature = "tracing", any(feature = "sqlite", feature = "mysql")))]
+pub(crate) async fn traced<R, E: std::fmt::Debug>(
+    span: QuerySpan,
+    run: impl Future<Output = Result<R, E>>,
+) -> Result<R, E> {
+    use tracing::Instrument;
+
+    let result = run.instrument(span.clone()).await;
+    if let Err(err) = &result {
+        span.record("error", tracing::field::debug(err));
+    }
+
+    result
+}
+
+#[cfg(all(not(feature = "tracing"), any(feature = "sqlite", feature = "mysql")))]
+pub(crate) async fn traced<R, E>(
+    _span: QuerySpan,
+    run: impl Future<Output = Result<R, E>>,
+) -> Result<R, E> {
+    run.await
+}
+
+// Polls the rows of a stream in the span, which records the first error
+#[cfg(all(feature = "tracing", any(feature = "sqlite", feature = "mysql")))]
+pub(crate) fn traced_stream<'a, T: 'a, E: std::fmt::Debug + 'a>(
+    span: QuerySpan,
+    mut rows: BoxStream<'a, Result<T, E>>,
+) -> BoxStream<'a, Result<T, E>> {
+    use futures::stream::StreamExt;
+    use std::task::Poll;
+
+    let mut failed = false;
+    futures::stream::poll_fn(move |cx| {
+        let _entered = span.enter();
+        let row = rows.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Err(err))) = &row {
+            if !failed {
+                failed = true;
+                span.record("error", tracing::field::debug(err));
+            }
+        }
+
+        row
+    })
+    .boxed()
+}
+
+#[cfg(all(not(feature = "tracing"), any(feature = "sqlite", feature = "mysql")))]
+pub(crate) fn traced_stream<'a, T, E>(
+    _span: QuerySpan,
+    rows: BoxStream<'a, Result<T, E>>,
+) -> BoxStream<'a, Result<T, E>> {
+    rows
+}
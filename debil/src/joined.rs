@@ -0,0 +1,126 @@
+use crate::{Dialect, QueryBuilder, SqlMapper, SqlNull, SqlTable};
+use std::collections::HashMap;
+
+/// Alias of a column selected by `SqlConn::load_joined`, e.g. `user__id`
+pub fn joined_alias(table_name: &str, column: &str) -> String {
+    format!("{}__{}", table_name, column)
+}
+
+/// A member of the tuple loaded by `SqlConn::load_joined`: a table, or `Option` of a table
+/// which is `None` where an outer join found no row
+pub trait JoinedTable: Sized {
+    type Table: SqlTable;
+
+    fn from_joined(values: &mut HashMap<String, <Self::Table as SqlMapper>::ValueType>) -> Self;
+}
+
+// Takes the columns of T out of the joined row, keyed by their bare names
+fn take_columns<T: SqlTable>(
+    values: &mut HashMap<String, T::ValueType>,
+) -> HashMap<String, T::ValueType> {
+    let table_name = SqlTable::table_name(std::marker::PhantomData::<T>);
+
+    SqlTable::schema_of(std::marker::PhantomData::<T>)
+        .into_iter()
+        .filter_map(|(column, _, _)| {
+            values
+                .remove(&joined_alias(&table_name, &column))
+                .map(|value| (column, value))
+        })
+        .collect()
+}
+
+impl<T: SqlTable> JoinedTable for T {
+    type Table = T;
+
+    fn from_joined(values: &mut HashMap<String, T::ValueType>) -> Self {
+        T::map_from_sql(take_columns::<T>(values))
+    }
+}
+
+impl<T: SqlTable> JoinedTable for Option<T>
+where
+    T::ValueType: SqlNull,
+{
+    type Table = T;
+
+    fn from_joined(values: &mut HashMap<String, T::ValueType>) -> Self {
+        let columns = take_columns::<T>(values);
+        if columns.values().all(SqlNull::is_null) {
+            return None;
+        }
+
+        Some(T::map_from_sql(columns))
+    }
+}
+
+/// Tuples of tables such as `(User, Post)` or `(User, Option<Post>)`, loaded by
+/// `SqlConn::load_joined` from a single row of a joined query
+pub trait JoinedRow: SqlMapper {
+    /// Selects every column of the tables with a table-prefixed alias.
+    /// The first table is qualified with the alias if the builder has one from `table_as`,
+    /// and the others with their table names.
+    fn select_joined(
+        builder: QueryBuilder<Self::ValueType>,
+        dialect: &dyn Dialect,
+    ) -> QueryBuilder<Self::ValueType>;
+}
+
+fn joined_columns<T: SqlTable>(dialect: &dyn Dialect, qualifier: &str) -> Vec<String> {
+    let table_name = SqlTable::table_name(std::marker::PhantomData::<T>);
+
+    SqlTable::schema_of(std::marker::PhantomData::<T>)
+        .into_iter()
+        .map(|(column, _, _)| {
+            format!(
+                "{}.{} AS {}",
+                dialect.quote_qualified(qualifier),
+                dialect.quote_identifier(&column),
+                dialect.quote_identifier(&joined_alias(&table_name, &column))
+            )
+        })
+        .collect()
+}
+
+macro_rules! impl_joined_row {
+    ($first:ident, $($rest:ident),+) => {
+        impl<$first: JoinedTable, $($rest: JoinedTable),+> SqlMapper for ($first, $($rest),+)
+        where
+            $($rest::Table: SqlTable<ValueType = <$first::Table as SqlMapper>::ValueType>),+
+        {
+            type ValueType = <$first::Table as SqlMapper>::ValueType;
+
+            fn map_from_sql(mut values: HashMap<String, Self::ValueType>) -> Self {
+                (
+                    $first::from_joined(&mut values),
+                    $($rest::from_joined(&mut values)),+
+                )
+            }
+        }
+
+        impl<$first: JoinedTable, $($rest: JoinedTable),+> JoinedRow for ($first, $($rest),+)
+        where
+            $($rest::Table: SqlTable<ValueType = <$first::Table as SqlMapper>::ValueType>),+
+        {
+            fn select_joined(
+                builder: QueryBuilder<Self::ValueType>,
+                dialect: &dyn Dialect,
+            ) -> QueryBuilder<Self::ValueType> {
+                let (builder, qualifier) = builder.default_table(SqlTable::table_name(
+                    std::marker::PhantomData::<$first::Table>,
+                ));
+
+                builder
+                    .append_selects(joined_columns::<$first::Table>(dialect, &qualifier))
+                    $(.append_selects(joined_columns::<$rest::Table>(
+                        dialect,
+                        &SqlTable::table_name(std::marker::PhantomData::<$rest::Table>),
+                    )))+
+            }
+        }
+    };
+}
+
+impl_joined_row!(A, B);
+impl_joined_row!(A, B, C);
+impl_joined_row!(A, B, C, D);
@@ -3,20 +3,54 @@ pub use debil_derive::*;
 mod types;
 pub use types::*;
 
+mod dialect;
+pub use dialect::*;
+
 mod conn;
 pub use conn::*;
 
+mod relation;
+pub use relation::*;
+
 mod query_builder;
 pub use query_builder::*;
 
+mod joined;
+pub use joined::*;
+
+mod compound_query;
+pub use compound_query::*;
+
+mod window;
+pub use window::*;
+
 mod macros;
-pub use macros::*;
+
+mod hook;
+pub use hook::{Instrumented, QueryEvent, QueryHook, SlowQuery};
+
+mod pool;
+pub use pool::*;
 
 mod query;
 pub use query::*;
 
+mod routing;
+pub use routing::*;
+
+mod placeholder;
+pub use placeholder::{expand_params, to_positional, PlaceholderStyle, UnknownPlaceholder};
+
+pub mod mock;
+
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 
 #[cfg(feature = "mysql")]
 pub mod mysql;
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+#[cfg(feature = "memory")]
+pub mod memory;
@@ -1,8 +1,17 @@
+/// `table.column` of a field, e.g. `accessor!(User::name)` is `user.name`.
+/// Give a dialect as in `accessor!(conn.dialect(), User::name)` to quote the names for it.
 #[macro_export]
 macro_rules! accessor {
     ($t: ident :: $v: ident) => {
         format!("{}.{}", $crate::table_name::<$t>(), <$t>::$v())
     };
+    ($dialect: expr, $t: ident :: $v: ident) => {
+        format!(
+            "{}.{}",
+            $crate::Dialect::quote_qualified($dialect, &$crate::table_name::<$t>()),
+            $crate::Dialect::quote_identifier($dialect, <$t>::$v())
+        )
+    };
 }
 
 #[macro_export]
@@ -30,45 +39,56 @@ mod test {
     }
 }
 
+/// Equations of fields and their binds for `QueryBuilder::wheres`,
+/// e.g. `record_expr!(User, { name: "foo".to_string() })`.
+/// Give a dialect as in `record_expr!(conn.dialect(), User, { ... })` to quote the names for it.
 #[macro_export]
 macro_rules! record_expr {
     ($name:ident, {$($body:tt)*}) => {
-        record_expr!(@wrapper _internal_for_type_checking, $name, $($body)*)
+        record_expr!(&$crate::GenericDialect, $name, {$($body)*})
     };
 
-    (@wrapper $var:ident, $name:ident, $($body:tt)*) => {
+    (@wrapper $dialect:ident, $var:ident, $name:ident, $($body:tt)*) => {
         {
             let mut result = vec![];
             let mut params = vec![];
             let mut $var: $name = Default::default();
 
-            record_expr!(@record_expr result, params, $var, $name, $($body)*)
+            record_expr!(@record_expr $dialect, result, params, $var, $name, $($body)*)
         }
     };
 
-    (@record_expr $result:ident, $params:ident, $var:ident, $name:ident, $field:ident : $e:expr $(,)?) => {
+    (@record_expr $dialect:ident, $result:ident, $params:ident, $var:ident, $name:ident, $field:ident : $e:expr $(,)?) => {
         {
             let expr = $e;
             $var.$field = expr.clone();
 
             let params_name = format!(":{}", accessor_name!($name::$field));
-            $result.push(format!("{} = {}", accessor!($name::$field), &params_name));
+            $result.push(format!("{} = {}", accessor!($dialect, $name::$field), &params_name));
             $params.push((accessor_name!($name::$field).to_string(), SqlValue::serialize(expr)));
 
             ($result, $params)
         }
     };
 
-    (@record_expr $result:ident, $params:ident, $var:ident, $name:ident, $field:ident : $e:expr, $($tails:tt)*) => {
+    (@record_expr $dialect:ident, $result:ident, $params:ident, $var:ident, $name:ident, $field:ident : $e:expr, $($tails:tt)*) => {
         {
             let expr = $e;
             $var.$field = expr.clone();
 
             let params_name = format!(":{}", accessor_name!($name::$field));
-            $result.push(format!("{} = {}", accessor!($name::$field), &params_name));
+            $result.push(format!("{} = {}", accessor!($dialect, $name::$field), &params_name));
             $params.push((accessor_name!($name::$field).to_string(), SqlValue::serialize(expr)));
 
-            record_expr!(@record_expr $result, $params, $var, $name, $($tails)*)
+            record_expr!(@record_expr $dialect, $result, $params, $var, $name, $($tails)*)
+        }
+    };
+
+    ($dialect:expr, $name:ident, {$($body:tt)*}) => {
+        {
+            let dialect: &dyn $crate::Dialect = $dialect;
+
+            record_expr!(@wrapper dialect, _internal_for_type_checking, $name, $($body)*)
         }
     };
 }
@@ -192,5 +212,16 @@ mod test_record_expr {
                 ] as Vec<(String, Binary)>
             )
         );
+
+        // quoted for the dialect
+        let (exprs, _): (_, Vec<(String, Binary)>) =
+            record_expr!(&crate::SqliteDialect, H, { f: 2000, g: "fooo".to_string() });
+        assert_eq!(
+            exprs,
+            vec![
+                r#""table_H"."foo" = :foo"#.to_string(),
+                r#""table_H"."g" = :g"#.to_string()
+            ]
+        );
     }
 }
@@ -0,0 +1,14 @@
+// A backend which runs the SQL debil generates on tables held in memory, without a database.
+// It understands CREATE TABLE / INDEX, INSERT (with ON CONFLICT), UPDATE, DELETE and SELECT
+// with WHERE, JOIN, ORDER BY and LIMIT, which is what the builders produce.
+mod conn;
+mod engine;
+mod error;
+mod parser;
+mod types;
+
+pub use conn::*;
+pub use error::*;
+pub use types::*;
+
+pub type DefaultSqlValue = MemoryValue;
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::memory::engine::{Database, Output};
+use crate::memory::error::Error;
+use crate::memory::types::{AsMemoryValue, MemoryValue};
+use crate::{Dialect, MemoryDialect, Params, Row, SqlConn, SqlMapper};
+use async_trait::async_trait;
+
+/// A `SqlConn` which keeps its tables in memory.
+///
+/// Clones share the same tables, like connections to one database which runs one transaction
+/// at a time: while a clone has a transaction open, the statements of the other clones wait
+/// until it is committed or rolled back. Rows are stored as `HashMap`s of column names to `V`.
+pub struct MemoryConn<V = MemoryValue> {
+    db: Arc<Mutex<Database<V>>>,
+    transaction_lock: Arc<tokio::sync::Mutex<()>>,
+    // Held from start_transaction until commit or rollback
+    transaction: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl<V> Clone for MemoryConn<V> {
+    fn clone(&self) -> Self {
+        MemoryConn {
+            db: self.db.clone(),
+            transaction_lock: self.transaction_lock.clone(),
+            transaction: None,
+        }
+    }
+}
+
+impl<V> Default for MemoryConn<V> {
+    fn default() -> Self {
+        MemoryConn {
+            db: Arc::new(Mutex::new(Database::default())),
+            transaction_lock: Arc::new(tokio::sync::Mutex::new(())),
+            transaction: None,
+        }
+    }
+}
+
+impl<V> Drop for MemoryConn<V> {
+    // The tables outlive this clone, so its transaction must not leak into the others
+    fn drop(&mut self) {
+        if self.transaction.is_some() {
+            if let Ok(mut db) = self.db.lock() {
+                db.rollback();
+            }
+        }
+    }
+}
+
+impl<V: AsMemoryValue> MemoryConn<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rows of the table in the order they were inserted, for assertions of tests
+    pub fn rows(&self, table_name: &str) -> Result<Vec<HashMap<String, V>>, Error> {
+        Ok(self.db.lock().unwrap().table(table_name)?.rows.clone())
+    }
+
+    async fn execute(&self, query: &str, params: Params<V>) -> Result<Output<V>, Error> {
+        let _guard = match self.transaction {
+            Some(_) => None,
+            None => Some(self.transaction_lock.lock().await),
+        };
+
+        self.db.lock().unwrap().execute(query, params.0)
+    }
+}
+
+#[async_trait]
+impl<V: AsMemoryValue> SqlConn<V> for MemoryConn<V> {
+    type Error = Error;
+
+    fn dialect(&self) -> &dyn Dialect {
+        &MemoryDialect
+    }
+
+    async fn sql_exec(&mut self, query: String, params: Params<V>) -> Result<u64, Error> {
+        Ok(match self.execute(&query, params).await? {
+            Output::Affected(rows) => rows,
+            Output::Rows(_, rows) => rows.len() as u64,
+        })
+    }
+
+    async fn sql_query<T: SqlMapper<ValueType = V> + Sync + Send>(
+        &mut self,
+        query: String,
+        params: Params<V>,
+    ) -> Result<Vec<T>, Error> {
+        match self.execute(&query, params).await? {
+            Output::Affected(_) => Ok(vec![]),
+            Output::Rows(columns, rows) => {
+                let columns: Arc<[String]> = columns.into();
+
+                Ok(rows
+                    .into_iter()
+                    .map(|values| T::map_from_row(Row::new(columns.clone(), values)))
+                    .collect())
+            }
+        }
+    }
+
+    async fn sql_batch_exec(&mut self, query: String, params: Vec<Params<V>>) -> Result<(), Error> {
+        for params in params {
+            self.execute(&query, params).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn start_transaction(&mut self) -> Result<(), Error> {
+        let acquired = self.transaction.is_none();
+        if acquired {
+            self.transaction = Some(self.transaction_lock.clone().lock_owned().await);
+        }
+
+        let result = self.execute("BEGIN", Params::new()).await;
+        // A nested BEGIN fails, but keeps the transaction already open by this clone
+        if result.is_err() && acquired {
+            self.transaction = None;
+        }
+        result?;
+
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), Error> {
+        let result = self.execute("COMMIT", Params::new()).await;
+        self.transaction = None;
+        result?;
+
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), Error> {
+        let result = self.execute("ROLLBACK", Params::new()).await;
+        self.transaction = None;
+        result?;
+
+        Ok(())
+    }
+}
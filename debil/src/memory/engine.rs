@@ -0,0 +1,1245 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::memory::error::Error;
+use crate::memory::parser::{
+    self, BinaryOp, ColumnDef, Expr, JoinKind, OnConflict, Select, SelectItem, Statement, UnaryOp,
+};
+use crate::memory::types::{AsMemoryValue, MemoryValue};
+
+pub(crate) type Record<V> = HashMap<String, V>;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Table<V> {
+    name: String,
+    columns: Vec<ColumnDef>,
+    primary_key: Vec<String>,
+    pub(crate) rows: Vec<Record<V>>,
+}
+
+impl<V> Table<V> {
+    // Column names are case insensitive, and rows are keyed by the name in CREATE TABLE
+    fn column_name(&self, name: &str) -> Result<String, Error> {
+        self.columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+            .map(|c| c.name.clone())
+            .ok_or_else(|| Error::NoSuchColumn(format!("{}.{}", self.name, name)))
+    }
+
+    fn qualified(&self, columns: &[String]) -> String {
+        columns
+            .iter()
+            .map(|c| format!("{}.{}", self.name, c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Index {
+    table: String,
+    columns: Vec<String>,
+    unique: bool,
+}
+
+pub(crate) enum Output<V> {
+    Affected(u64),
+    // column names, and rows
+    Rows(Vec<String>, Vec<Vec<V>>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Database<V> {
+    // keyed by the lowercase names
+    tables: HashMap<String, Table<V>>,
+    indexes: HashMap<String, Index>,
+    last_insert_id: Option<i64>,
+    // state at BEGIN, which ROLLBACK restores
+    snapshot: Option<Box<Database<V>>>,
+}
+
+impl<V> Default for Database<V> {
+    fn default() -> Self {
+        Database {
+            tables: HashMap::new(),
+            indexes: HashMap::new(),
+            last_insert_id: None,
+            snapshot: None,
+        }
+    }
+}
+
+impl<V> Database<V> {
+    /// Restores the state at BEGIN, returning false when no transaction is active
+    pub(crate) fn rollback(&mut self) -> bool {
+        match self.snapshot.take() {
+            Some(snapshot) => {
+                *self = *snapshot;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn key_of(name: &str) -> String {
+    name.to_lowercase()
+}
+
+fn null<V: AsMemoryValue>() -> V {
+    V::from_memory_value(MemoryValue::Null)
+}
+
+fn equals(a: &MemoryValue, b: &MemoryValue) -> bool {
+    a.compare(b) == Some(Ordering::Equal)
+}
+
+impl<V: AsMemoryValue> Database<V> {
+    pub(crate) fn table(&self, name: &str) -> Result<&Table<V>, Error> {
+        self.tables
+            .get(&key_of(name))
+            .ok_or_else(|| Error::NoSuchTable(name.to_string()))
+    }
+
+    pub(crate) fn execute(
+        &mut self,
+        sql: &str,
+        params: Vec<(String, V)>,
+    ) -> Result<Output<V>, Error> {
+        let params = params
+            .into_iter()
+            .map(|(k, v)| (k.trim_start_matches(':').to_string(), v))
+            .collect::<HashMap<_, _>>();
+
+        match parser::parse(sql)? {
+            Statement::CreateTable {
+                name,
+                if_not_exists,
+                columns,
+                primary_key,
+            } => self.create_table(name, if_not_exists, columns, primary_key),
+            Statement::DropTable { name, if_exists } => {
+                if self.tables.remove(&key_of(&name)).is_none() && !if_exists {
+                    return Err(Error::NoSuchTable(name));
+                }
+                self.indexes.retain(|_, index| index.table != key_of(&name));
+
+                Ok(Output::Affected(0))
+            }
+            Statement::CreateIndex {
+                name,
+                table,
+                columns,
+                unique,
+                if_not_exists,
+            } => self.create_index(name, table, columns, unique, if_not_exists),
+            Statement::DropIndex { name, if_exists } => {
+                if self.indexes.remove(&key_of(&name)).is_none() && !if_exists {
+                    return Err(Error::SchemaError(format!("no such index: {}", name)));
+                }
+
+                Ok(Output::Affected(0))
+            }
+            Statement::Insert {
+                table,
+                columns,
+                rows,
+                on_conflict,
+            } => self.insert(&params, table, columns, rows, on_conflict),
+            Statement::Update {
+                table,
+                assignments,
+                filter,
+            } => self.update(&params, table, assignments, filter),
+            Statement::Delete { table, filter } => self.delete(&params, table, filter),
+            Statement::Select(select) => self.select(&params, select),
+            Statement::Begin => {
+                if self.snapshot.is_some() {
+                    return Err(Error::SchemaError(
+                        "cannot start a transaction within a transaction".to_string(),
+                    ));
+                }
+                self.snapshot = Some(Box::new(self.clone()));
+
+                Ok(Output::Affected(0))
+            }
+            Statement::Commit => match self.snapshot.take() {
+                Some(_) => Ok(Output::Affected(0)),
+                None => Err(Error::SchemaError(
+                    "cannot commit - no transaction is active".to_string(),
+                )),
+            },
+            Statement::Rollback => {
+                if !self.rollback() {
+                    return Err(Error::SchemaError(
+                        "cannot rollback - no transaction is active".to_string(),
+                    ));
+                }
+
+                Ok(Output::Affected(0))
+            }
+        }
+    }
+
+    fn create_table(
+        &mut self,
+        name: String,
+        if_not_exists: bool,
+        columns: Vec<ColumnDef>,
+        primary_key: Vec<String>,
+    ) -> Result<Output<V>, Error> {
+        if self.tables.contains_key(&key_of(&name)) {
+            return if if_not_exists {
+                Ok(Output::Affected(0))
+            } else {
+                Err(Error::SchemaError(format!("table {} already exists", name)))
+            };
+        }
+
+        let mut table = Table {
+            name: name.clone(),
+            columns,
+            primary_key: vec![],
+            rows: vec![],
+        };
+        table.primary_key = primary_key
+            .iter()
+            .map(|c| table.column_name(c))
+            .collect::<Result<_, _>>()?;
+        self.tables.insert(key_of(&name), table);
+
+        Ok(Output::Affected(0))
+    }
+
+    fn create_index(
+        &mut self,
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        unique: bool,
+        if_not_exists: bool,
+    ) -> Result<Output<V>, Error> {
+        if self.indexes.contains_key(&key_of(&name)) {
+            return if if_not_exists {
+                Ok(Output::Affected(0))
+            } else {
+                Err(Error::SchemaError(format!("index {} already exists", name)))
+            };
+        }
+
+        let target = self.table(&table)?;
+        let columns = columns
+            .iter()
+            .map(|c| target.column_name(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        if unique {
+            for (i, row) in target.rows.iter().enumerate() {
+                if find_conflict(&target.rows, std::slice::from_ref(&columns), row, Some(i))
+                    .is_some()
+                {
+                    return Err(Error::ConstraintViolation(format!(
+                        "UNIQUE constraint failed: {}",
+                        target.qualified(&columns)
+                    )));
+                }
+            }
+        }
+
+        self.indexes.insert(
+            key_of(&name),
+            Index {
+                table: key_of(&table),
+                columns,
+                unique,
+            },
+        );
+
+        Ok(Output::Affected(0))
+    }
+
+    // Sets of columns whose values must be unique together
+    fn unique_keys(&self, table: &Table<V>) -> Vec<Vec<String>> {
+        let mut keys = vec![];
+        if !table.primary_key.is_empty() {
+            keys.push(table.primary_key.clone());
+        }
+        keys.extend(
+            table
+                .columns
+                .iter()
+                .filter(|c| c.unique)
+                .map(|c| vec![c.name.clone()]),
+        );
+        keys.extend(
+            self.indexes
+                .values()
+                .filter(|index| index.unique && index.table == key_of(&table.name))
+                .map(|index| index.columns.clone()),
+        );
+
+        keys
+    }
+
+    fn insert(
+        &mut self,
+        params: &HashMap<String, V>,
+        table: String,
+        columns: Vec<String>,
+        values: Vec<Vec<Expr>>,
+        on_conflict: Option<(Vec<String>, OnConflict)>,
+    ) -> Result<Output<V>, Error> {
+        let eval = Eval {
+            params,
+            last_insert_id: self.last_insert_id,
+        };
+        let target = self.table(&table)?;
+        let keys = self.unique_keys(target);
+        let columns = columns
+            .iter()
+            .map(|c| target.column_name(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        let conflict_target = match &on_conflict {
+            Some((columns, _)) => Some(
+                columns
+                    .iter()
+                    .map(|c| target.column_name(c))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        // rows are changed on a copy, so that a failure leaves the table as it was
+        let mut rows = target.rows.clone();
+        let mut last_insert_id = self.last_insert_id;
+        let mut affected = 0;
+
+        for exprs in values {
+            if exprs.len() != columns.len() {
+                return Err(Error::SyntaxError(format!(
+                    "{} values for {} columns",
+                    exprs.len(),
+                    columns.len()
+                )));
+            }
+
+            let mut record = target
+                .columns
+                .iter()
+                .map(|c| (c.name.clone(), null()))
+                .collect::<Record<V>>();
+            for (column, expr) in columns.iter().zip(exprs.iter()) {
+                record.insert(column.clone(), eval.raw(expr, &NoRow)?);
+            }
+
+            for column in target.columns.iter().filter(|c| c.auto_increment) {
+                let value = match record[&column.name].to_memory_value() {
+                    MemoryValue::Null => {
+                        let next = rows
+                            .iter()
+                            .filter_map(|row| match row[&column.name].to_memory_value() {
+                                MemoryValue::Integer(v) => Some(v),
+                                _ => None,
+                            })
+                            .max()
+                            .unwrap_or(0)
+                            + 1;
+                        record.insert(
+                            column.name.clone(),
+                            V::from_memory_value(MemoryValue::Integer(next)),
+                        );
+                        Some(next)
+                    }
+                    MemoryValue::Integer(v) => Some(v),
+                    _ => None,
+                };
+                if value.is_some() {
+                    last_insert_id = value;
+                }
+            }
+
+            check_not_null(target, &record)?;
+
+            let (i, key) = match find_conflict(&rows, &keys, &record, None) {
+                None => {
+                    rows.push(record);
+                    affected += 1;
+                    continue;
+                }
+                Some(conflict) => conflict,
+            };
+            let violation = Error::ConstraintViolation(format!(
+                "UNIQUE constraint failed: {}",
+                target.qualified(key)
+            ));
+
+            match (&on_conflict, &conflict_target) {
+                (Some((_, action)), Some(conflict_target))
+                    if same_columns(key, conflict_target) =>
+                {
+                    if let OnConflict::Update(assignments) = action {
+                        let scope = TableRow {
+                            table: target,
+                            row: &rows[i],
+                            excluded: Some(&record),
+                        };
+                        let mut updated = rows[i].clone();
+                        for (column, expr) in assignments {
+                            updated.insert(target.column_name(column)?, eval.raw(expr, &scope)?);
+                        }
+
+                        check_not_null(target, &updated)?;
+                        if let Some((_, key)) = find_conflict(&rows, &keys, &updated, Some(i)) {
+                            return Err(Error::ConstraintViolation(format!(
+                                "UNIQUE constraint failed: {}",
+                                target.qualified(key)
+                            )));
+                        }
+                        rows[i] = updated;
+                        affected += 1;
+                    }
+                }
+                _ => return Err(violation),
+            }
+        }
+
+        self.tables.get_mut(&key_of(&table)).unwrap().rows = rows;
+        self.last_insert_id = last_insert_id;
+
+        Ok(Output::Affected(affected))
+    }
+
+    fn update(
+        &mut self,
+        params: &HashMap<String, V>,
+        table: String,
+        assignments: Vec<(String, Expr)>,
+        filter: Option<Expr>,
+    ) -> Result<Output<V>, Error> {
+        let eval = Eval {
+            params,
+            last_insert_id: self.last_insert_id,
+        };
+        let target = self.table(&table)?;
+        let keys = self.unique_keys(target);
+        let assignments = assignments
+            .iter()
+            .map(|(c, expr)| Ok((target.column_name(c)?, expr)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // every assignment sees the row before the UPDATE
+        let mut changes = vec![];
+        for (i, row) in target.rows.iter().enumerate() {
+            let scope = TableRow {
+                table: target,
+                row,
+                excluded: None,
+            };
+            if let Some(filter) = &filter {
+                if !eval.truth(filter, &scope)? {
+                    continue;
+                }
+            }
+
+            let mut updated = row.clone();
+            for (column, expr) in &assignments {
+                updated.insert(column.clone(), eval.raw(expr, &scope)?);
+            }
+            changes.push((i, updated));
+        }
+
+        let mut rows = target.rows.clone();
+        let affected = changes.len() as u64;
+        let changed = changes.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        for (i, updated) in changes {
+            check_not_null(target, &updated)?;
+            rows[i] = updated;
+        }
+        for i in changed {
+            if let Some((_, key)) = find_conflict(&rows, &keys, &rows[i], Some(i)) {
+                return Err(Error::ConstraintViolation(format!(
+                    "UNIQUE constraint failed: {}",
+                    target.qualified(key)
+                )));
+            }
+        }
+
+        self.tables.get_mut(&key_of(&table)).unwrap().rows = rows;
+
+        Ok(Output::Affected(affected))
+    }
+
+    fn delete(
+        &mut self,
+        params: &HashMap<String, V>,
+        table: String,
+        filter: Option<Expr>,
+    ) -> Result<Output<V>, Error> {
+        let eval = Eval {
+            params,
+            last_insert_id: self.last_insert_id,
+        };
+        let target = self.table(&table)?;
+
+        let deleted = target
+            .rows
+            .iter()
+            .map(|row| match &filter {
+                Some(filter) => eval.truth(
+                    filter,
+                    &TableRow {
+                        table: target,
+                        row,
+                        excluded: None,
+                    },
+                ),
+                None => Ok(true),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut deleted = deleted.into_iter();
+        let rows = &mut self.tables.get_mut(&key_of(&table)).unwrap().rows;
+        let before = rows.len();
+        rows.retain(|_| !deleted.next().unwrap());
+
+        Ok(Output::Affected((before - rows.len()) as u64))
+    }
+
+    fn select(&self, params: &HashMap<String, V>, select: Select) -> Result<Output<V>, Error> {
+        let eval = Eval {
+            params,
+            last_insert_id: self.last_insert_id,
+        };
+
+        let mut sources = vec![Source::of(
+            self.table(&select.from.name)?,
+            select.from.alias.clone(),
+        )];
+        for join in &select.joins {
+            sources.push(Source::of(
+                self.table(&join.table.name)?,
+                join.table.alias.clone(),
+            ));
+        }
+
+        // a joined row is an index into the rows of each source, or None for the NULLs of
+        // a LEFT JOIN
+        let mut joined = (0..sources[0].table.rows.len())
+            .map(|i| vec![Some(i)])
+            .collect::<Vec<_>>();
+        for (n, join) in select.joins.iter().enumerate() {
+            let source = &sources[n + 1];
+            let mut next = vec![];
+
+            for row in joined {
+                let mut matched = false;
+                for i in 0..source.table.rows.len() {
+                    let mut candidate = row.clone();
+                    candidate.push(Some(i));
+
+                    let on = match &join.on {
+                        Some(on) => eval.truth(
+                            on,
+                            &JoinedRow {
+                                sources: &sources[..n + 2],
+                                row: &candidate,
+                            },
+                        )?,
+                        None => true,
+                    };
+                    if on {
+                        next.push(candidate);
+                        matched = true;
+                    }
+                }
+
+                if !matched && join.kind == JoinKind::Left {
+                    let mut candidate = row;
+                    candidate.push(None);
+                    next.push(candidate);
+                }
+            }
+
+            joined = next;
+        }
+
+        if let Some(filter) = &select.filter {
+            let mut filtered = vec![];
+            for row in joined {
+                if eval.truth(
+                    filter,
+                    &JoinedRow {
+                        sources: &sources,
+                        row: &row,
+                    },
+                )? {
+                    filtered.push(row);
+                }
+            }
+            joined = filtered;
+        }
+
+        let mut names = vec![];
+        for item in &select.items {
+            match item {
+                SelectItem::Wildcard => {
+                    for source in &sources {
+                        names.extend(source.table.columns.iter().map(|c| c.name.clone()));
+                    }
+                }
+                SelectItem::QualifiedWildcard(qualifier) => {
+                    let source = find_source(&sources, qualifier)?;
+                    names.extend(source.table.columns.iter().map(|c| c.name.clone()));
+                }
+                SelectItem::Expr(_, name) => names.push(name.clone()),
+            }
+        }
+
+        // projected values, and the keys of ORDER BY
+        let mut results = vec![];
+        for row in &joined {
+            let scope = JoinedRow {
+                sources: &sources,
+                row,
+            };
+
+            let mut values = vec![];
+            for item in &select.items {
+                match item {
+                    SelectItem::Wildcard => {
+                        for (source, i) in sources.iter().zip(row.iter()) {
+                            values.extend(source.values(*i));
+                        }
+                    }
+                    SelectItem::QualifiedWildcard(qualifier) => {
+                        let n = sources
+                            .iter()
+                            .position(|s| s.is(qualifier))
+                            .unwrap_or_default();
+                        values.extend(sources[n].values(row[n]));
+                    }
+                    SelectItem::Expr(expr, _) => values.push(eval.raw(expr, &scope)?),
+                }
+            }
+
+            let mut keys = vec![];
+            for (expr, _) in &select.order_by {
+                let output = match expr {
+                    // ORDER BY refers to an output column by its name or its position
+                    Expr::Column(None, name) => {
+                        names.iter().position(|n| n.eq_ignore_ascii_case(name))
+                    }
+                    Expr::Literal(MemoryValue::Integer(n)) => {
+                        Some(*n as usize - 1).filter(|n| *n < values.len())
+                    }
+                    _ => None,
+                };
+                keys.push(match output {
+                    Some(n) => values[n].to_memory_value(),
+                    None => eval.value(expr, &scope)?,
+                });
+            }
+
+            results.push((values, keys));
+        }
+
+        results.sort_by(|(_, a), (_, b)| {
+            for ((a, b), (_, descending)) in a.iter().zip(b.iter()).zip(select.order_by.iter()) {
+                let ordering = a.sort_order(b);
+                if ordering != Ordering::Equal {
+                    return if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    };
+                }
+            }
+            Ordering::Equal
+        });
+
+        let mut rows = results
+            .into_iter()
+            .map(|(values, _)| values)
+            .collect::<Vec<_>>();
+        if select.distinct {
+            let mut seen: Vec<Vec<MemoryValue>> = vec![];
+            rows.retain(|row| {
+                let row = row.iter().map(|v| v.to_memory_value()).collect::<Vec<_>>();
+                if seen.contains(&row) {
+                    false
+                } else {
+                    seen.push(row);
+                    true
+                }
+            });
+        }
+
+        let offset = match &select.offset {
+            Some(expr) => eval.integer(expr)?.max(0) as usize,
+            None => 0,
+        };
+        // a negative LIMIT means no limit, as in SQLite
+        let limit = match &select.limit {
+            Some(expr) => usize::try_from(eval.integer(expr)?).ok(),
+            None => None,
+        };
+        let rows = rows
+            .into_iter()
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Ok(Output::Rows(names, rows))
+    }
+}
+
+fn check_not_null<V: AsMemoryValue>(table: &Table<V>, record: &Record<V>) -> Result<(), Error> {
+    for column in &table.columns {
+        if (column.not_null || table.primary_key.contains(&column.name))
+            && record[&column.name].to_memory_value() == MemoryValue::Null
+        {
+            return Err(Error::ConstraintViolation(format!(
+                "NOT NULL constraint failed: {}.{}",
+                table.name, column.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// A row other than `skip` which has the same values as `record` for one of `keys`.
+// NULLs are distinct from each other, as in SQL.
+fn find_conflict<'a, V: AsMemoryValue>(
+    rows: &[Record<V>],
+    keys: &'a [Vec<String>],
+    record: &Record<V>,
+    skip: Option<usize>,
+) -> Option<(usize, &'a Vec<String>)> {
+    for key in keys {
+        let values = key
+            .iter()
+            .map(|c| record[c].to_memory_value())
+            .collect::<Vec<_>>();
+        if values.contains(&MemoryValue::Null) {
+            continue;
+        }
+
+        let found = rows.iter().enumerate().position(|(i, row)| {
+            Some(i) != skip
+                && key
+                    .iter()
+                    .zip(values.iter())
+                    .all(|(c, v)| equals(&row[c].to_memory_value(), v))
+        });
+        if let Some(i) = found {
+            return Some((i, key));
+        }
+    }
+
+    None
+}
+
+fn same_columns(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().all(|c| b.contains(c))
+}
+
+struct Source<'a, V> {
+    // alias, or the name of the table
+    qualifier: String,
+    table: &'a Table<V>,
+}
+
+impl<'a, V: AsMemoryValue> Source<'a, V> {
+    fn of(table: &'a Table<V>, alias: Option<String>) -> Self {
+        Source {
+            qualifier: alias.unwrap_or_else(|| table.name.clone()),
+            table,
+        }
+    }
+
+    fn is(&self, qualifier: &str) -> bool {
+        self.qualifier.eq_ignore_ascii_case(qualifier)
+    }
+
+    fn values(&self, row: Option<usize>) -> Vec<V> {
+        self.table
+            .columns
+            .iter()
+            .map(|c| match row {
+                Some(i) => self.table.rows[i][&c.name].clone(),
+                None => null(),
+            })
+            .collect()
+    }
+}
+
+fn find_source<'s, 'a, V: AsMemoryValue>(
+    sources: &'s [Source<'a, V>],
+    qualifier: &str,
+) -> Result<&'s Source<'a, V>, Error> {
+    sources
+        .iter()
+        .find(|s| s.is(qualifier))
+        .ok_or_else(|| Error::NoSuchTable(qualifier.to_string()))
+}
+
+// Columns which an expression can refer to
+trait Scope<V> {
+    // None is a NULL of a LEFT JOIN which found no row
+    fn lookup(&self, table: Option<&str>, column: &str) -> Result<Option<&V>, Error>;
+}
+
+struct NoRow;
+
+impl<V> Scope<V> for NoRow {
+    fn lookup(&self, table: Option<&str>, column: &str) -> Result<Option<&V>, Error> {
+        Err(Error::NoSuchColumn(match table {
+            Some(table) => format!("{}.{}", table, column),
+            None => column.to_string(),
+        }))
+    }
+}
+
+// A row of UPDATE, DELETE, or the existing row of an upsert where `excluded` is the row
+// which was not inserted
+struct TableRow<'a, V> {
+    table: &'a Table<V>,
+    row: &'a Record<V>,
+    excluded: Option<&'a Record<V>>,
+}
+
+impl<'a, V> Scope<V> for TableRow<'a, V> {
+    fn lookup(&self, table: Option<&str>, column: &str) -> Result<Option<&V>, Error> {
+        let row = match (table, self.excluded) {
+            (Some(table), Some(excluded)) if table.eq_ignore_ascii_case("excluded") => excluded,
+            (Some(table), _) if !table.eq_ignore_ascii_case(&self.table.name) => {
+                return Err(Error::NoSuchColumn(format!("{}.{}", table, column)))
+            }
+            _ => self.row,
+        };
+
+        Ok(row.get(&self.table.column_name(column)?))
+    }
+}
+
+struct JoinedRow<'s, 'a, V> {
+    sources: &'s [Source<'a, V>],
+    row: &'s [Option<usize>],
+}
+
+impl<'s, 'a, V: AsMemoryValue> Scope<V> for JoinedRow<'s, 'a, V> {
+    fn lookup(&self, table: Option<&str>, column: &str) -> Result<Option<&V>, Error> {
+        let (n, column) = match table {
+            Some(table) => {
+                let n = self
+                    .sources
+                    .iter()
+                    .position(|s| s.is(table))
+                    .ok_or_else(|| Error::NoSuchColumn(format!("{}.{}", table, column)))?;
+                (n, self.sources[n].table.column_name(column)?)
+            }
+            None => {
+                let mut found = self
+                    .sources
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(n, s)| s.table.column_name(column).ok().map(|c| (n, c)));
+                let first = found
+                    .next()
+                    .ok_or_else(|| Error::NoSuchColumn(column.to_string()))?;
+                if found.next().is_some() {
+                    return Err(Error::SyntaxError(format!(
+                        "ambiguous column name: {}",
+                        column
+                    )));
+                }
+                first
+            }
+        };
+
+        Ok(self.row[n].and_then(|i| self.sources[n].table.rows[i].get(&column)))
+    }
+}
+
+struct Eval<'a, V> {
+    params: &'a HashMap<String, V>,
+    last_insert_id: Option<i64>,
+}
+
+impl<'a, V: AsMemoryValue> Eval<'a, V> {
+    // Columns and params are copied as they are, and the rest is converted from MemoryValue
+    fn raw(&self, expr: &Expr, scope: &dyn Scope<V>) -> Result<V, Error> {
+        match expr {
+            Expr::Column(table, column) => Ok(scope
+                .lookup(table.as_deref(), column)?
+                .cloned()
+                .unwrap_or_else(null)),
+            Expr::Param(name) => self.param(name).cloned(),
+            _ => self.value(expr, scope).map(V::from_memory_value),
+        }
+    }
+
+    fn param(&self, name: &str) -> Result<&V, Error> {
+        self.params.get(name).ok_or_else(|| {
+            Error::SyntaxError(format!("no value is bound to the placeholder :{}", name))
+        })
+    }
+
+    // WHERE and ON, where an unknown condition is false
+    fn truth(&self, expr: &Expr, scope: &dyn Scope<V>) -> Result<bool, Error> {
+        Ok(truth(&self.value(expr, scope)?) == Some(true))
+    }
+
+    fn integer(&self, expr: &Expr) -> Result<i64, Error> {
+        match self.value(expr, &NoRow)? {
+            MemoryValue::Integer(v) => Ok(v),
+            v => Err(Error::SyntaxError(format!("{:?} is not an integer", v))),
+        }
+    }
+
+    fn value(&self, expr: &Expr, scope: &dyn Scope<V>) -> Result<MemoryValue, Error> {
+        Ok(match expr {
+            Expr::Literal(v) => v.clone(),
+            Expr::Param(name) => self.param(name)?.to_memory_value(),
+            Expr::Column(table, column) => scope
+                .lookup(table.as_deref(), column)?
+                .map_or(MemoryValue::Null, |v| v.to_memory_value()),
+            Expr::Unary(UnaryOp::Not, expr) => {
+                from_truth(truth(&self.value(expr, scope)?).map(|b| !b))
+            }
+            Expr::Unary(UnaryOp::Neg, expr) => match self.value(expr, scope)? {
+                MemoryValue::Null => MemoryValue::Null,
+                MemoryValue::Integer(v) => MemoryValue::Integer(-v),
+                MemoryValue::Real(v) => MemoryValue::Real(-v),
+                v => return Err(Error::Unsupported(format!("negation of {:?}", v))),
+            },
+            Expr::Binary(lhs, BinaryOp::And, rhs) => {
+                match (
+                    truth(&self.value(lhs, scope)?),
+                    truth(&self.value(rhs, scope)?),
+                ) {
+                    (Some(false), _) | (_, Some(false)) => from_truth(Some(false)),
+                    (Some(true), Some(true)) => from_truth(Some(true)),
+                    _ => MemoryValue::Null,
+                }
+            }
+            Expr::Binary(lhs, BinaryOp::Or, rhs) => {
+                match (
+                    truth(&self.value(lhs, scope)?),
+                    truth(&self.value(rhs, scope)?),
+                ) {
+                    (Some(true), _) | (_, Some(true)) => from_truth(Some(true)),
+                    (Some(false), Some(false)) => from_truth(Some(false)),
+                    _ => MemoryValue::Null,
+                }
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                binary(op, self.value(lhs, scope)?, self.value(rhs, scope)?)?
+            }
+            Expr::IsNull(expr, negated) => from_truth(Some(
+                (self.value(expr, scope)? == MemoryValue::Null) != *negated,
+            )),
+            Expr::InList(expr, list, negated) => {
+                let value = self.value(expr, scope)?;
+                let mut result = Some(false);
+                if value == MemoryValue::Null {
+                    result = None;
+                } else {
+                    for item in list {
+                        match value.compare(&self.value(item, scope)?) {
+                            Some(Ordering::Equal) => {
+                                result = Some(true);
+                                break;
+                            }
+                            None => result = None,
+                            _ => {}
+                        }
+                    }
+                }
+                from_truth(result.map(|b| b != *negated))
+            }
+            Expr::Like(expr, pattern, negated) => {
+                match (self.value(expr, scope)?, self.value(pattern, scope)?) {
+                    (MemoryValue::Null, _) | (_, MemoryValue::Null) => MemoryValue::Null,
+                    (value, pattern) => {
+                        from_truth(Some(like(&to_text(&value), &to_text(&pattern)) != *negated))
+                    }
+                }
+            }
+            Expr::Between(expr, low, high, negated) => {
+                let value = self.value(expr, scope)?;
+                let low = value.compare(&self.value(low, scope)?);
+                let high = value.compare(&self.value(high, scope)?);
+                let result = match (low, high) {
+                    (Some(Ordering::Less), _) | (_, Some(Ordering::Greater)) => Some(false),
+                    (Some(_), Some(_)) => Some(true),
+                    _ => None,
+                };
+                from_truth(result.map(|b| b != *negated))
+            }
+            Expr::Function(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.value(arg, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.function(name, args)?
+            }
+        })
+    }
+
+    fn function(&self, name: &str, args: Vec<MemoryValue>) -> Result<MemoryValue, Error> {
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(Error::SyntaxError(format!(
+                    "wrong number of arguments to function {}()",
+                    name
+                )))
+            }
+        };
+
+        Ok(match name {
+            "LAST_INSERT_ID" | "LAST_INSERT_ROWID" => {
+                arity(0)?;
+                self.last_insert_id
+                    .map_or(MemoryValue::Null, MemoryValue::Integer)
+            }
+            "LOWER" | "UPPER" => {
+                arity(1)?;
+                match &args[0] {
+                    MemoryValue::Text(v) if name == "LOWER" => MemoryValue::Text(v.to_lowercase()),
+                    MemoryValue::Text(v) => MemoryValue::Text(v.to_uppercase()),
+                    v => v.clone(),
+                }
+            }
+            "LENGTH" => {
+                arity(1)?;
+                match &args[0] {
+                    MemoryValue::Null => MemoryValue::Null,
+                    MemoryValue::Blob(v) => MemoryValue::Integer(v.len() as i64),
+                    v => MemoryValue::Integer(to_text(v).chars().count() as i64),
+                }
+            }
+            "ABS" => {
+                arity(1)?;
+                match &args[0] {
+                    MemoryValue::Integer(v) => MemoryValue::Integer(v.abs()),
+                    MemoryValue::Real(v) => MemoryValue::Real(v.abs()),
+                    v => v.clone(),
+                }
+            }
+            "COALESCE" | "IFNULL" => args
+                .into_iter()
+                .find(|v| *v != MemoryValue::Null)
+                .unwrap_or(MemoryValue::Null),
+            "COUNT" | "SUM" | "AVG" | "MIN" | "MAX" | "TOTAL" | "GROUP_CONCAT" => {
+                return Err(Error::Unsupported(format!("aggregate function {}()", name)))
+            }
+            _ => return Err(Error::Unsupported(format!("function {}()", name))),
+        })
+    }
+}
+
+fn truth(value: &MemoryValue) -> Option<bool> {
+    match value {
+        MemoryValue::Null => None,
+        MemoryValue::Integer(v) => Some(*v != 0),
+        MemoryValue::Real(v) => Some(*v != 0.0),
+        MemoryValue::Text(v) => Some(v.trim().parse::<f64>().is_ok_and(|v| v != 0.0)),
+        MemoryValue::Blob(_) => Some(false),
+    }
+}
+
+fn from_truth(value: Option<bool>) -> MemoryValue {
+    value.map_or(MemoryValue::Null, |b| MemoryValue::Integer(b as i64))
+}
+
+fn to_text(value: &MemoryValue) -> String {
+    match value {
+        MemoryValue::Null => String::new(),
+        MemoryValue::Integer(v) => v.to_string(),
+        MemoryValue::Real(v) => v.to_string(),
+        MemoryValue::Text(v) => v.clone(),
+        MemoryValue::Blob(v) => String::from_utf8_lossy(v).into_owned(),
+    }
+}
+
+fn binary(op: &BinaryOp, lhs: MemoryValue, rhs: MemoryValue) -> Result<MemoryValue, Error> {
+    use MemoryValue::*;
+
+    if lhs == Null || rhs == Null {
+        return Ok(Null);
+    }
+
+    let compared = |f: fn(Ordering) -> bool| from_truth(lhs.compare(&rhs).map(f));
+    Ok(match op {
+        BinaryOp::Eq => compared(|o| o == Ordering::Equal),
+        BinaryOp::NotEq => compared(|o| o != Ordering::Equal),
+        BinaryOp::Lt => compared(|o| o == Ordering::Less),
+        BinaryOp::LtEq => compared(|o| o != Ordering::Greater),
+        BinaryOp::Gt => compared(|o| o == Ordering::Greater),
+        BinaryOp::GtEq => compared(|o| o != Ordering::Less),
+        BinaryOp::Concat => Text(to_text(&lhs) + &to_text(&rhs)),
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators are evaluated lazily"),
+        _ => match (&lhs, &rhs) {
+            (Integer(a), Integer(b)) => {
+                let (a, b) = (*a, *b);
+                match op {
+                    BinaryOp::Add => a.checked_add(b).map_or(Null, Integer),
+                    BinaryOp::Sub => a.checked_sub(b).map_or(Null, Integer),
+                    BinaryOp::Mul => a.checked_mul(b).map_or(Null, Integer),
+                    // division by zero is NULL, as in SQLite
+                    BinaryOp::Div => a.checked_div(b).map_or(Null, Integer),
+                    _ => a.checked_rem(b).map_or(Null, Integer),
+                }
+            }
+            (Integer(_), Real(_)) | (Real(_), Integer(_)) | (Real(_), Real(_)) => {
+                let a = match lhs {
+                    Integer(v) => v as f64,
+                    Real(v) => v,
+                    _ => unreachable!(),
+                };
+                let b = match rhs {
+                    Integer(v) => v as f64,
+                    Real(v) => v,
+                    _ => unreachable!(),
+                };
+                match op {
+                    BinaryOp::Add => Real(a + b),
+                    BinaryOp::Sub => Real(a - b),
+                    BinaryOp::Mul => Real(a * b),
+                    _ if b == 0.0 => Null,
+                    BinaryOp::Div => Real(a / b),
+                    _ => Real(a % b),
+                }
+            }
+            _ => {
+                return Err(Error::Unsupported(format!(
+                    "arithmetic on {:?} and {:?}",
+                    lhs, rhs
+                )))
+            }
+        },
+    })
+}
+
+// LIKE with `%` and `_`, which is case insensitive for ASCII as in SQLite
+fn like(value: &str, pattern: &str) -> bool {
+    let value = value.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+
+    // matched[j]: whether value[..i] matches pattern[..j]
+    let mut matched = vec![false; pattern.len() + 1];
+    matched[0] = true;
+    for j in 0..pattern.len() {
+        matched[j + 1] = matched[j] && pattern[j] == '%';
+    }
+
+    for c in value {
+        let mut next = vec![false; pattern.len() + 1];
+        for j in 0..pattern.len() {
+            next[j + 1] = match pattern[j] {
+                '%' => next[j] || matched[j + 1],
+                '_' => matched[j],
+                p => matched[j] && p.eq_ignore_ascii_case(&c),
+            };
+        }
+        matched = next;
+    }
+
+    matched[pattern.len()]
+}
+
+#[test]
+fn like_patterns() {
+    assert!(like("hello", "h%"));
+    assert!(like("hello", "%LL%"));
+    assert!(like("hello", "h_llo"));
+    assert!(!like("hello", "h_lo"));
+    assert!(like("", "%"));
+    assert!(!like("hello", "world%"));
+}
+
+#[test]
+fn execute_statements() {
+    let mut db = Database::<MemoryValue>::default();
+    let rows = |output| match output {
+        Output::Rows(names, rows) => (names, rows),
+        Output::Affected(_) => panic!("expected rows"),
+    };
+
+    db.execute(
+        r#"CREATE TABLE IF NOT EXISTS "item" ("id" INTEGER GENERATED BY DEFAULT AS IDENTITY, "name" TEXT UNIQUE NOT NULL, "price" INTEGER, CONSTRAINT "item_pkey" PRIMARY KEY("id"))"#,
+        vec![],
+    )
+    .unwrap();
+    db.execute(
+        r#"INSERT INTO "item" ("name", "price") VALUES (:a, 100), (:b, NULL), ('c', 300)"#,
+        vec![
+            (":a".to_string(), MemoryValue::Text("a".to_string())),
+            ("b".to_string(), MemoryValue::Text("b".to_string())),
+        ],
+    )
+    .unwrap();
+    assert_eq!(db.last_insert_id, Some(3));
+
+    assert_eq!(
+        rows(db
+            .execute(
+                r#"SELECT "name", price * 2 AS doubled FROM "item" WHERE price IS NOT NULL ORDER BY doubled DESC"#,
+                vec![],
+            )
+            .unwrap()),
+        (
+            vec!["name".to_string(), "doubled".to_string()],
+            vec![
+                vec![MemoryValue::Text("c".to_string()), MemoryValue::Integer(600)],
+                vec![MemoryValue::Text("a".to_string()), MemoryValue::Integer(200)],
+            ]
+        )
+    );
+
+    assert_eq!(
+        db.execute("INSERT INTO item (name) VALUES ('a')", vec![])
+            .err(),
+        Some(Error::ConstraintViolation(
+            "UNIQUE constraint failed: item.name".to_string()
+        ))
+    );
+    // a failed statement changes nothing
+    assert_eq!(
+        db.execute("INSERT INTO item (name) VALUES ('d'), (NULL)", vec![])
+            .err(),
+        Some(Error::ConstraintViolation(
+            "NOT NULL constraint failed: item.name".to_string()
+        ))
+    );
+    assert_eq!(db.table("item").unwrap().rows.len(), 3);
+
+    db.execute(
+        r#"INSERT INTO item (id, name, price) VALUES (1, 'x', 10) ON CONFLICT("id") DO UPDATE SET "price" = excluded."price""#,
+        vec![],
+    )
+    .unwrap();
+    assert_eq!(
+        rows(
+            db.execute("SELECT name, price FROM item WHERE id = 1", vec![])
+                .unwrap()
+        )
+        .1,
+        vec![vec![
+            MemoryValue::Text("a".to_string()),
+            MemoryValue::Integer(10)
+        ]]
+    );
+
+    db.execute("BEGIN", vec![]).unwrap();
+    db.execute("DELETE FROM item WHERE price > 5", vec![])
+        .unwrap();
+    assert_eq!(db.table("item").unwrap().rows.len(), 1);
+    db.execute("ROLLBACK", vec![]).unwrap();
+    assert_eq!(db.table("item").unwrap().rows.len(), 3);
+}
@@ -0,0 +1,39 @@
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    NotFoundError,
+    SyntaxError(String),
+    /// Valid SQL which the memory backend does not run, such as subqueries or GROUP BY
+    Unsupported(String),
+    NoSuchTable(String),
+    NoSuchColumn(String),
+    /// Violation of a UNIQUE, PRIMARY KEY or NOT NULL constraint
+    ConstraintViolation(String),
+    /// A table or index which already exists, or misuse of transactions
+    SchemaError(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFoundError => write!(f, "record not found"),
+            Error::SyntaxError(message) => write!(f, "syntax error: {}", message),
+            Error::Unsupported(message) => write!(f, "unsupported: {}", message),
+            Error::NoSuchTable(table) => write!(f, "no such table: {}", table),
+            Error::NoSuchColumn(column) => write!(f, "no such column: {}", column),
+            Error::ConstraintViolation(message) => write!(f, "{}", message),
+            Error::SchemaError(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl crate::HasNotFound for Error {
+    fn not_found() -> Self {
+        Error::NotFoundError
+    }
+
+    fn unsupported(err: crate::Unsupported) -> Self {
+        Error::Unsupported(err.to_string())
+    }
+}
@@ -0,0 +1,1122 @@
+use crate::memory::error::Error;
+use crate::memory::types::MemoryValue;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    // an unquoted identifier or a keyword
+    Word(String),
+    // an identifier in double quotes or backquotes
+    Quoted(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Param(String),
+    Symbol(&'static str),
+}
+
+// Longer symbols come first, so that `<=` is not read as `<` and `=`
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "<>", "!=", "==", "||", "(", ")", ",", ".", "*", "=", "<", ">", "+", "-", "/", "%",
+    ";",
+];
+
+// Keywords which end an expression or a table, and so cannot be an alias without AS
+const RESERVED: &[&str] = &[
+    "FROM",
+    "WHERE",
+    "AND",
+    "OR",
+    "NOT",
+    "INNER",
+    "LEFT",
+    "RIGHT",
+    "FULL",
+    "CROSS",
+    "OUTER",
+    "JOIN",
+    "ON",
+    "ORDER",
+    "GROUP",
+    "HAVING",
+    "LIMIT",
+    "OFFSET",
+    "AS",
+    "ASC",
+    "DESC",
+    "IS",
+    "IN",
+    "LIKE",
+    "BETWEEN",
+    "UNION",
+    "INTERSECT",
+    "EXCEPT",
+    "SET",
+    "VALUES",
+];
+
+// Tokens with the byte range they came from
+fn tokenize(sql: &str) -> Result<Vec<(Token, usize, usize)>, Error> {
+    let chars = sql.char_indices().collect::<Vec<_>>();
+    let offset = |i: usize| chars.get(i).map_or(sql.len(), |(o, _)| *o);
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i].1 != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len()
+                && !(chars[i].1 == '*' && chars.get(i + 1).map(|(_, c)| *c) == Some('/'))
+            {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' || c == '"' || c == '`' {
+            // a doubled quote is an escaped quote
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i).map(|(_, c)| *c) {
+                    None => {
+                        return Err(Error::SyntaxError(format!(
+                            "unterminated quote at {}",
+                            start
+                        )))
+                    }
+                    Some(q) if q == c => {
+                        if chars.get(i + 1).map(|(_, c)| *c) == Some(c) {
+                            value.push(c);
+                            i += 2;
+                        } else {
+                            i += 1;
+                            break;
+                        }
+                    }
+                    Some(v) => {
+                        value.push(v);
+                        i += 1;
+                    }
+                }
+            }
+            let token = if c == '\'' {
+                Token::Str(value)
+            } else {
+                Token::Quoted(value)
+            };
+            tokens.push((token, start, offset(i)));
+        } else if c.is_ascii_digit() {
+            let begin = i;
+            while i < chars.len() && chars[i].1.is_ascii_digit() {
+                i += 1;
+            }
+            let is_float =
+                i + 1 < chars.len() && chars[i].1 == '.' && chars[i + 1].1.is_ascii_digit();
+            if is_float {
+                i += 1;
+                while i < chars.len() && chars[i].1.is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text = &sql[offset(begin)..offset(i)];
+            let token = if is_float {
+                Token::Float(text.parse().unwrap())
+            } else {
+                Token::Int(
+                    text.parse()
+                        .map_err(|_| Error::SyntaxError(format!("integer too large: {}", text)))?,
+                )
+            };
+            tokens.push((token, start, offset(i)));
+        } else if c == ':' && next.is_some_and(|c| c.is_ascii_alphabetic() || c == '_') {
+            i += 1;
+            let begin = i;
+            while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            tokens.push((
+                Token::Param(sql[offset(begin)..offset(i)].to_string()),
+                start,
+                offset(i),
+            ));
+        } else if c.is_alphabetic() || c == '_' {
+            let begin = i;
+            while i < chars.len()
+                && (chars[i].1.is_alphanumeric() || chars[i].1 == '_' || chars[i].1 == '$')
+            {
+                i += 1;
+            }
+            tokens.push((
+                Token::Word(sql[offset(begin)..offset(i)].to_string()),
+                start,
+                offset(i),
+            ));
+        } else {
+            let rest = &sql[start..];
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(**s))
+                .ok_or_else(|| Error::SyntaxError(format!("unexpected character {:?}", c)))?;
+            i += symbol.chars().count();
+            tokens.push((Token::Symbol(symbol), start, offset(i)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Literal(MemoryValue),
+    Param(String),
+    // optional qualifier, and column name
+    Column(Option<String>, String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    // `IS NULL`, or `IS NOT NULL` if negated
+    IsNull(Box<Expr>, bool),
+    InList(Box<Expr>, Vec<Expr>, bool),
+    Like(Box<Expr>, Box<Expr>, bool),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>, bool),
+    Function(String, Vec<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ColumnDef {
+    pub name: String,
+    pub unique: bool,
+    pub not_null: bool,
+    pub auto_increment: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum OnConflict {
+    Nothing,
+    Update(Vec<(String, Expr)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum JoinKind {
+    Inner,
+    Left,
+    Cross,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    pub on: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SelectItem {
+    Wildcard,
+    QualifiedWildcard(String),
+    // expression, and its column name in the result
+    Expr(Expr, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Select {
+    pub distinct: bool,
+    pub items: Vec<SelectItem>,
+    pub from: TableRef,
+    pub joins: Vec<Join>,
+    pub filter: Option<Expr>,
+    // expression, and whether it is descending
+    pub order_by: Vec<(Expr, bool)>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Statement {
+    CreateTable {
+        name: String,
+        if_not_exists: bool,
+        columns: Vec<ColumnDef>,
+        primary_key: Vec<String>,
+    },
+    DropTable {
+        name: String,
+        if_exists: bool,
+    },
+    CreateIndex {
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        unique: bool,
+        if_not_exists: bool,
+    },
+    DropIndex {
+        name: String,
+        if_exists: bool,
+    },
+    Insert {
+        table: String,
+        columns: Vec<String>,
+        rows: Vec<Vec<Expr>>,
+        on_conflict: Option<(Vec<String>, OnConflict)>,
+    },
+    Update {
+        table: String,
+        assignments: Vec<(String, Expr)>,
+        filter: Option<Expr>,
+    },
+    Delete {
+        table: String,
+        filter: Option<Expr>,
+    },
+    Select(Select),
+    Begin,
+    Commit,
+    Rollback,
+}
+
+pub(crate) fn parse(sql: &str) -> Result<Statement, Error> {
+    let mut parser = Parser {
+        sql,
+        tokens: tokenize(sql)?,
+        pos: 0,
+    };
+    let statement = parser.statement()?;
+    parser.eat_symbol(";");
+
+    match parser.tokens.get(parser.pos) {
+        None => Ok(statement),
+        Some((Token::Word(w), _, _))
+            if ["UNION", "INTERSECT", "EXCEPT", "GROUP", "HAVING"]
+                .iter()
+                .any(|k| w.eq_ignore_ascii_case(k)) =>
+        {
+            Err(Error::Unsupported(w.to_ascii_uppercase()))
+        }
+        Some((_, start, _)) => Err(Error::SyntaxError(format!(
+            "unexpected `{}`",
+            &sql[*start..]
+        ))),
+    }
+}
+
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _, _)| t)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|(t, _, _)| t)
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, Error> {
+        Err(Error::SyntaxError(match self.tokens.get(self.pos) {
+            Some((_, start, _)) => format!("expected {} at `{}`", expected, &self.sql[*start..]),
+            None => format!("expected {} at the end", expected),
+        }))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            self.error(keyword)
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol_of(symbol))) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            self.error(&format!("`{}`", symbol))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::Word(w)) if !is_reserved(w) => {
+                let w = w.clone();
+                self.pos += 1;
+                Ok(w)
+            }
+            Some(Token::Quoted(q)) => {
+                let q = q.clone();
+                self.pos += 1;
+                Ok(q)
+            }
+            _ => self.error("an identifier"),
+        }
+    }
+
+    // A possibly qualified name such as `schema.table`
+    fn table_name(&mut self) -> Result<String, Error> {
+        let mut name = self.identifier()?;
+        while self.eat_symbol(".") {
+            name = format!("{}.{}", name, self.identifier()?);
+        }
+
+        Ok(name)
+    }
+
+    fn identifier_list(&mut self) -> Result<Vec<String>, Error> {
+        self.expect_symbol("(")?;
+        let mut names = vec![self.identifier()?];
+        while self.eat_symbol(",") {
+            names.push(self.identifier()?);
+        }
+        self.expect_symbol(")")?;
+
+        Ok(names)
+    }
+
+    fn if_exists(&mut self) -> Result<bool, Error> {
+        if self.eat_keyword("IF") {
+            self.expect_keyword("EXISTS")?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn if_not_exists(&mut self) -> Result<bool, Error> {
+        if self.eat_keyword("IF") {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        if self.eat_keyword("CREATE") {
+            let unique = self.eat_keyword("UNIQUE");
+            if self.eat_keyword("INDEX") {
+                self.create_index(unique)
+            } else if !unique && self.eat_keyword("TABLE") {
+                self.create_table()
+            } else {
+                self.error("TABLE or INDEX")
+            }
+        } else if self.eat_keyword("DROP") {
+            if self.eat_keyword("TABLE") {
+                let if_exists = self.if_exists()?;
+                Ok(Statement::DropTable {
+                    if_exists,
+                    name: self.table_name()?,
+                })
+            } else if self.eat_keyword("INDEX") {
+                let if_exists = self.if_exists()?;
+                let name = self.identifier()?;
+                if self.eat_keyword("ON") {
+                    self.table_name()?;
+                }
+                Ok(Statement::DropIndex { name, if_exists })
+            } else {
+                self.error("TABLE or INDEX")
+            }
+        } else if self.eat_keyword("INSERT") {
+            self.insert()
+        } else if self.eat_keyword("UPDATE") {
+            let table = self.table_name()?;
+            self.expect_keyword("SET")?;
+            let assignments = self.assignments()?;
+            let filter = self.filter()?;
+            Ok(Statement::Update {
+                table,
+                assignments,
+                filter,
+            })
+        } else if self.eat_keyword("DELETE") {
+            self.expect_keyword("FROM")?;
+            let table = self.table_name()?;
+            let filter = self.filter()?;
+            Ok(Statement::Delete { table, filter })
+        } else if self.eat_keyword("SELECT") {
+            self.select().map(Statement::Select)
+        } else if self.eat_keyword("BEGIN") || self.eat_keyword("START") {
+            self.eat_keyword("TRANSACTION");
+            Ok(Statement::Begin)
+        } else if self.eat_keyword("COMMIT") {
+            Ok(Statement::Commit)
+        } else if self.eat_keyword("ROLLBACK") {
+            Ok(Statement::Rollback)
+        } else {
+            match self.peek() {
+                Some(Token::Word(w)) => Err(Error::Unsupported(w.to_ascii_uppercase())),
+                _ => self.error("a statement"),
+            }
+        }
+    }
+
+    fn create_table(&mut self) -> Result<Statement, Error> {
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.table_name()?;
+        let mut columns = vec![];
+        let mut primary_key = vec![];
+
+        self.expect_symbol("(")?;
+        loop {
+            if self.eat_keyword("CONSTRAINT") {
+                self.identifier()?;
+            }
+            if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                primary_key = self.identifier_list()?;
+            } else {
+                let column = self.column_def()?;
+                if column.1 {
+                    primary_key = vec![column.0.name.clone()];
+                }
+                columns.push(column.0);
+            }
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+
+        Ok(Statement::CreateTable {
+            name,
+            if_not_exists,
+            columns,
+            primary_key,
+        })
+    }
+
+    // A column definition, and whether it is declared as PRIMARY KEY.
+    // Types are skipped, since values are typed dynamically as in SQLite.
+    fn column_def(&mut self) -> Result<(ColumnDef, bool), Error> {
+        let mut column = ColumnDef {
+            name: self.identifier()?,
+            unique: false,
+            not_null: false,
+            auto_increment: false,
+        };
+        let mut primary_key = false;
+        let mut depth = 0;
+
+        loop {
+            match self.peek() {
+                None => break,
+                Some(Token::Symbol(",")) | Some(Token::Symbol(")")) if depth == 0 => break,
+                Some(Token::Symbol("(")) => depth += 1,
+                Some(Token::Symbol(")")) => depth -= 1,
+                Some(Token::Word(w)) => match w.to_ascii_uppercase().as_str() {
+                    "UNIQUE" => column.unique = true,
+                    "NULL" => {
+                        column.not_null = matches!(
+                            &self.tokens[self.pos - 1].0,
+                            Token::Word(prev) if prev.eq_ignore_ascii_case("NOT")
+                        )
+                    }
+                    "KEY" => primary_key = true,
+                    "IDENTITY" | "AUTO_INCREMENT" | "AUTOINCREMENT" => column.auto_increment = true,
+                    _ => {}
+                },
+                _ => {}
+            }
+            self.pos += 1;
+        }
+
+        Ok((column, primary_key))
+    }
+
+    fn create_index(&mut self, unique: bool) -> Result<Statement, Error> {
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.identifier()?;
+        self.expect_keyword("ON")?;
+        let table = self.table_name()?;
+        let columns = self.identifier_list()?;
+
+        Ok(Statement::CreateIndex {
+            name,
+            table,
+            columns,
+            unique,
+            if_not_exists,
+        })
+    }
+
+    fn insert(&mut self) -> Result<Statement, Error> {
+        self.expect_keyword("INTO")?;
+        let table = self.table_name()?;
+        let columns = self.identifier_list()?;
+        self.expect_keyword("VALUES")?;
+
+        let mut rows = vec![];
+        loop {
+            self.expect_symbol("(")?;
+            let mut row = vec![self.expr()?];
+            while self.eat_symbol(",") {
+                row.push(self.expr()?);
+            }
+            self.expect_symbol(")")?;
+            rows.push(row);
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        let on_conflict = if self.eat_keyword("ON") {
+            self.expect_keyword("CONFLICT")?;
+            let target = self.identifier_list()?;
+            self.expect_keyword("DO")?;
+            if self.eat_keyword("NOTHING") {
+                Some((target, OnConflict::Nothing))
+            } else {
+                self.expect_keyword("UPDATE")?;
+                self.expect_keyword("SET")?;
+                Some((target, OnConflict::Update(self.assignments()?)))
+            }
+        } else {
+            None
+        };
+
+        Ok(Statement::Insert {
+            table,
+            columns,
+            rows,
+            on_conflict,
+        })
+    }
+
+    fn assignments(&mut self) -> Result<Vec<(String, Expr)>, Error> {
+        let mut assignments = vec![];
+        loop {
+            let column = self.identifier()?;
+            self.expect_symbol("=")?;
+            assignments.push((column, self.expr()?));
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        Ok(assignments)
+    }
+
+    fn filter(&mut self) -> Result<Option<Expr>, Error> {
+        if self.eat_keyword("WHERE") {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn table_ref(&mut self) -> Result<TableRef, Error> {
+        if self.peek() == Some(&Token::Symbol("(")) {
+            return Err(Error::Unsupported("subquery in FROM".to_string()));
+        }
+
+        let name = self.table_name()?;
+        let alias = if self.eat_keyword("AS") {
+            Some(self.identifier()?)
+        } else {
+            self.identifier().ok()
+        };
+
+        Ok(TableRef { name, alias })
+    }
+
+    fn select(&mut self) -> Result<Select, Error> {
+        let distinct = self.eat_keyword("DISTINCT");
+        let mut items = vec![];
+        loop {
+            items.push(self.select_item()?);
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        self.expect_keyword("FROM")?;
+        let from = self.table_ref()?;
+
+        let mut joins = vec![];
+        loop {
+            let kind = if self.eat_keyword("INNER") || self.is_keyword("JOIN") {
+                JoinKind::Inner
+            } else if self.eat_keyword("LEFT") {
+                self.eat_keyword("OUTER");
+                JoinKind::Left
+            } else if self.eat_keyword("CROSS") {
+                JoinKind::Cross
+            } else if self.is_keyword("RIGHT") || self.is_keyword("FULL") {
+                return Err(Error::Unsupported("RIGHT and FULL OUTER JOIN".to_string()));
+            } else {
+                break;
+            };
+            self.expect_keyword("JOIN")?;
+
+            let table = self.table_ref()?;
+            let on = if self.eat_keyword("ON") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            joins.push(Join { kind, table, on });
+        }
+
+        let filter = self.filter()?;
+
+        let mut order_by = vec![];
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let expr = self.expr()?;
+                let descending = if self.eat_keyword("DESC") {
+                    true
+                } else {
+                    self.eat_keyword("ASC");
+                    false
+                };
+                order_by.push((expr, descending));
+
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let limit = if self.eat_keyword("LIMIT") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let offset = if self.eat_keyword("OFFSET") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        Ok(Select {
+            distinct,
+            items,
+            from,
+            joins,
+            filter,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn select_item(&mut self) -> Result<SelectItem, Error> {
+        if self.eat_symbol("*") {
+            return Ok(SelectItem::Wildcard);
+        }
+        if matches!(self.peek(), Some(Token::Word(_)) | Some(Token::Quoted(_)))
+            && self.peek_at(1) == Some(&Token::Symbol("."))
+            && self.peek_at(2) == Some(&Token::Symbol("*"))
+        {
+            let table = self.identifier()?;
+            self.pos += 2;
+            return Ok(SelectItem::QualifiedWildcard(table));
+        }
+
+        let start = self.tokens.get(self.pos).map_or(0, |(_, start, _)| *start);
+        let expr = self.expr()?;
+        let end = self.tokens[self.pos - 1].2;
+
+        let name = if self.eat_keyword("AS") {
+            self.identifier()?
+        } else if let Ok(alias) = self.identifier() {
+            alias
+        } else if let Expr::Column(_, column) = &expr {
+            column.clone()
+        } else {
+            self.sql[start..end].to_string()
+        };
+
+        Ok(SelectItem::Expr(expr, name))
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.and_expr()?;
+        while self.eat_keyword("OR") {
+            lhs = Expr::Binary(Box::new(lhs), BinaryOp::Or, Box::new(self.and_expr()?));
+        }
+
+        Ok(lhs)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.not_expr()?;
+        while self.eat_keyword("AND") {
+            lhs = Expr::Binary(Box::new(lhs), BinaryOp::And, Box::new(self.not_expr()?));
+        }
+
+        Ok(lhs)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr, Error> {
+        if self.eat_keyword("NOT") {
+            Ok(Expr::Unary(UnaryOp::Not, Box::new(self.not_expr()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.additive()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("=")) | Some(Token::Symbol("==")) => Some(BinaryOp::Eq),
+                Some(Token::Symbol("<>")) | Some(Token::Symbol("!=")) => Some(BinaryOp::NotEq),
+                Some(Token::Symbol("<")) => Some(BinaryOp::Lt),
+                Some(Token::Symbol("<=")) => Some(BinaryOp::LtEq),
+                Some(Token::Symbol(">")) => Some(BinaryOp::Gt),
+                Some(Token::Symbol(">=")) => Some(BinaryOp::GtEq),
+                _ => None,
+            };
+            if let Some(op) = op {
+                self.pos += 1;
+                lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.additive()?));
+                continue;
+            }
+
+            if self.eat_keyword("IS") {
+                let negated = self.eat_keyword("NOT");
+                self.expect_keyword("NULL")?;
+                lhs = Expr::IsNull(Box::new(lhs), negated);
+                continue;
+            }
+
+            let negated = self.is_keyword("NOT")
+                && matches!(self.peek_at(1), Some(Token::Word(w)) if ["IN", "LIKE", "BETWEEN"].iter().any(|k| w.eq_ignore_ascii_case(k)));
+            if negated {
+                self.pos += 1;
+            }
+
+            if self.eat_keyword("IN") {
+                self.expect_symbol("(")?;
+                if self.is_keyword("SELECT") {
+                    return Err(Error::Unsupported("subquery in IN".to_string()));
+                }
+                let mut list = vec![];
+                if !self.eat_symbol(")") {
+                    loop {
+                        list.push(self.expr()?);
+                        if !self.eat_symbol(",") {
+                            break;
+                        }
+                    }
+                    self.expect_symbol(")")?;
+                }
+                lhs = Expr::InList(Box::new(lhs), list, negated);
+            } else if self.eat_keyword("LIKE") {
+                lhs = Expr::Like(Box::new(lhs), Box::new(self.additive()?), negated);
+            } else if self.eat_keyword("BETWEEN") {
+                let low = self.additive()?;
+                self.expect_keyword("AND")?;
+                let high = self.additive()?;
+                lhs = Expr::Between(Box::new(lhs), Box::new(low), Box::new(high), negated);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("+")) => BinaryOp::Add,
+                Some(Token::Symbol("-")) => BinaryOp::Sub,
+                Some(Token::Symbol("||")) => BinaryOp::Concat,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("*")) => BinaryOp::Mul,
+                Some(Token::Symbol("/")) => BinaryOp::Div,
+                Some(Token::Symbol("%")) => BinaryOp::Mod,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if self.eat_symbol("-") {
+            Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?)))
+        } else {
+            self.eat_symbol("+");
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return self.error("an expression"),
+        };
+
+        match token {
+            Token::Int(v) => {
+                self.pos += 1;
+                Ok(Expr::Literal(MemoryValue::Integer(v)))
+            }
+            Token::Float(v) => {
+                self.pos += 1;
+                Ok(Expr::Literal(MemoryValue::Real(v)))
+            }
+            Token::Str(v) => {
+                self.pos += 1;
+                Ok(Expr::Literal(MemoryValue::Text(v)))
+            }
+            Token::Param(name) => {
+                self.pos += 1;
+                Ok(Expr::Param(name))
+            }
+            Token::Symbol("(") => {
+                self.pos += 1;
+                if self.is_keyword("SELECT") {
+                    return Err(Error::Unsupported("subquery".to_string()));
+                }
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Token::Word(w) if w.eq_ignore_ascii_case("NULL") => {
+                self.pos += 1;
+                Ok(Expr::Literal(MemoryValue::Null))
+            }
+            Token::Word(w) if w.eq_ignore_ascii_case("TRUE") || w.eq_ignore_ascii_case("FALSE") => {
+                self.pos += 1;
+                Ok(Expr::Literal(MemoryValue::Integer(
+                    w.eq_ignore_ascii_case("TRUE") as i64,
+                )))
+            }
+            Token::Word(w)
+                if ["EXISTS", "CASE", "CAST"]
+                    .iter()
+                    .any(|k| w.eq_ignore_ascii_case(k)) =>
+            {
+                Err(Error::Unsupported(w.to_ascii_uppercase()))
+            }
+            Token::Word(_) | Token::Quoted(_) => {
+                let name = self.identifier()?;
+
+                if self.eat_symbol("(") {
+                    let mut args = vec![];
+                    if self.eat_symbol("*") {
+                        args.push(Expr::Literal(MemoryValue::Integer(1)));
+                        self.expect_symbol(")")?;
+                    } else if !self.eat_symbol(")") {
+                        loop {
+                            args.push(self.expr()?);
+                            if !self.eat_symbol(",") {
+                                break;
+                            }
+                        }
+                        self.expect_symbol(")")?;
+                    }
+                    Ok(Expr::Function(name.to_ascii_uppercase(), args))
+                } else {
+                    // the last part is the column, and the rest is a possibly qualified table
+                    let mut parts = vec![name];
+                    while self.eat_symbol(".") {
+                        parts.push(self.identifier()?);
+                    }
+                    let column = parts.pop().unwrap();
+
+                    Ok(Expr::Column(
+                        if parts.is_empty() {
+                            None
+                        } else {
+                            Some(parts.join("."))
+                        },
+                        column,
+                    ))
+                }
+            }
+            _ => self.error("an expression"),
+        }
+    }
+}
+
+fn symbol_of(symbol: &str) -> &'static str {
+    SYMBOLS
+        .iter()
+        .find(|s| **s == symbol)
+        .expect("symbol_of: unknown symbol")
+}
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED.iter().any(|k| word.eq_ignore_ascii_case(k))
+}
+
+#[test]
+fn parse_generated_queries() {
+    assert_eq!(
+        parse(
+            r#"CREATE TABLE IF NOT EXISTS "user" ("id" BIGINT GENERATED BY DEFAULT AS IDENTITY, "name" VARCHAR(50) UNIQUE NOT NULL, CONSTRAINT "user_pkey" PRIMARY KEY("id"))"#
+        ),
+        Ok(Statement::CreateTable {
+            name: "user".to_string(),
+            if_not_exists: true,
+            columns: vec![
+                ColumnDef {
+                    name: "id".to_string(),
+                    unique: false,
+                    not_null: false,
+                    auto_increment: true,
+                },
+                ColumnDef {
+                    name: "name".to_string(),
+                    unique: true,
+                    not_null: true,
+                    auto_increment: false,
+                },
+            ],
+            primary_key: vec!["id".to_string()],
+        })
+    );
+
+    assert_eq!(
+        parse("SELECT u.name AS n, COUNT(*) FROM users AS u LEFT JOIN items i ON u.id = i.user_id WHERE u.age >= :age AND NOT u.id IN (1, 2) ORDER BY n DESC LIMIT 10 OFFSET 5"),
+        Ok(Statement::Select(Select {
+            distinct: false,
+            items: vec![
+                SelectItem::Expr(
+                    Expr::Column(Some("u".to_string()), "name".to_string()),
+                    "n".to_string()
+                ),
+                SelectItem::Expr(
+                    Expr::Function(
+                        "COUNT".to_string(),
+                        vec![Expr::Literal(MemoryValue::Integer(1))]
+                    ),
+                    "COUNT(*)".to_string()
+                ),
+            ],
+            from: TableRef {
+                name: "users".to_string(),
+                alias: Some("u".to_string()),
+            },
+            joins: vec![Join {
+                kind: JoinKind::Left,
+                table: TableRef {
+                    name: "items".to_string(),
+                    alias: Some("i".to_string()),
+                },
+                on: Some(Expr::Binary(
+                    Box::new(Expr::Column(Some("u".to_string()), "id".to_string())),
+                    BinaryOp::Eq,
+                    Box::new(Expr::Column(Some("i".to_string()), "user_id".to_string())),
+                )),
+            }],
+            filter: Some(Expr::Binary(
+                Box::new(Expr::Binary(
+                    Box::new(Expr::Column(Some("u".to_string()), "age".to_string())),
+                    BinaryOp::GtEq,
+                    Box::new(Expr::Param("age".to_string())),
+                )),
+                BinaryOp::And,
+                Box::new(Expr::Unary(
+                    UnaryOp::Not,
+                    Box::new(Expr::InList(
+                        Box::new(Expr::Column(Some("u".to_string()), "id".to_string())),
+                        vec![
+                            Expr::Literal(MemoryValue::Integer(1)),
+                            Expr::Literal(MemoryValue::Integer(2)),
+                        ],
+                        false,
+                    )),
+                )),
+            )),
+            order_by: vec![(Expr::Column(None, "n".to_string()), true)],
+            limit: Some(Expr::Literal(MemoryValue::Integer(10))),
+            offset: Some(Expr::Literal(MemoryValue::Integer(5))),
+        }))
+    );
+
+    assert_eq!(
+        parse("SELECT * FROM t UNION SELECT * FROM u"),
+        Err(Error::Unsupported("UNION".to_string()))
+    );
+    assert_eq!(
+        parse("SELECT * FROM t WHERE id IN (SELECT id FROM u)"),
+        Err(Error::Unsupported("subquery in IN".to_string()))
+    );
+    assert!(matches!(
+        parse("SELECT * FROM t WHERE"),
+        Err(Error::SyntaxError(_))
+    ));
+}
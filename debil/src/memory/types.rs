@@ -0,0 +1,222 @@
+use std::marker::PhantomData;
+
+use crate::{SqlLiteral, SqlNull, SqlValue};
+
+/// A value of the memory backend, which has the storage classes of SQLite.
+/// Booleans are stored as 0 and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MemoryValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl MemoryValue {
+    // Order of SQLite: NULL, then numbers, then texts, then blobs.
+    // Returns None when either is NULL, where a comparison is unknown.
+    pub(crate) fn compare(&self, other: &MemoryValue) -> Option<std::cmp::Ordering> {
+        use MemoryValue::*;
+
+        fn class(v: &MemoryValue) -> u8 {
+            match v {
+                Null => 0,
+                Integer(_) | Real(_) => 1,
+                Text(_) => 2,
+                Blob(_) => 3,
+            }
+        }
+
+        match (self, other) {
+            (Null, _) | (_, Null) => None,
+            (Integer(a), Integer(b)) => Some(a.cmp(b)),
+            (Integer(a), Real(b)) => (*a as f64).partial_cmp(b),
+            (Real(a), Integer(b)) => a.partial_cmp(&(*b as f64)),
+            (Real(a), Real(b)) => a.partial_cmp(b),
+            (Text(a), Text(b)) => Some(a.cmp(b)),
+            (Blob(a), Blob(b)) => Some(a.cmp(b)),
+            (a, b) => Some(class(a).cmp(&class(b))),
+        }
+    }
+
+    // Ordering for ORDER BY, where NULL comes first
+    pub(crate) fn sort_order(&self, other: &MemoryValue) -> std::cmp::Ordering {
+        match (self, other) {
+            (MemoryValue::Null, MemoryValue::Null) => std::cmp::Ordering::Equal,
+            (MemoryValue::Null, _) => std::cmp::Ordering::Less,
+            (_, MemoryValue::Null) => std::cmp::Ordering::Greater,
+            (a, b) => a.compare(b).unwrap_or(std::cmp::Ordering::Equal),
+        }
+    }
+}
+
+/// A value type which `MemoryConn` can store. Values are converted to `MemoryValue` where they
+/// are compared or computed, and back where they are stored or returned, so the conversion
+/// must not lose information.
+pub trait AsMemoryValue: Clone + Sync + Send + 'static {
+    fn to_memory_value(&self) -> MemoryValue;
+
+    fn from_memory_value(value: MemoryValue) -> Self;
+}
+
+impl AsMemoryValue for MemoryValue {
+    fn to_memory_value(&self) -> MemoryValue {
+        self.clone()
+    }
+
+    fn from_memory_value(value: MemoryValue) -> Self {
+        value
+    }
+}
+
+impl SqlNull for MemoryValue {
+    fn is_null(&self) -> bool {
+        *self == MemoryValue::Null
+    }
+}
+
+impl SqlLiteral for MemoryValue {
+    fn to_sql_literal(&self) -> String {
+        match self {
+            MemoryValue::Null => "NULL".to_string(),
+            MemoryValue::Integer(v) => v.to_string(),
+            MemoryValue::Real(v) => v.to_string(),
+            MemoryValue::Text(v) => crate::types::quote_string(v),
+            MemoryValue::Blob(v) => format!(
+                "X'{}'",
+                v.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+            ),
+        }
+    }
+}
+
+impl SqlValue<bool> for MemoryValue {
+    fn column_type(_: PhantomData<bool>, _size: i32) -> String {
+        "BOOLEAN".to_string()
+    }
+
+    fn serialize(val: bool) -> Self {
+        MemoryValue::Integer(val as i64)
+    }
+
+    fn deserialize(self) -> bool {
+        match self {
+            MemoryValue::Integer(v) => v != 0,
+            _ => panic!("MemoryValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<i32> for MemoryValue {
+    fn column_type(_: PhantomData<i32>, _size: i32) -> String {
+        "INTEGER".to_string()
+    }
+
+    fn serialize(val: i32) -> Self {
+        MemoryValue::Integer(val as i64)
+    }
+
+    fn deserialize(self) -> i32 {
+        match self {
+            MemoryValue::Integer(v) => v as i32,
+            _ => panic!("MemoryValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<i64> for MemoryValue {
+    fn column_type(_: PhantomData<i64>, _size: i32) -> String {
+        "BIGINT".to_string()
+    }
+
+    fn serialize(val: i64) -> Self {
+        MemoryValue::Integer(val)
+    }
+
+    fn deserialize(self) -> i64 {
+        match self {
+            MemoryValue::Integer(v) => v,
+            _ => panic!("MemoryValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<f64> for MemoryValue {
+    fn column_type(_: PhantomData<f64>, _size: i32) -> String {
+        "REAL".to_string()
+    }
+
+    fn serialize(val: f64) -> Self {
+        MemoryValue::Real(val)
+    }
+
+    fn deserialize(self) -> f64 {
+        match self {
+            MemoryValue::Real(v) => v,
+            MemoryValue::Integer(v) => v as f64,
+            _ => panic!("MemoryValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<String> for MemoryValue {
+    fn column_type(_: PhantomData<String>, size: i32) -> String {
+        if size > 0 {
+            format!("VARCHAR({})", size)
+        } else {
+            "TEXT".to_string()
+        }
+    }
+
+    fn serialize(val: String) -> Self {
+        MemoryValue::Text(val)
+    }
+
+    fn deserialize(self) -> String {
+        match self {
+            MemoryValue::Text(v) => v,
+            _ => panic!("MemoryValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<Vec<u8>> for MemoryValue {
+    fn column_type(_: PhantomData<Vec<u8>>, _size: i32) -> String {
+        "BLOB".to_string()
+    }
+
+    fn serialize(val: Vec<u8>) -> Self {
+        MemoryValue::Blob(val)
+    }
+
+    fn deserialize(self) -> Vec<u8> {
+        match self {
+            MemoryValue::Blob(v) => v,
+            _ => panic!("MemoryValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl<V> SqlValue<Option<V>> for MemoryValue
+where
+    MemoryValue: SqlValue<V>,
+{
+    fn column_type(_: PhantomData<Option<V>>, size: i32) -> String {
+        <MemoryValue as SqlValue<V>>::column_type(PhantomData::<V>, size)
+    }
+
+    fn serialize(val: Option<V>) -> Self {
+        match val {
+            None => MemoryValue::Null,
+            Some(v) => SqlValue::serialize(v),
+        }
+    }
+
+    fn deserialize(self) -> Option<V> {
+        match self {
+            MemoryValue::Null => None,
+            _ => Some(SqlValue::deserialize(self)),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// A connection without a database, for unit tests of code written against `SqlConn`.
+// A pattern of `MockConn` matches a query which contains it, ignoring differences of whitespace.
+use crate::{Dialect, GenericDialect, HasNotFound, Params, Row, SqlConn, SqlMapper, SqlTable};
+use async_trait::async_trait;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    NotFoundError,
+    /// An error scripted with `MockConn::fails`
+    Scripted(String),
+    Unsupported(crate::Unsupported),
+}
+
+impl HasNotFound for Error {
+    fn not_found() -> Self {
+        Error::NotFoundError
+    }
+
+    fn unsupported(err: crate::Unsupported) -> Self {
+        Error::Unsupported(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFoundError => write!(f, "record not found"),
+            Error::Scripted(message) => write!(f, "{}", message),
+            Error::Unsupported(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A query run on a `MockConn`. Transactions are recorded as `BEGIN`, `COMMIT` and `ROLLBACK`,
+/// and a batch as one query per params.
+#[derive(Clone, Debug)]
+pub struct Executed<V> {
+    pub query: String,
+    pub params: Vec<(String, V)>,
+}
+
+impl<V> Executed<V> {
+    /// Value bound to `name`, which may be written with or without the leading colon
+    pub fn param(&self, name: &str) -> Option<&V> {
+        let name = name.trim_start_matches(':');
+
+        self.params
+            .iter()
+            .find(|(k, _)| k.trim_start_matches(':') == name)
+            .map(|(_, v)| v)
+    }
+}
+
+enum Response<V> {
+    Rows(Vec<Row<V>>),
+    Affected(u64),
+    Fail(String),
+}
+
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn matches(query: &str, pattern: &str) -> bool {
+    normalize(query).contains(&normalize(pattern))
+}
+
+/// A `SqlConn` which records queries instead of running them.
+///
+/// Queries without a scripted response succeed with no rows and 0 affected rows.
+/// When several responses match a query, the one scripted first wins.
+pub struct MockConn<V> {
+    dialect: Box<dyn Dialect>,
+    responses: Vec<(String, Response<V>)>,
+    executed: Vec<Executed<V>>,
+}
+
+impl<V> Default for MockConn<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> MockConn<V> {
+    pub fn new() -> Self {
+        MockConn {
+            dialect: Box::new(GenericDialect),
+            responses: vec![],
+            executed: vec![],
+        }
+    }
+
+    /// Dialect which queries are generated in, `GenericDialect` by default
+    pub fn with_dialect(mut self, dialect: impl Dialect + 'static) -> Self {
+        self.dialect = Box::new(dialect);
+
+        self
+    }
+
+    /// Answers queries matching `pattern` with the rows
+    pub fn returns(mut self, pattern: impl Into<String>, rows: Vec<Row<V>>) -> Self {
+        self.responses.push((pattern.into(), Response::Rows(rows)));
+
+        self
+    }
+
+    /// Answers queries matching `pattern` with the records, as if they were selected from a table
+    pub fn returns_records<T: SqlTable<ValueType = V>>(
+        self,
+        pattern: impl Into<String>,
+        records: Vec<T>,
+    ) -> Self {
+        let rows = records
+            .into_iter()
+            .map(|record| {
+                let (columns, values): (Vec<_>, Vec<_>) = record.map_to_sql().into_iter().unzip();
+                Row::new(columns.into(), values)
+            })
+            .collect();
+
+        self.returns(pattern, rows)
+    }
+
+    /// Number of rows which statements matching `pattern` report as affected
+    pub fn affects(mut self, pattern: impl Into<String>, rows: u64) -> Self {
+        self.responses
+            .push((pattern.into(), Response::Affected(rows)));
+
+        self
+    }
+
+    /// Fails queries matching `pattern` with `Error::Scripted`
+    pub fn fails(mut self, pattern: impl Into<String>, message: impl Into<String>) -> Self {
+        self.responses
+            .push((pattern.into(), Response::Fail(message.into())));
+
+        self
+    }
+
+    /// Queries run so far, in order
+    pub fn executed(&self) -> &[Executed<V>] {
+        &self.executed
+    }
+
+    pub fn executed_matching(&self, pattern: &str) -> Vec<&Executed<V>> {
+        self.executed
+            .iter()
+            .filter(|e| matches(&e.query, pattern))
+            .collect()
+    }
+
+    /// Forgets the queries run so far, keeping the scripted responses
+    pub fn clear(&mut self) {
+        self.executed.clear();
+    }
+
+    /// Panics unless a query matching `pattern` has been run
+    pub fn assert_executed(&self, pattern: &str) {
+        assert!(
+            !self.executed_matching(pattern).is_empty(),
+            "no query matching `{}` was executed; executed queries:\n{}",
+            pattern,
+            self.executed_queries()
+        );
+    }
+
+    /// Panics unless queries matching `pattern` have been run exactly `times` times
+    pub fn assert_executed_times(&self, pattern: &str, times: usize) {
+        let count = self.executed_matching(pattern).len();
+        assert_eq!(
+            count,
+            times,
+            "a query matching `{}` was executed {} times instead of {}; executed queries:\n{}",
+            pattern,
+            count,
+            times,
+            self.executed_queries()
+        );
+    }
+
+    pub fn assert_not_executed(&self, pattern: &str) {
+        self.assert_executed_times(pattern, 0);
+    }
+
+    fn executed_queries(&self) -> String {
+        self.executed
+            .iter()
+            .map(|e| format!("  {}", e.query))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn record(&mut self, query: String, params: Params<V>) -> Option<&Response<V>> {
+        let response = self
+            .responses
+            .iter()
+            .find(|(pattern, _)| matches(&query, pattern))
+            .map(|(_, response)| response);
+        self.executed.push(Executed {
+            query,
+            params: params.0,
+        });
+
+        response
+    }
+}
+
+#[async_trait]
+impl<V: 'static + Sync + Send + Clone> SqlConn<V> for MockConn<V> {
+    type Error = Error;
+
+    fn dialect(&self) -> &dyn Dialect {
+        self.dialect.as_ref()
+    }
+
+    async fn sql_exec(&mut self, query: String, params: Params<V>) -> Result<u64, Error> {
+        match self.record(query, params) {
+            Some(Response::Affected(rows)) => Ok(*rows),
+            Some(Response::Rows(rows)) => Ok(rows.len() as u64),
+            Some(Response::Fail(message)) => Err(Error::Scripted(message.clone())),
+            None => Ok(0),
+        }
+    }
+
+    async fn sql_query<T: SqlMapper<ValueType = V> + Sync + Send>(
+        &mut self,
+        query: String,
+        params: Params<V>,
+    ) -> Result<Vec<T>, Error> {
+        match self.record(query, params) {
+            Some(Response::Rows(rows)) => Ok(rows.iter().cloned().map(T::map_from_row).collect()),
+            Some(Response::Fail(message)) => Err(Error::Scripted(message.clone())),
+            Some(Response::Affected(_)) | None => Ok(vec![]),
+        }
+    }
+
+    async fn sql_batch_exec(&mut self, query: String, params: Vec<Params<V>>) -> Result<(), Error> {
+        for params in params {
+            self.sql_exec(query.clone(), params).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn start_transaction(&mut self) -> Result<(), Error> {
+        self.sql_exec("BEGIN".to_string(), Params::new()).await?;
+
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), Error> {
+        self.sql_exec("COMMIT".to_string(), Params::new()).await?;
+
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), Error> {
+        self.sql_exec("ROLLBACK".to_string(), Params::new()).await?;
+
+        Ok(())
+    }
+}
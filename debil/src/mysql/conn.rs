@@ -1,9 +1,12 @@
 use crate as debil;
 use crate::conn::SqlConn;
+use crate::hook::{query_span, traced, traced_stream};
 use crate::mysql::error::Error;
 use crate::mysql::types::MySQLValue;
 use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use mysql_async::prelude::*;
+use std::sync::Arc;
 
 pub struct DebilConn {
     conn: mysql_async::Conn,
@@ -13,10 +16,14 @@ impl debil::HasNotFound for Error {
     fn not_found() -> Self {
         Error::NotFoundError
     }
+
+    fn unsupported(err: debil::Unsupported) -> Self {
+        Error::Unsupported(err)
+    }
 }
 
 fn to_params(params: debil::Params<MySQLValue>) -> params::Params {
-    if params.0.len() == 0 {
+    if params.0.is_empty() {
         params::Params::Empty
     } else {
         params
@@ -28,48 +35,118 @@ fn to_params(params: debil::Params<MySQLValue>) -> params::Params {
     }
 }
 
+// Maps a row with the column names, which are computed from the first row of a result
+// and shared by the rest of them
+fn map_row<T: debil::SqlMapper<ValueType = MySQLValue>>(
+    columns: &mut Option<Arc<[String]>>,
+    row: mysql_async::Row,
+) -> T {
+    let columns = columns
+        .get_or_insert_with(|| {
+            row.columns_ref()
+                .iter()
+                .map(|c| c.name_str().into_owned())
+                .collect()
+        })
+        .clone();
+    let values = row.unwrap().into_iter().map(MySQLValue).collect::<Vec<_>>();
+
+    debil::map_from_row::<T>(debil::Row::new(columns, values))
+}
+
 #[async_trait]
 impl debil::SqlConn<MySQLValue> for DebilConn {
     type Error = Error;
 
+    fn dialect(&self) -> &dyn debil::Dialect {
+        &debil::MySqlDialect
+    }
+
     async fn sql_exec(
         &mut self,
         query: String,
         params: debil::Params<MySQLValue>,
     ) -> Result<u64, Error> {
-        self.conn
-            .exec_drop(query.as_str(), to_params(params))
-            .await?;
+        traced(
+            query_span("mysql", &query),
+            self.conn.exec_drop(query.as_str(), to_params(params)),
+        )
+        .await?;
 
         Ok(self.conn.affected_rows())
     }
 
+    // mysql_async keeps prepared statements in the LRU cache of the connection, whose capacity
+    // is `stmt_cache_size` of the connection options
+    async fn prepare(&mut self, query: String) -> Result<debil::Statement, Self::Error> {
+        self.conn.prep(query.as_str()).await?;
+
+        Ok(debil::Statement::new(query))
+    }
+
     async fn sql_query<T: debil::SqlMapper<ValueType = MySQLValue> + Sync + Send>(
         &mut self,
         query: String,
         params: debil::Params<MySQLValue>,
     ) -> Result<Vec<T>, Self::Error> {
-        let result = self.conn.exec(query.as_str(), to_params(params)).await?;
-        let vs = result
+        let result: Vec<mysql_async::Row> = traced(
+            query_span("mysql", &query),
+            self.conn.exec(query.as_str(), to_params(params)),
+        )
+        .await?;
+
+        let mut columns = None;
+
+        Ok(result
             .into_iter()
-            .map(|row: mysql_async::Row| {
-                let column_names = row
-                    .columns_ref()
-                    .iter()
-                    .map(|c| c.name_str().into_owned())
-                    .collect::<Vec<_>>();
-                let values = row.unwrap().into_iter().map(MySQLValue).collect::<Vec<_>>();
-
-                debil::map_from_sql::<T>(
-                    column_names
-                        .into_iter()
-                        .zip(values)
-                        .collect::<std::collections::HashMap<_, _>>(),
+            .map(|row| map_row::<T>(&mut columns, row))
+            .collect())
+    }
+
+    // Rows are read from the server as the stream is polled
+    fn sql_query_stream<'a, T: debil::SqlMapper<ValueType = MySQLValue> + Sync + Send + 'a>(
+        &'a mut self,
+        query: String,
+        params: debil::Params<MySQLValue>,
+    ) -> BoxStream<'a, Result<T, Self::Error>> {
+        let span = query_span("mysql", &query);
+        let rows = futures::stream::once(self.conn.exec_iter(query, to_params(params)))
+            .map_ok(|result| {
+                futures::stream::try_unfold(
+                    (result, None),
+                    |(mut result, mut columns)| async move {
+                        Ok(result
+                            .next()
+                            .await?
+                            .map(|row| (map_row::<T>(&mut columns, row), (result, columns))))
+                    },
                 )
             })
-            .collect();
+            .map_err(Error::from)
+            .try_flatten()
+            .boxed();
+
+        traced_stream(span, rows)
+    }
 
-        Ok(vs)
+    async fn start_transaction(&mut self) -> Result<(), Self::Error> {
+        self.conn
+            .query_drop("START TRANSACTION".to_string())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        self.conn.query_drop("COMMIT".to_string()).await?;
+
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), Self::Error> {
+        self.conn.query_drop("ROLLBACK".to_string()).await?;
+
+        Ok(())
     }
 
     async fn sql_batch_exec(
@@ -77,17 +154,67 @@ impl debil::SqlConn<MySQLValue> for DebilConn {
         query: String,
         params_vec: Vec<debil::Params<MySQLValue>>,
     ) -> Result<(), Self::Error> {
-        self.conn
-            .exec_batch(
+        traced(
+            query_span("mysql", &query),
+            self.conn.exec_batch(
                 query.as_str(),
                 params_vec.into_iter().map(to_params).collect::<Vec<_>>(),
-            )
-            .await?;
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Opens a connection to the server per pooled connection, with the statement cache size
+/// of the `PoolConfig`. `Pool::close` disconnects the idle connections gracefully.
+pub struct ConnectionManager {
+    opts: mysql_async::Opts,
+}
+
+#[async_trait]
+impl debil::ManageConnection for ConnectionManager {
+    type Connection = DebilConn;
+    type Error = Error;
+
+    async fn connect(&self) -> Result<DebilConn, Error> {
+        Ok(DebilConn::from_conn(
+            mysql_async::Conn::new(self.opts.clone()).await?,
+        ))
+    }
+
+    async fn is_valid(&self, mut conn: DebilConn) -> Result<DebilConn, Error> {
+        conn.conn.ping().await?;
+
+        Ok(conn)
+    }
+
+    async fn disconnect(&self, conn: DebilConn) -> Result<(), Error> {
+        conn.conn.disconnect().await?;
 
         Ok(())
     }
 }
 
+pub type DebilPool = debil::Pool<ConnectionManager>;
+
+impl debil::Pool<ConnectionManager> {
+    pub fn from_opts(opts: mysql_async::Opts, config: debil::PoolConfig) -> DebilPool {
+        let mut builder = mysql_async::OptsBuilder::from_opts(opts);
+        if let Some(size) = config.statement_cache_size {
+            builder = builder.stmt_cache_size(size);
+        }
+
+        debil::Pool::new(
+            ConnectionManager {
+                opts: builder.into(),
+            },
+            config,
+        )
+    }
+}
+
 impl DebilConn {
     pub fn as_conn(self) -> mysql_async::Conn {
         self.conn
@@ -114,7 +241,10 @@ impl DebilConn {
         self.sql_exec(
             format!(
                 "DROP TABLE IF EXISTS {}",
-                debil::SqlTable::table_name(std::marker::PhantomData::<T>),
+                debil::Dialect::quote_qualified(
+                    &debil::MySqlDialect,
+                    &debil::SqlTable::table_name(std::marker::PhantomData::<T>)
+                ),
             ),
             debil::Params::<MySQLValue>::new(),
         )
@@ -129,6 +259,7 @@ impl DebilConn {
         self.create_table::<T>().await?;
 
         let table_name = debil::SqlTable::table_name(std::marker::PhantomData::<T>);
+        let quoted_table_name = debil::Dialect::quote_qualified(&debil::MySqlDialect, &table_name);
         let schema = debil::SqlTable::schema_of(std::marker::PhantomData::<T>);
 
         for (column_name, column_type, attr) in schema {
@@ -141,8 +272,13 @@ impl DebilConn {
                 self.sql_exec(
                     format!(
                         "ALTER TABLE {} ADD COLUMN {}",
-                        table_name,
-                        debil::create_column_query(column_name, column_type, attr)
+                        quoted_table_name,
+                        debil::create_column_query_for(
+                            &debil::MySqlDialect,
+                            column_name,
+                            column_type,
+                            attr
+                        )
                     ),
                     debil::Params::<MySQLValue>::new(),
                 )
@@ -155,8 +291,13 @@ impl DebilConn {
                 self.sql_exec(
                     format!(
                         "ALTER TABLE {} MODIFY COLUMN {}",
-                        table_name,
-                        debil::create_column_query(column_name, column_type, attr)
+                        quoted_table_name,
+                        debil::create_column_query_for(
+                            &debil::MySqlDialect,
+                            column_name,
+                            column_type,
+                            attr
+                        )
                     ),
                     debil::Params::<MySQLValue>::new(),
                 )
@@ -166,44 +307,4 @@ impl DebilConn {
 
         Ok(())
     }
-
-    pub async fn create_all<T: debil::SqlTable<ValueType = MySQLValue> + Clone>(
-        &mut self,
-        datas: Vec<T>,
-    ) -> Result<(), Error> {
-        if datas.len() == 0 {
-            return Ok(());
-        }
-
-        let (query, _) = datas[0].clone().insert_query_with_params();
-        let mut parameters = Vec::new();
-        for data in datas {
-            let (_, ps) = data.insert_query_with_params();
-            parameters.push(debil::Params(ps));
-        }
-
-        self.sql_batch_exec(query, parameters).await?;
-
-        Ok(())
-    }
-
-    pub async fn start_transaction(&mut self) -> Result<(), Error> {
-        self.conn
-            .query_drop("START TRANSACTION".to_string())
-            .await?;
-
-        Ok(())
-    }
-
-    pub async fn commit(&mut self) -> Result<(), Error> {
-        self.conn.query_drop("COMMIT".to_string()).await?;
-
-        Ok(())
-    }
-
-    pub async fn rollback(&mut self) -> Result<(), Error> {
-        self.conn.query_drop("ROLLBACK".to_string()).await?;
-
-        Ok(())
-    }
 }
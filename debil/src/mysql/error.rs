@@ -2,6 +2,7 @@
 pub enum Error {
     NotFoundError,
     MySQLError(mysql_async::Error),
+    Unsupported(crate::Unsupported),
 }
 
 impl From<mysql_async::Error> for Error {
@@ -1,12 +1,24 @@
 use std::marker::PhantomData;
 
-use crate::SqlValue;
+use crate::{SqlLiteral, SqlNull, SqlValue};
 
 #[derive(Clone)]
 pub struct MySQLValue(pub mysql_async::Value);
 
+impl SqlNull for MySQLValue {
+    fn is_null(&self) -> bool {
+        self.0 == mysql_async::Value::NULL
+    }
+}
+
+impl SqlLiteral for MySQLValue {
+    fn to_sql_literal(&self) -> String {
+        self.0.as_sql(false)
+    }
+}
+
 impl SqlValue<bool> for MySQLValue {
-    fn column_type(_: PhantomData<bool>, size: i32) -> String {
+    fn column_type(_: PhantomData<bool>, _size: i32) -> String {
         "bool".to_string()
     }
 
@@ -52,7 +64,7 @@ impl SqlValue<i32> for MySQLValue {
 }
 
 impl SqlValue<u32> for MySQLValue {
-    fn column_type(_: PhantomData<u32>, size: i32) -> String {
+    fn column_type(_: PhantomData<u32>, _size: i32) -> String {
         "int unsigned".to_string()
     }
 
@@ -80,7 +92,7 @@ impl SqlValue<i64> for MySQLValue {
 }
 
 impl SqlValue<u64> for MySQLValue {
-    fn column_type(_: PhantomData<u64>, size: i32) -> String {
+    fn column_type(_: PhantomData<u64>, _size: i32) -> String {
         "bigint unsigned".to_string()
     }
 
@@ -0,0 +1,278 @@
+use crate::{Dialect, Params};
+
+// Scanner for `:name` placeholders in a query string.
+// String literals, quoted identifiers, comments and `::` casts are copied as they are.
+pub(crate) fn replace_placeholders(
+    dialect: &dyn Dialect,
+    query: &str,
+    mut f: impl FnMut(&str) -> String,
+) -> String {
+    let backslash_escapes = dialect.backslash_escapes();
+    let chars = query.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(query.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            quote @ ('\'' | '"' | '`') => {
+                let start = i;
+                i += 1;
+                while i < chars.len() {
+                    if backslash_escapes && chars[i] == '\\' && quote == '\'' {
+                        i += 2;
+                        continue;
+                    }
+                    if chars[i] == quote {
+                        // doubled quote is an escaped quote
+                        if i + 1 < chars.len() && chars[i + 1] == quote {
+                            i += 2;
+                            continue;
+                        }
+                        break;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+                result.extend(&chars[start..i]);
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                let start = i;
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                result.extend(&chars[start..i]);
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let start = i;
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i = (i + 2).min(chars.len());
+                result.extend(&chars[start..i]);
+            }
+            ':' if chars.get(i + 1) == Some(&':') => {
+                result.push_str("::");
+                i += 2;
+            }
+            ':' if chars
+                .get(i + 1)
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') =>
+            {
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let name = chars[start..i].iter().collect::<String>();
+                result.push_str(&f(&name));
+            }
+            c => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaceholderStyle {
+    /// `?`, which takes one value per occurrence
+    Question,
+    /// `$1`, `$2`, ..., where every occurrence of a name refers to the same value
+    Dollar,
+}
+
+/// A placeholder in the query which has no value in the params
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownPlaceholder(pub String);
+
+impl std::fmt::Display for UnknownPlaceholder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no value is bound to the placeholder :{}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPlaceholder {}
+
+fn find_param<'a, V>(params: &'a Params<V>, name: &str) -> Option<&'a V> {
+    params
+        .0
+        .iter()
+        .find(|(k, _)| k.trim_start_matches(':') == name)
+        .map(|(_, v)| v)
+}
+
+/// Rewrites `:name` placeholders into positional ones, for drivers which don't take named params.
+/// The returned values are in the order of the positional placeholders.
+pub fn to_positional<V: Clone>(
+    dialect: &dyn Dialect,
+    query: &str,
+    params: &Params<V>,
+    style: PlaceholderStyle,
+) -> Result<(String, Vec<V>), UnknownPlaceholder> {
+    let mut values = Vec::new();
+    let mut names = Vec::<String>::new();
+    let mut unknown = None;
+
+    let query = replace_placeholders(dialect, query, |name| {
+        let value = match find_param(params, name) {
+            Some(value) => value,
+            None => {
+                unknown.get_or_insert_with(|| UnknownPlaceholder(name.to_string()));
+                return format!(":{}", name);
+            }
+        };
+
+        match style {
+            PlaceholderStyle::Question => {
+                values.push(value.clone());
+                "?".to_string()
+            }
+            PlaceholderStyle::Dollar => {
+                let index = match names.iter().position(|n| n == name) {
+                    Some(index) => index,
+                    None => {
+                        names.push(name.to_string());
+                        values.push(value.clone());
+                        names.len() - 1
+                    }
+                };
+                format!("${}", index + 1)
+            }
+        }
+    });
+
+    match unknown {
+        Some(err) => Err(err),
+        None => Ok((query, values)),
+    }
+}
+
+/// Replaces `:name` placeholders with their values rendered by `literal`, e.g. for logging.
+/// Placeholders without a value are left as they are.
+pub fn expand_params<V>(
+    dialect: &dyn Dialect,
+    query: &str,
+    params: &Params<V>,
+    literal: impl Fn(&V) -> String,
+) -> String {
+    replace_placeholders(dialect, query, |name| match find_param(params, name) {
+        Some(value) => literal(value),
+        None => format!(":{}", name),
+    })
+}
+
+#[test]
+fn replace_placeholders_skips_literals() {
+    use crate::GenericDialect;
+
+    let rename = |name: &str| {
+        if name == "a" {
+            ":b".to_string()
+        } else {
+            format!(":{}", name)
+        }
+    };
+
+    assert_eq!(
+        replace_placeholders(
+            &GenericDialect,
+            "SELECT * FROM t WHERE x = :a AND y = :ab",
+            rename
+        ),
+        "SELECT * FROM t WHERE x = :b AND y = :ab"
+    );
+    assert_eq!(
+        replace_placeholders(
+            &GenericDialect,
+            "SELECT ':a', \":a\", `:a`, 'it''s :a', :a",
+            rename
+        ),
+        "SELECT ':a', \":a\", `:a`, 'it''s :a', :b"
+    );
+    assert_eq!(
+        replace_placeholders(
+            &GenericDialect,
+            "SELECT x::a, :a -- :a\n/* :a */ FROM t",
+            rename
+        ),
+        "SELECT x::a, :b -- :a\n/* :a */ FROM t"
+    );
+}
+
+#[test]
+fn to_positional_placeholders() {
+    use crate::GenericDialect;
+
+    let params = Params(vec![(":a".to_string(), 1), ("b".to_string(), 2)]);
+    let query =
+        "SELECT * FROM t WHERE x = :b AND y IN (:a, :b) AND z = ':a' AND w::text = :a -- :c";
+
+    assert_eq!(
+        to_positional(&GenericDialect, query, &params, PlaceholderStyle::Question),
+        Ok((
+            "SELECT * FROM t WHERE x = ? AND y IN (?, ?) AND z = ':a' AND w::text = ? -- :c"
+                .to_string(),
+            vec![2, 1, 2, 1]
+        ))
+    );
+    assert_eq!(
+        to_positional(&GenericDialect, query, &params, PlaceholderStyle::Dollar),
+        Ok((
+            "SELECT * FROM t WHERE x = $1 AND y IN ($2, $1) AND z = ':a' AND w::text = $2 -- :c"
+                .to_string(),
+            vec![2, 1]
+        ))
+    );
+    assert_eq!(
+        to_positional(
+            &GenericDialect,
+            "SELECT :c",
+            &params,
+            PlaceholderStyle::Question
+        ),
+        Err(UnknownPlaceholder("c".to_string()))
+    );
+    assert_eq!(
+        expand_params(&GenericDialect, "SELECT :a, :b, :c, ':a'", &params, |v| v
+            .to_string()),
+        "SELECT 1, 2, :c, ':a'"
+    );
+}
+
+#[test]
+fn backslash_escapes_only_for_mysql() {
+    use crate::{GenericDialect, MySqlDialect};
+
+    let params = Params(vec![("a".to_string(), 1)]);
+
+    // a backslash is a plain character in standard SQL, so the literal ends right after it
+    assert_eq!(
+        to_positional(
+            &GenericDialect,
+            r"SELECT * FROM t WHERE p = 'C:\' AND x = :a",
+            &params,
+            PlaceholderStyle::Dollar
+        ),
+        Ok((
+            r"SELECT * FROM t WHERE p = 'C:\' AND x = $1".to_string(),
+            vec![1]
+        ))
+    );
+    // while in MySQL it escapes the quote
+    assert_eq!(
+        to_positional(
+            &MySqlDialect,
+            r"SELECT * FROM t WHERE p = 'it\'s :a' AND x = :a",
+            &params,
+            PlaceholderStyle::Question
+        ),
+        Ok((
+            r"SELECT * FROM t WHERE p = 'it\'s :a' AND x = ?".to_string(),
+            vec![1]
+        ))
+    );
+}
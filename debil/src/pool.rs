@@ -0,0 +1,497 @@
+use crate::{Dialect, Params, SqlConn, SqlMapper};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Opens and checks connections for a `Pool`. Each backend provides one for its `DebilPool`.
+#[async_trait]
+pub trait ManageConnection: Send + Sync + 'static {
+    type Connection: Send + 'static;
+    type Error: Send;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error>;
+
+    /// Health check of an idle connection, run on checkout. The connection is taken by value,
+    /// so that a blocking check can move it to another thread, and given back if it is valid.
+    async fn is_valid(&self, conn: Self::Connection) -> Result<Self::Connection, Self::Error>;
+
+    /// Closes a connection for `Pool::close`. Dropping it is enough unless the backend has a
+    /// graceful way to say goodbye to the server.
+    async fn disconnect(&self, conn: Self::Connection) -> Result<(), Self::Error> {
+        drop(conn);
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Upper bound of connections open at once. `Pool::get` waits while all of them are in use.
+    pub max_size: usize,
+    /// Idle connections older than this are closed instead of being handed out
+    pub idle_timeout: Option<Duration>,
+    /// Runs `ManageConnection::is_valid` on an idle connection before handing it out
+    pub test_on_checkout: bool,
+    /// Capacity of the prepared statement cache of each connection,
+    /// or the default of the backend if None
+    pub statement_cache_size: Option<usize>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            idle_timeout: Some(Duration::from_secs(600)),
+            test_on_checkout: true,
+            statement_cache_size: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolMetrics {
+    pub max_size: usize,
+    /// Connections currently open, both idle and in use
+    pub connections: usize,
+    pub idle: usize,
+    pub in_use: usize,
+    /// Number of `Pool::get` calls which succeeded
+    pub checkouts: u64,
+    /// Number of connections opened so far
+    pub created: u64,
+    /// Number of idle connections closed for `idle_timeout`
+    pub closed_idle: u64,
+    /// Number of idle connections closed for failing the health check
+    pub failed_health_checks: u64,
+    /// Number of connections closed instead of going back to the pool, as they were returned
+    /// in a transaction or while a query was running
+    pub discarded: u64,
+}
+
+struct PoolInner<M: ManageConnection> {
+    manager: M,
+    config: PoolConfig,
+    idle: Mutex<Vec<(M::Connection, Instant)>>,
+    semaphore: Arc<tokio::sync::Semaphore>,
+    in_use: AtomicUsize,
+    checkouts: AtomicU64,
+    created: AtomicU64,
+    closed_idle: AtomicU64,
+    failed_health_checks: AtomicU64,
+    discarded: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl<M: ManageConnection> PoolInner<M> {
+    // Takes the most recently returned idle connection which has not timed out
+    fn take_idle(&self) -> Option<M::Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        let expired = self.remove_expired(&mut idle);
+        let conn = idle.pop().map(|(conn, _)| conn);
+        drop(idle);
+
+        // close the expired connections outside of the lock
+        drop(expired);
+
+        conn
+    }
+
+    fn remove_expired(&self, idle: &mut Vec<(M::Connection, Instant)>) -> Vec<M::Connection> {
+        let timeout = match self.config.idle_timeout {
+            Some(timeout) => timeout,
+            None => return vec![],
+        };
+
+        let mut expired = vec![];
+        let mut i = 0;
+        while i < idle.len() {
+            if idle[i].1.elapsed() >= timeout {
+                expired.push(idle.remove(i).0);
+            } else {
+                i += 1;
+            }
+        }
+        self.closed_idle
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+
+        expired
+    }
+}
+
+/// A pool of connections which hands out `PooledConn`s, which implement `SqlConn` themselves.
+/// Connections are opened lazily up to `PoolConfig::max_size`. Cloning the pool shares it.
+pub struct Pool<M: ManageConnection> {
+    inner: Arc<PoolInner<M>>,
+}
+
+impl<M: ManageConnection> Clone for Pool<M> {
+    fn clone(&self) -> Self {
+        Pool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: ManageConnection> Pool<M> {
+    pub fn new(manager: M, config: PoolConfig) -> Self {
+        assert!(config.max_size > 0, "max_size of a pool must be positive");
+
+        Pool {
+            inner: Arc::new(PoolInner {
+                manager,
+                semaphore: Arc::new(tokio::sync::Semaphore::new(config.max_size)),
+                config,
+                idle: Mutex::new(vec![]),
+                in_use: AtomicUsize::new(0),
+                checkouts: AtomicU64::new(0),
+                created: AtomicU64::new(0),
+                closed_idle: AtomicU64::new(0),
+                failed_health_checks: AtomicU64::new(0),
+                discarded: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.inner.config
+    }
+
+    pub fn manager(&self) -> &M {
+        &self.inner.manager
+    }
+
+    /// Checks out a connection, waiting for one to be returned if all of them are in use
+    pub async fn get(&self) -> Result<PooledConn<M>, M::Error> {
+        let permit = self
+            .inner
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore of a pool is never closed");
+
+        let conn = loop {
+            let conn = match self.inner.take_idle() {
+                Some(conn) => conn,
+                None => {
+                    let conn = self.inner.manager.connect().await?;
+                    self.inner.created.fetch_add(1, Ordering::Relaxed);
+
+                    break conn;
+                }
+            };
+
+            if !self.inner.config.test_on_checkout {
+                break conn;
+            }
+            if let Ok(conn) = self.inner.manager.is_valid(conn).await {
+                break conn;
+            }
+            self.inner
+                .failed_health_checks
+                .fetch_add(1, Ordering::Relaxed);
+        };
+
+        self.inner.in_use.fetch_add(1, Ordering::Relaxed);
+        self.inner.checkouts.fetch_add(1, Ordering::Relaxed);
+
+        Ok(PooledConn {
+            conn: Some(conn),
+            pool: self.inner.clone(),
+            _permit: permit,
+            in_transaction: false,
+            busy: false,
+        })
+    }
+
+    /// Closes the idle connections with `ManageConnection::disconnect`, for shutting down.
+    /// Connections in use are dropped instead of going back to the pool when they are returned.
+    /// Returns the first error of `disconnect`, after trying every idle connection.
+    pub async fn close(&self) -> Result<(), M::Error> {
+        self.inner.closed.store(true, Ordering::Relaxed);
+        let idle = std::mem::take(&mut *self.inner.idle.lock().unwrap());
+
+        let mut result = Ok(());
+        for (conn, _) in idle {
+            if let Err(err) = self.inner.manager.disconnect(conn).await {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        let (idle, expired) = {
+            let mut idle = self.inner.idle.lock().unwrap();
+            let expired = self.inner.remove_expired(&mut idle);
+            (idle.len(), expired)
+        };
+        drop(expired);
+        let in_use = self.inner.in_use.load(Ordering::Relaxed);
+
+        PoolMetrics {
+            max_size: self.inner.config.max_size,
+            connections: idle + in_use,
+            idle,
+            in_use,
+            checkouts: self.inner.checkouts.load(Ordering::Relaxed),
+            created: self.inner.created.load(Ordering::Relaxed),
+            closed_idle: self.inner.closed_idle.load(Ordering::Relaxed),
+            failed_health_checks: self.inner.failed_health_checks.load(Ordering::Relaxed),
+            discarded: self.inner.discarded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A connection checked out from a `Pool`, which goes back to the pool when dropped.
+///
+/// A connection dropped in a transaction, or while a query is running such as when the future
+/// of the query is cancelled, is closed instead, so that the next user does not inherit its state.
+/// Only transactions and queries run through `SqlConn` of the `PooledConn` are tracked,
+/// not the ones run on the connection it derefs to.
+pub struct PooledConn<M: ManageConnection> {
+    conn: Option<M::Connection>,
+    pool: Arc<PoolInner<M>>,
+    _permit: tokio::sync::OwnedSemaphorePermit,
+    in_transaction: bool,
+    // a query has started and not finished yet
+    busy: bool,
+}
+
+impl<M: ManageConnection> PooledConn<M> {
+    /// Takes the connection out of the pool for good, freeing its slot
+    pub fn into_inner(mut self) -> M::Connection {
+        self.conn.take().unwrap()
+    }
+}
+
+impl<M: ManageConnection> std::ops::Deref for PooledConn<M> {
+    type Target = M::Connection;
+
+    fn deref(&self) -> &M::Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl<M: ManageConnection> std::ops::DerefMut for PooledConn<M> {
+    fn deref_mut(&mut self) -> &mut M::Connection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl<M: ManageConnection> Drop for PooledConn<M> {
+    fn drop(&mut self) {
+        self.pool.in_use.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(conn) = self.conn.take() {
+            if self.in_transaction || self.busy {
+                self.pool.discarded.fetch_add(1, Ordering::Relaxed);
+            } else if !self.pool.closed.load(Ordering::Relaxed) {
+                self.pool.idle.lock().unwrap().push((conn, Instant::now()));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<V, M> SqlConn<V> for PooledConn<M>
+where
+    V: 'static + Sync + Send,
+    M: ManageConnection,
+    M::Connection: SqlConn<V>,
+{
+    type Error = <M::Connection as SqlConn<V>>::Error;
+
+    fn dialect(&self) -> &dyn Dialect {
+        (**self).dialect()
+    }
+
+    fn max_placeholders(&self) -> usize {
+        (**self).max_placeholders()
+    }
+
+    fn max_rows_per_statement(&self) -> usize {
+        (**self).max_rows_per_statement()
+    }
+
+    async fn sql_exec(&mut self, query: String, params: Params<V>) -> Result<u64, Self::Error> {
+        self.busy = true;
+        let result = (**self).sql_exec(query, params).await;
+        self.busy = false;
+
+        result
+    }
+
+    async fn sql_query<T: SqlMapper<ValueType = V> + Sync + Send>(
+        &mut self,
+        query: String,
+        params: Params<V>,
+    ) -> Result<Vec<T>, Self::Error> {
+        self.busy = true;
+        let result = (**self).sql_query::<T>(query, params).await;
+        self.busy = false;
+
+        result
+    }
+
+    async fn sql_batch_exec(
+        &mut self,
+        query: String,
+        params: Vec<Params<V>>,
+    ) -> Result<(), Self::Error> {
+        self.busy = true;
+        let result = (**self).sql_batch_exec(query, params).await;
+        self.busy = false;
+
+        result
+    }
+
+    async fn prepare(&mut self, query: String) -> Result<crate::Statement, Self::Error> {
+        self.busy = true;
+        let result = (**self).prepare(query).await;
+        self.busy = false;
+
+        result
+    }
+
+    // The connection is busy until the stream ends
+    fn sql_query_stream<'a, T: SqlMapper<ValueType = V> + Sync + Send + 'a>(
+        &'a mut self,
+        query: String,
+        params: Params<V>,
+    ) -> futures::stream::BoxStream<'a, Result<T, Self::Error>> {
+        use futures::stream::StreamExt;
+
+        let busy = &mut self.busy;
+        *busy = true;
+        let rows = self
+            .conn
+            .as_mut()
+            .unwrap()
+            .sql_query_stream::<T>(query, params);
+
+        rows.chain(
+            futures::stream::once(async move {
+                *busy = false;
+            })
+            .filter_map(|()| futures::future::ready(None)),
+        )
+        .boxed()
+    }
+
+    // A transaction counts from the start of BEGIN, which may have taken effect even if it
+    // fails, to the success of COMMIT or ROLLBACK
+    async fn start_transaction(&mut self) -> Result<(), Self::Error> {
+        self.in_transaction = true;
+
+        (**self).start_transaction().await
+    }
+
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        (**self).commit().await?;
+        self.in_transaction = false;
+
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), Self::Error> {
+        (**self).rollback().await?;
+        self.in_transaction = false;
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn pool_checks_idle_connections() {
+    // a connection is its serial number, and only 1 fails the health check
+    struct Manager(AtomicUsize);
+
+    #[async_trait]
+    impl ManageConnection for Manager {
+        type Connection = usize;
+        type Error = ();
+
+        async fn connect(&self) -> Result<usize, ()> {
+            Ok(self.0.fetch_add(1, Ordering::Relaxed))
+        }
+
+        async fn is_valid(&self, conn: usize) -> Result<usize, ()> {
+            if conn != 1 {
+                Ok(conn)
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    let pool = Pool::new(Manager(AtomicUsize::new(0)), PoolConfig::default());
+    let (c0, c1) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    assert_eq!((*c0, *c1), (0, 1));
+    drop(c0);
+    drop(c1);
+
+    // 1 fails the health check and is closed, then 0 is handed out
+    assert_eq!(*pool.get().await.unwrap(), 0);
+    assert_eq!(
+        pool.metrics(),
+        PoolMetrics {
+            max_size: 10,
+            connections: 1,
+            idle: 1,
+            in_use: 0,
+            checkouts: 3,
+            created: 2,
+            closed_idle: 0,
+            failed_health_checks: 1,
+            discarded: 0,
+        }
+    );
+    assert_eq!(pool.get().await.unwrap().into_inner(), 0);
+    assert_eq!(pool.metrics().connections, 0);
+}
+
+#[tokio::test]
+async fn pool_closes_idle_connections() {
+    // records the connections given to disconnect
+    struct Manager(Mutex<Vec<usize>>, AtomicUsize);
+
+    #[async_trait]
+    impl ManageConnection for Manager {
+        type Connection = usize;
+        type Error = ();
+
+        async fn connect(&self) -> Result<usize, ()> {
+            Ok(self.1.fetch_add(1, Ordering::Relaxed))
+        }
+
+        async fn is_valid(&self, conn: usize) -> Result<usize, ()> {
+            Ok(conn)
+        }
+
+        async fn disconnect(&self, conn: usize) -> Result<(), ()> {
+            self.0.lock().unwrap().push(conn);
+
+            Ok(())
+        }
+    }
+
+    let pool = Pool::new(
+        Manager(Mutex::new(vec![]), AtomicUsize::new(0)),
+        PoolConfig::default(),
+    );
+    let (c0, c1) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    drop(c0);
+
+    pool.close().await.unwrap();
+    assert_eq!(*pool.manager().0.lock().unwrap(), vec![0]);
+
+    // a connection returned after close does not go back to the pool
+    drop(c1);
+    assert_eq!(pool.metrics().connections, 0);
+}
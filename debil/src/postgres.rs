@@ -0,0 +1,170 @@
+// SQL generation for PostgreSQL. This module has no driver; queries built with `PostgresDialect`
+// use `$n` placeholders whose values are the binds in order, so they can be passed to any driver.
+use std::marker::PhantomData;
+
+use crate::{SqlLiteral, SqlNull, SqlValue};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostgresValue {
+    Null,
+    Bool(bool),
+    Int(i32),
+    BigInt(i64),
+    Double(f64),
+    Text(String),
+    Bytea(Vec<u8>),
+}
+
+impl SqlNull for PostgresValue {
+    fn is_null(&self) -> bool {
+        *self == PostgresValue::Null
+    }
+}
+
+impl SqlLiteral for PostgresValue {
+    fn to_sql_literal(&self) -> String {
+        match self {
+            PostgresValue::Null => "NULL".to_string(),
+            PostgresValue::Bool(v) => if *v { "TRUE" } else { "FALSE" }.to_string(),
+            PostgresValue::Int(v) => v.to_string(),
+            PostgresValue::BigInt(v) => v.to_string(),
+            PostgresValue::Double(v) => v.to_string(),
+            PostgresValue::Text(v) => crate::types::quote_string(v),
+            PostgresValue::Bytea(v) => format!(
+                "'\\x{}'",
+                v.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+            ),
+        }
+    }
+}
+
+impl SqlValue<bool> for PostgresValue {
+    fn column_type(_: PhantomData<bool>, _size: i32) -> String {
+        "BOOLEAN".to_string()
+    }
+
+    fn serialize(val: bool) -> Self {
+        PostgresValue::Bool(val)
+    }
+
+    fn deserialize(self) -> bool {
+        match self {
+            PostgresValue::Bool(v) => v,
+            _ => panic!("PostgresValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<String> for PostgresValue {
+    fn column_type(_: PhantomData<String>, size: i32) -> String {
+        if size > 0 {
+            format!("VARCHAR({})", size)
+        } else {
+            "TEXT".to_string()
+        }
+    }
+
+    fn serialize(val: String) -> Self {
+        PostgresValue::Text(val)
+    }
+
+    fn deserialize(self) -> String {
+        match self {
+            PostgresValue::Text(v) => v,
+            _ => panic!("PostgresValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<i32> for PostgresValue {
+    fn column_type(_: PhantomData<i32>, _size: i32) -> String {
+        "INTEGER".to_string()
+    }
+
+    fn serialize(val: i32) -> Self {
+        PostgresValue::Int(val)
+    }
+
+    fn deserialize(self) -> i32 {
+        match self {
+            PostgresValue::Int(v) => v,
+            _ => panic!("PostgresValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<i64> for PostgresValue {
+    fn column_type(_: PhantomData<i64>, _size: i32) -> String {
+        "BIGINT".to_string()
+    }
+
+    fn serialize(val: i64) -> Self {
+        PostgresValue::BigInt(val)
+    }
+
+    fn deserialize(self) -> i64 {
+        match self {
+            PostgresValue::BigInt(v) => v,
+            _ => panic!("PostgresValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<f64> for PostgresValue {
+    fn column_type(_: PhantomData<f64>, _size: i32) -> String {
+        "DOUBLE PRECISION".to_string()
+    }
+
+    fn serialize(val: f64) -> Self {
+        PostgresValue::Double(val)
+    }
+
+    fn deserialize(self) -> f64 {
+        match self {
+            PostgresValue::Double(v) => v,
+            _ => panic!("PostgresValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl SqlValue<Vec<u8>> for PostgresValue {
+    fn column_type(_: PhantomData<Vec<u8>>, _size: i32) -> String {
+        "BYTEA".to_string()
+    }
+
+    fn serialize(val: Vec<u8>) -> Self {
+        PostgresValue::Bytea(val)
+    }
+
+    fn deserialize(self) -> Vec<u8> {
+        match self {
+            PostgresValue::Bytea(v) => v,
+            _ => panic!("PostgresValue::deserialize: invalid type"),
+        }
+    }
+}
+
+impl<V> SqlValue<Option<V>> for PostgresValue
+where
+    PostgresValue: SqlValue<V>,
+{
+    fn column_type(_: PhantomData<Option<V>>, size: i32) -> String {
+        <PostgresValue as SqlValue<V>>::column_type(PhantomData::<V>, size)
+    }
+
+    fn serialize(val: Option<V>) -> Self {
+        match val {
+            None => PostgresValue::Null,
+            Some(v) => SqlValue::serialize(v),
+        }
+    }
+
+    fn deserialize(self) -> Option<V> {
+        match self {
+            PostgresValue::Null => None,
+            _ => Some(SqlValue::deserialize(self)),
+        }
+    }
+}
+
+pub type DefaultSqlValue = PostgresValue;
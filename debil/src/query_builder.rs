@@ -1,12 +1,33 @@
+use std::collections::HashSet;
+
 use async_trait::async_trait;
 
-use crate::Params;
+use crate::placeholder::replace_placeholders;
+use crate::{CompoundQuery, Dialect, GenericDialect, Params, Row, SqlConn};
 
 #[derive(Clone, Debug)]
 pub enum JoinType {
     Inner,
     Left,
     Right,
+    // Not supported by MySQL
+    Full,
+    Cross,
+}
+
+#[derive(Clone, Debug)]
+enum JoinCondition {
+    // `base.lhs = target.rhs`, where base is the FROM table
+    Columns(String, String),
+    Expr(String),
+    Nothing,
+}
+
+// Table names are quoted by the dialect when built, but a subquery is already SQL
+#[derive(Clone, Debug)]
+enum Source {
+    Table(String),
+    Subquery(String),
 }
 
 #[derive(Clone, Debug)]
@@ -15,50 +36,143 @@ pub enum Ordering {
     Descending,
 }
 
-impl Ordering {
-    pub fn to_string(&self) -> String {
+impl std::fmt::Display for Ordering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Ordering::*;
 
-        match self {
+        f.write_str(match self {
             Ascending => "ASC",
             Descending => "DESC",
+        })
+    }
+}
+
+// A builder nested in another one. It is rendered when the outer builder is built, so that
+// both use the same dialect; until then, its place in the outer query is held by a marker.
+#[derive(Clone, Debug)]
+enum Nested<V> {
+    Query(Box<QueryBuilder<V>>),
+    Compound(CompoundQuery<V>),
+}
+
+impl<V> Nested<V> {
+    fn render(self, dialect: &dyn Dialect) -> (String, Vec<(String, V)>) {
+        match self {
+            Nested::Query(builder) => builder.render(dialect),
+            Nested::Compound(compound) => compound.render(dialect),
         }
-        .to_string()
+    }
+}
+
+// Conditions of a `wheres` call with their binds, which are merged into the binds of the query
+// when it is rendered for a dialect
+type Condition<V> = (Vec<String>, Vec<(String, V)>);
+
+fn nested_marker(index: usize) -> String {
+    format!("\u{1}{}\u{1}", index)
+}
+
+// Table names with whitespace carry an alias as in `user u`, or are some other SQL,
+// so they are written as they are. Plain names are quoted for the dialect.
+fn quote_table(dialect: &dyn Dialect, table_name: &str) -> String {
+    if table_name.contains(char::is_whitespace) {
+        table_name.to_string()
+    } else {
+        dialect.quote_qualified(table_name)
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct QueryBuilder<V> {
+    ctes: Vec<(String, String)>,
+    recursive: bool,
     selects: Vec<String>,
-    from: Option<String>,
-    wheres: Vec<String>,
+    from: Option<Source>,
+    from_alias: Option<String>,
+    wheres: Vec<Condition<V>>,
     limit: Option<i32>,
-    joins: Vec<(JoinType, String, String, String)>,
+    offset: Option<i32>,
+    joins: Vec<(JoinType, String, Option<String>, JoinCondition)>,
     groups: Vec<String>,
     orders: Vec<(String, Ordering)>,
-    binds: Vec<(String, V)>,
+    nested: Vec<Nested<V>>,
+}
+
+impl<V> Default for QueryBuilder<V> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<V> QueryBuilder<V> {
     pub fn new() -> QueryBuilder<V> {
         QueryBuilder {
+            ctes: vec![],
+            recursive: false,
             selects: vec![],
             from: None,
+            from_alias: None,
             wheres: Vec::new(),
             limit: None,
+            offset: None,
             joins: vec![],
             groups: vec![],
             orders: vec![],
-            binds: vec![],
+            nested: vec![],
         }
     }
 
     pub fn table(mut self, table_name: impl Into<String>) -> QueryBuilder<V> {
-        self.from = Some(table_name.into());
+        self.from = Some(Source::Table(table_name.into()));
+        self.from_alias = None;
+
+        self
+    }
+
+    /// Prepends `WITH name AS (...)`, so that the query can refer to `name` as a table
+    pub fn with(mut self, name: impl Into<String>, builder: QueryBuilder<V>) -> QueryBuilder<V> {
+        let marker = self.nest(Nested::Query(Box::new(builder)));
+        self.ctes.push((name.into(), marker));
+
+        self
+    }
+
+    /// Prepends `WITH RECURSIVE name AS (anchor UNION ALL recursive)`,
+    /// where `recursive` refers to `name` itself
+    pub fn with_recursive(
+        mut self,
+        name: impl Into<String>,
+        anchor: QueryBuilder<V>,
+        recursive: QueryBuilder<V>,
+    ) -> QueryBuilder<V> {
+        let marker = self.nest(Nested::Compound(anchor.union_all(recursive)));
+        self.ctes.push((name.into(), marker));
+        self.recursive = true;
 
         self
     }
 
+    /// `FROM table_name AS alias`
+    pub fn table_as(
+        mut self,
+        table_name: impl Into<String>,
+        alias: impl Into<String>,
+    ) -> QueryBuilder<V> {
+        self.from = Some(Source::Table(table_name.into()));
+        self.from_alias = Some(alias.into());
+
+        self
+    }
+
+    // Sets the FROM table unless the builder already has an aliased one, and returns the name
+    // which columns of the FROM table should be qualified with
+    pub(crate) fn default_table(self, table_name: String) -> (QueryBuilder<V>, String) {
+        match self.from_alias.clone() {
+            Some(alias) => (self, alias),
+            None => (self.table(table_name.clone()), table_name),
+        }
+    }
+
     pub fn selects(mut self, selects: Vec<impl Into<String>>) -> QueryBuilder<V> {
         self.selects = selects.into_iter().map(|v| v.into()).collect::<Vec<_>>();
 
@@ -74,17 +188,79 @@ impl<V> QueryBuilder<V> {
 
     pub fn wheres<S: Into<String>>(
         mut self,
-        (cond, mut params): (Vec<S>, Vec<(String, V)>),
+        (cond, params): (Vec<S>, Vec<(String, V)>),
     ) -> QueryBuilder<V> {
         self.wheres
-            .append(&mut cond.into_iter().map(|v| v.into()).collect::<Vec<_>>());
-        self.binds.append(&mut params);
+            .push((cond.into_iter().map(|v| v.into()).collect(), params));
+
+        self
+    }
+
+    /// Adds a condition built from the SQL of another builder, e.g.
+    /// `filter_subquery(sub, |q| format!("price > ({})", q))`
+    pub fn filter_subquery(
+        mut self,
+        subquery: QueryBuilder<V>,
+        cond: impl FnOnce(String) -> String,
+    ) -> QueryBuilder<V> {
+        let marker = self.nest(Nested::Query(Box::new(subquery)));
+        self.wheres.push((vec![cond(marker)], vec![]));
+
+        self
+    }
+
+    pub fn filter_in(
+        self,
+        column: impl Into<String>,
+        subquery: QueryBuilder<V>,
+    ) -> QueryBuilder<V> {
+        let column = column.into();
+        self.filter_subquery(subquery, |q| format!("{} IN ({})", column, q))
+    }
+
+    pub fn filter_not_in(
+        self,
+        column: impl Into<String>,
+        subquery: QueryBuilder<V>,
+    ) -> QueryBuilder<V> {
+        let column = column.into();
+        self.filter_subquery(subquery, |q| format!("{} NOT IN ({})", column, q))
+    }
+
+    pub fn filter_exists(self, subquery: QueryBuilder<V>) -> QueryBuilder<V> {
+        self.filter_subquery(subquery, |q| format!("EXISTS ({})", q))
+    }
+
+    pub fn filter_not_exists(self, subquery: QueryBuilder<V>) -> QueryBuilder<V> {
+        self.filter_subquery(subquery, |q| format!("NOT EXISTS ({})", q))
+    }
+
+    /// Uses another builder as the FROM source, i.e. `FROM (SELECT ...) AS alias`
+    pub fn from_subquery(mut self, subquery: QueryBuilder<V>, alias: impl Into<String>) -> Self {
+        let marker = self.nest(Nested::Query(Box::new(subquery)));
+        self.from = Some(Source::Subquery(format!("({})", marker)));
+        self.from_alias = Some(alias.into());
 
         self
     }
 
+    /// Appends `(SELECT ...) AS alias` to the select expressions
+    pub fn select_subquery(mut self, subquery: QueryBuilder<V>, alias: impl Into<String>) -> Self {
+        let marker = self.nest(Nested::Query(Box::new(subquery)));
+        self.selects
+            .push(format!("({}) AS {}", marker, alias.into()));
+
+        self
+    }
+
+    fn nest(&mut self, nested: Nested<V>) -> String {
+        self.nested.push(nested);
+
+        nested_marker(self.nested.len() - 1)
+    }
+
     pub fn filter(mut self, cond: impl Into<String>) -> QueryBuilder<V> {
-        self.wheres.push(cond.into());
+        self.wheres.push((vec![cond.into()], vec![]));
 
         self
     }
@@ -95,52 +271,108 @@ impl<V> QueryBuilder<V> {
         self
     }
 
+    pub fn offset(mut self, n: i32) -> QueryBuilder<V> {
+        self.offset = Some(n);
+
+        self
+    }
+
     pub fn order_by(mut self, column_name: impl Into<String>, ordering: Ordering) -> Self {
         self.orders.push((column_name.into(), ordering));
 
         self
     }
 
-    pub fn inner_join(
+    fn join_columns(
         mut self,
+        join_type: JoinType,
         target_table: impl Into<String>,
-        on_fields: (impl Into<String>, impl Into<String>),
+        (lhs, rhs): (impl Into<String>, impl Into<String>),
     ) -> QueryBuilder<V> {
         self.joins.push((
-            JoinType::Inner,
+            join_type,
             target_table.into(),
-            on_fields.0.into(),
-            on_fields.1.into(),
+            None,
+            JoinCondition::Columns(lhs.into(), rhs.into()),
         ));
 
         self
     }
 
+    pub fn inner_join(
+        self,
+        target_table: impl Into<String>,
+        on_fields: (impl Into<String>, impl Into<String>),
+    ) -> QueryBuilder<V> {
+        self.join_columns(JoinType::Inner, target_table, on_fields)
+    }
+
     pub fn left_join(
-        mut self,
+        self,
+        target_table: impl Into<String>,
+        on_fields: (impl Into<String>, impl Into<String>),
+    ) -> QueryBuilder<V> {
+        self.join_columns(JoinType::Left, target_table, on_fields)
+    }
+
+    pub fn right_join(
+        self,
         target_table: impl Into<String>,
         on_fields: (impl Into<String>, impl Into<String>),
     ) -> QueryBuilder<V> {
+        self.join_columns(JoinType::Right, target_table, on_fields)
+    }
+
+    /// Panics when built for a dialect without FULL OUTER JOIN, such as MySQL
+    pub fn full_join(
+        self,
+        target_table: impl Into<String>,
+        on_fields: (impl Into<String>, impl Into<String>),
+    ) -> QueryBuilder<V> {
+        self.join_columns(JoinType::Full, target_table, on_fields)
+    }
+
+    pub fn cross_join(mut self, target_table: impl Into<String>) -> QueryBuilder<V> {
         self.joins.push((
-            JoinType::Left,
+            JoinType::Cross,
             target_table.into(),
-            on_fields.0.into(),
-            on_fields.1.into(),
+            None,
+            JoinCondition::Nothing,
         ));
 
         self
     }
 
-    pub fn right_join(
+    /// Joins with an arbitrary condition, e.g. `join_on(JoinType::Left, "b", "a.x = b.x AND a.y = b.y")`
+    pub fn join_on(
         mut self,
+        join_type: JoinType,
         target_table: impl Into<String>,
-        on_fields: (impl Into<String>, impl Into<String>),
+        cond: impl Into<String>,
     ) -> QueryBuilder<V> {
         self.joins.push((
-            JoinType::Right,
+            join_type,
             target_table.into(),
-            on_fields.0.into(),
-            on_fields.1.into(),
+            None,
+            JoinCondition::Expr(cond.into()),
+        ));
+
+        self
+    }
+
+    /// `join_on` with an aliased target table, i.e. `JOIN target_table AS alias ON cond`
+    pub fn join_as(
+        mut self,
+        join_type: JoinType,
+        target_table: impl Into<String>,
+        alias: impl Into<String>,
+        cond: impl Into<String>,
+    ) -> QueryBuilder<V> {
+        self.joins.push((
+            join_type,
+            target_table.into(),
+            Some(alias.into()),
+            JoinCondition::Expr(cond.into()),
         ));
 
         self
@@ -154,84 +386,154 @@ impl<V> QueryBuilder<V> {
     }
 
     pub fn build(self) -> (String, Params<V>) {
-        let table = self.from.unwrap();
-        let from = format!("FROM {}", table.clone());
-        let where_clause = format!("WHERE {}", self.wheres.as_slice().join(" AND "));
-        let limit_clause = self
-            .limit
-            .map(|i| format!("LIMIT {}", i))
-            .unwrap_or("".to_string());
-
-        (
-            [
-                // SELECT clause
+        self.build_for(&GenericDialect)
+    }
+
+    pub fn build_for(self, dialect: &dyn Dialect) -> (String, Params<V>) {
+        let (query, binds) = self.render(dialect);
+
+        bind_placeholders(dialect, query, binds)
+    }
+
+    // Renders the query with `:name` placeholders, so that it can still be merged into another one
+    pub(crate) fn render(self, dialect: &dyn Dialect) -> (String, Vec<(String, V)>) {
+        let table = match self.from.unwrap() {
+            Source::Table(table) => quote_table(dialect, &table),
+            Source::Subquery(query) => query,
+        };
+        let alias = self
+            .from_alias
+            .map(|alias| dialect.quote_identifier(&alias));
+        let from = match &alias {
+            Some(alias) => format!("FROM {} AS {}", table, alias),
+            None => format!("FROM {}", table),
+        };
+        let base = alias.unwrap_or(table);
+        let mut binds = vec![];
+        let mut wheres = vec![];
+        for (cond, params) in self.wheres {
+            wheres.append(&mut merge_binds(dialect, &mut binds, cond, params));
+        }
+        let where_clause = format!("WHERE {}", wheres.as_slice().join(" AND "));
+        let limit_clause = dialect.limit_offset(self.limit, self.offset);
+
+        let mut query = [
+            // WITH clause
+            if !self.ctes.is_empty() {
                 format!(
-                    "SELECT {}",
-                    if self.selects.is_empty() {
-                        "*".to_string()
-                    } else {
-                        self.selects.as_slice().join(", ")
-                    }
-                ),
-                from,
-                // JOIN clause
-                self.joins
-                    .into_iter()
-                    .map(|(jt, another_table, lhs, rhs)| {
-                        format!(
-                            "{} JOIN {} ON {}.{} = {}.{}",
-                            match jt {
-                                JoinType::Inner => "INNER",
-                                JoinType::Left => "LEFT",
-                                JoinType::Right => "RIGHT",
-                            },
-                            another_table,
-                            table,
-                            lhs,
-                            another_table,
-                            rhs
-                        )
-                    })
-                    .collect::<Vec<_>>()
-                    .as_slice()
-                    .join(" "),
-                // WHERE clause
-                if !self.wheres.is_empty() {
-                    where_clause
+                    "WITH {}{}",
+                    if self.recursive { "RECURSIVE " } else { "" },
+                    self.ctes
+                        .into_iter()
+                        .map(|(name, query)| {
+                            format!("{} AS ({})", dialect.quote_identifier(&name), query)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            } else {
+                String::new()
+            },
+            // SELECT clause
+            format!(
+                "SELECT {}",
+                if self.selects.is_empty() {
+                    "*".to_string()
                 } else {
-                    String::new()
-                },
-                // GROUP BY clause
-                if !self.groups.is_empty() {
-                    format!("GROUP BY {}", self.groups.as_slice().join(", "))
-                } else {
-                    String::new()
-                },
-                // ORDER BY clause
-                if !self.orders.is_empty() {
-                    format!(
-                        "ORDER BY {}",
-                        self.orders
-                            .into_iter()
-                            .map(|(k, o)| format!("{} {}", k, o.to_string()))
-                            .collect::<Vec<_>>()
-                            .as_slice()
-                            .join(", ")
-                    )
-                } else {
-                    String::new()
-                },
-                // LIMIT clause
-                limit_clause,
-            ]
-            .iter()
-            .filter(|s| s.len() != 0)
-            .cloned()
-            .collect::<Vec<_>>()
-            .as_slice()
-            .join(" "),
-            Params(self.binds),
-        )
+                    self.selects.as_slice().join(", ")
+                }
+            ),
+            from,
+            // JOIN clause
+            self.joins
+                .into_iter()
+                .map(|(jt, another_table, alias, cond)| {
+                    let another_table = quote_table(dialect, &another_table);
+                    let alias = alias.map(|alias| dialect.quote_identifier(&alias));
+
+                    let join = format!(
+                        "{} JOIN {}",
+                        match jt {
+                            JoinType::Inner => "INNER",
+                            JoinType::Left => "LEFT",
+                            JoinType::Right => "RIGHT",
+                            JoinType::Full => {
+                                assert!(
+                                    dialect.supports_full_outer_join(),
+                                    "full_join: FULL OUTER JOIN is not supported by the {} dialect",
+                                    dialect.name()
+                                );
+                                "FULL OUTER"
+                            }
+                            JoinType::Cross => "CROSS",
+                        },
+                        match &alias {
+                            Some(alias) => format!("{} AS {}", another_table, alias),
+                            None => another_table.clone(),
+                        }
+                    );
+
+                    match cond {
+                        JoinCondition::Columns(lhs, rhs) => format!(
+                            "{} ON {}.{} = {}.{}",
+                            join,
+                            base,
+                            dialect.quote_identifier(&lhs),
+                            alias.unwrap_or(another_table),
+                            dialect.quote_identifier(&rhs)
+                        ),
+                        JoinCondition::Expr(expr) => format!("{} ON {}", join, expr),
+                        JoinCondition::Nothing => join,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .as_slice()
+                .join(" "),
+            // WHERE clause
+            if !wheres.is_empty() {
+                where_clause
+            } else {
+                String::new()
+            },
+            // GROUP BY clause
+            if !self.groups.is_empty() {
+                format!("GROUP BY {}", self.groups.as_slice().join(", "))
+            } else {
+                String::new()
+            },
+            // ORDER BY clause
+            if !self.orders.is_empty() {
+                format!(
+                    "ORDER BY {}",
+                    self.orders
+                        .into_iter()
+                        .map(|(k, o)| format!("{} {}", k, o))
+                        .collect::<Vec<_>>()
+                        .as_slice()
+                        .join(", ")
+                )
+            } else {
+                String::new()
+            },
+            // LIMIT clause
+            limit_clause,
+        ]
+        .iter()
+        .filter(|s| !s.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .as_slice()
+        .join(" ");
+
+        for (i, nested) in self.nested.into_iter().enumerate() {
+            let (nested_query, params) = nested.render(dialect);
+            let nested_query = merge_binds(dialect, &mut binds, vec![nested_query], params)
+                .pop()
+                .unwrap();
+            query = query.replace(&nested_marker(i), &nested_query);
+        }
+
+        (query, binds)
     }
 
     pub async fn load<R: QueryExecutor<T, E, ValueType = V>, T, E>(
@@ -265,6 +567,93 @@ impl<V> QueryBuilder<V> {
     }
 }
 
+impl<V: 'static + Sync + Send + Clone> QueryBuilder<V> {
+    /// Runs EXPLAIN (EXPLAIN QUERY PLAN on SQLite) on the query, whose rows have the columns
+    /// the database reports, e.g. `detail` on SQLite and `key` and `rows` on MySQL
+    pub async fn explain<C: SqlConn<V> + Send>(
+        self,
+        conn: &mut C,
+    ) -> Result<Vec<Row<V>>, C::Error> {
+        let (query, params) = self.build_for(conn.dialect());
+        let query = conn.dialect().explain(&query);
+
+        conn.sql_query::<Row<V>>(query, params).await
+    }
+}
+
+// Appends binds used in the given fragments. A bind whose name is already taken is renamed
+// to a fresh one, and its placeholders in the fragments are rewritten accordingly.
+pub(crate) fn merge_binds<V>(
+    dialect: &dyn Dialect,
+    binds: &mut Vec<(String, V)>,
+    fragments: Vec<String>,
+    params: Vec<(String, V)>,
+) -> Vec<String> {
+    let existing = binds
+        .iter()
+        .map(|(k, _)| k.trim_start_matches(':').to_string())
+        .collect::<HashSet<_>>();
+    let mut taken = existing.clone();
+    taken.extend(
+        params
+            .iter()
+            .map(|(k, _)| k.trim_start_matches(':').to_string()),
+    );
+
+    let mut renames = std::collections::HashMap::new();
+    for (name, value) in params {
+        let prefix = if name.starts_with(':') { ":" } else { "" };
+        let bare = name.trim_start_matches(':').to_string();
+
+        if !renames.contains_key(&bare) && existing.contains(&bare) {
+            let fresh = (1..)
+                .map(|i| format!("{}_{}", bare, i))
+                .find(|n| !taken.contains(n))
+                .unwrap();
+            taken.insert(fresh.clone());
+            renames.insert(bare.clone(), fresh);
+        }
+
+        match renames.get(&bare) {
+            Some(fresh) => binds.push((format!("{}{}", prefix, fresh), value)),
+            None => binds.push((name, value)),
+        }
+    }
+
+    if renames.is_empty() {
+        return fragments;
+    }
+
+    fragments
+        .into_iter()
+        .map(|fragment| {
+            replace_placeholders(dialect, &fragment, |name| {
+                format!(":{}", renames.get(name).map(|s| s.as_str()).unwrap_or(name))
+            })
+        })
+        .collect()
+}
+
+// Rewrites `:name` placeholders into the dialect's form. Positional placeholders refer to binds
+// in their order, so a name used more than once refers to the same bind.
+pub(crate) fn bind_placeholders<V>(
+    dialect: &dyn Dialect,
+    query: String,
+    binds: Vec<(String, V)>,
+) -> (String, Params<V>) {
+    let query = replace_placeholders(dialect, &query, |name| {
+        match binds
+            .iter()
+            .position(|(k, _)| k.trim_start_matches(':') == name)
+        {
+            Some(index) => dialect.placeholder(name, index),
+            None => format!(":{}", name),
+        }
+    });
+
+    (query, Params(binds))
+}
+
 #[async_trait]
 pub trait QueryExecutor<T, E> {
     type ValueType;
@@ -341,3 +730,231 @@ fn query_with_build() {
         "SELECT * FROM foo ORDER BY piyo ASC, nyan DESC"
     );
 }
+
+#[test]
+fn query_with_subquery() {
+    let (query, params) = QueryBuilder::<i32>::new()
+        .table("user")
+        .wheres((vec!["user.age = :age"], vec![("age".to_string(), 20)]))
+        .filter_in(
+            "user.id",
+            QueryBuilder::new()
+                .table("post")
+                .selects(vec!["post.user_id"])
+                .wheres((vec!["post.age = :age"], vec![("age".to_string(), 30)])),
+        )
+        .build();
+    assert_eq!(
+        query,
+        "SELECT * FROM user WHERE user.age = :age AND user.id IN (SELECT post.user_id FROM post WHERE post.age = :age_1)"
+    );
+    assert_eq!(
+        params.0,
+        vec![("age".to_string(), 20), ("age_1".to_string(), 30)]
+    );
+
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .table("user")
+            .filter_not_exists(
+                QueryBuilder::new()
+                    .table("post")
+                    .selects(vec!["1"])
+                    .filter("post.user_id = user.id")
+            )
+            .build()
+            .0,
+        "SELECT * FROM user WHERE NOT EXISTS (SELECT 1 FROM post WHERE post.user_id = user.id)"
+    );
+
+    let (query, params) = QueryBuilder::<i32>::new()
+        .from_subquery(
+            QueryBuilder::new()
+                .table("post")
+                .selects(vec!["post.user_id", "COUNT(*) AS n"])
+                .wheres((vec!["post.n > :n"], vec![("n".to_string(), 1)]))
+                .group_by(vec!["post.user_id"]),
+            "t",
+        )
+        .select_subquery(
+            QueryBuilder::new()
+                .table("user")
+                .selects(vec!["user.name"])
+                .filter("user.id = t.user_id"),
+            "name",
+        )
+        .filter_subquery(
+            QueryBuilder::new()
+                .table("config")
+                .selects(vec!["config.threshold"])
+                .wheres((vec!["config.n = :n"], vec![("n".to_string(), 2)])),
+            |q| format!("t.n > ({})", q),
+        )
+        .build();
+    assert_eq!(
+        query,
+        "SELECT (SELECT user.name FROM user WHERE user.id = t.user_id) AS name FROM (SELECT post.user_id, COUNT(*) AS n FROM post WHERE post.n > :n GROUP BY post.user_id) AS t WHERE t.n > (SELECT config.threshold FROM config WHERE config.n = :n_1)"
+    );
+    assert_eq!(params.0, vec![("n".to_string(), 1), ("n_1".to_string(), 2)]);
+}
+
+#[test]
+fn query_with_join_conditions() {
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .table_as("employee", "e")
+            .join_as(JoinType::Left, "employee", "m", "m.id = e.manager_id")
+            .inner_join("department", ("department_id", "id"))
+            .build()
+            .0,
+        "SELECT * FROM employee AS e LEFT JOIN employee AS m ON m.id = e.manager_id INNER JOIN department ON e.department_id = department.id"
+    );
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .table("a")
+            .inner_join("b", ("b_id", "id"))
+            .join_on(JoinType::Inner, "c", "c.x = b.x AND c.y = b.y")
+            .build()
+            .0,
+        "SELECT * FROM a INNER JOIN b ON a.b_id = b.id INNER JOIN c ON c.x = b.x AND c.y = b.y"
+    );
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .table("a")
+            .cross_join("b")
+            .full_join("c", ("id", "a_id"))
+            .build()
+            .0,
+        "SELECT * FROM a CROSS JOIN b FULL OUTER JOIN c ON a.id = c.a_id"
+    );
+
+    // table names with an alias are left as they are
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .table("user u")
+            .join_on(JoinType::Inner, "post p", "p.user_id = u.id")
+            .build_for(&crate::SqliteDialect)
+            .0,
+        "SELECT * FROM user u INNER JOIN post p ON p.user_id = u.id"
+    );
+}
+
+#[test]
+#[should_panic(expected = "FULL OUTER JOIN is not supported by the mysql dialect")]
+fn query_with_unsupported_full_join() {
+    QueryBuilder::<()>::new()
+        .table("a")
+        .full_join("b", ("id", "a_id"))
+        .build_for(&crate::MySqlDialect);
+}
+
+#[test]
+fn query_with_cte() {
+    let (query, params) = QueryBuilder::<i32>::new()
+        .with(
+            "adult",
+            QueryBuilder::new()
+                .table("user")
+                .wheres((vec!["user.age >= :age"], vec![("age".to_string(), 20)])),
+        )
+        .table("adult")
+        .wheres((vec!["adult.age < :age"], vec![("age".to_string(), 30)]))
+        .build();
+    assert_eq!(
+        query,
+        "WITH adult AS (SELECT * FROM user WHERE user.age >= :age_1) SELECT * FROM adult WHERE adult.age < :age"
+    );
+    assert_eq!(
+        params.0,
+        vec![("age".to_string(), 30), ("age_1".to_string(), 20)]
+    );
+
+    assert_eq!(
+        QueryBuilder::<()>::new()
+            .with_recursive(
+                "tree",
+                QueryBuilder::new()
+                    .table("category")
+                    .selects(vec!["id", "parent_id"])
+                    .filter("category.id = 1"),
+                QueryBuilder::new()
+                    .table("category")
+                    .selects(vec!["category.id", "category.parent_id"])
+                    .inner_join("tree", ("parent_id", "id")),
+            )
+            .table("tree")
+            .build()
+            .0,
+        "WITH RECURSIVE tree AS (SELECT id, parent_id FROM category WHERE category.id = 1 UNION ALL SELECT category.id, category.parent_id FROM category INNER JOIN tree ON category.parent_id = tree.id) SELECT * FROM tree"
+    );
+}
+
+#[test]
+fn query_with_dialect() {
+    use crate::{MySqlDialect, SqliteDialect};
+
+    let builder = QueryBuilder::<()>::new()
+        .table("user")
+        .filter_in(
+            "user.id",
+            QueryBuilder::new()
+                .table("post")
+                .selects(vec!["post.user_id"])
+                .offset(5),
+        )
+        .limit(10)
+        .offset(20);
+    assert_eq!(
+        builder.clone().build_for(&SqliteDialect).0,
+        r#"SELECT * FROM "user" WHERE user.id IN (SELECT post.user_id FROM "post" LIMIT -1 OFFSET 5) LIMIT 10 OFFSET 20"#
+    );
+    assert_eq!(
+        builder.build_for(&MySqlDialect).0,
+        "SELECT * FROM `user` WHERE user.id IN (SELECT post.user_id FROM `post` LIMIT 18446744073709551615 OFFSET 5) LIMIT 10 OFFSET 20"
+    );
+}
+
+#[test]
+fn query_with_quoted_identifiers() {
+    use crate::{MySqlDialect, SqliteDialect};
+
+    let builder = QueryBuilder::<()>::new()
+        .with("order", QueryBuilder::new().table("main.order"))
+        .table_as("order", "group")
+        .inner_join("key", ("key_id", "id"));
+    assert_eq!(
+        builder.clone().build_for(&SqliteDialect).0,
+        r#"WITH "order" AS (SELECT * FROM "main"."order") SELECT * FROM "order" AS "group" INNER JOIN "key" ON "group"."key_id" = "key"."id""#
+    );
+    assert_eq!(
+        builder.build_for(&MySqlDialect).0,
+        "WITH `order` AS (SELECT * FROM `main`.`order`) SELECT * FROM `order` AS `group` INNER JOIN `key` ON `group`.`key_id` = `key`.`id`"
+    );
+}
+
+#[test]
+fn query_with_positional_placeholders() {
+    let (query, params) = QueryBuilder::<i32>::new()
+        .table("user")
+        .wheres((
+            vec!["user.age >= :age", "user.age < :age + 10"],
+            vec![("age".to_string(), 20)],
+        ))
+        .filter_in(
+            "user.id",
+            QueryBuilder::new()
+                .table("post")
+                .selects(vec!["post.user_id"])
+                .wheres((vec!["post.age = :age"], vec![("age".to_string(), 30)])),
+        )
+        .filter("user.name <> ':age'")
+        .build_for(&crate::PostgresDialect);
+    assert_eq!(
+        query,
+        r#"SELECT * FROM "user" WHERE user.age >= $1 AND user.age < $1 + 10 AND user.id IN (SELECT post.user_id FROM "post" WHERE post.age = $2) AND user.name <> ':age'"#
+    );
+    assert_eq!(
+        params.0,
+        vec![("age".to_string(), 20), ("age_1".to_string(), 30)]
+    );
+}
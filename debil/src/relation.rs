@@ -0,0 +1,416 @@
+use crate::query_builder::bind_placeholders;
+use crate::{Dialect, Params, QueryBuilder, SqlConn, SqlMapper, SqlTable, SqlValue};
+
+/// Value of the primary key, which is a tuple for a composite primary key.
+/// `#[derive(Table)]` implements this by cloning the primary key fields.
+pub trait HasPrimaryKey: SqlTable {
+    type PrimaryKey;
+
+    fn primary_key(&self) -> Self::PrimaryKey;
+}
+
+/// `#[sql(belongs_to = "P", foreign_key = "...")]`, where the foreign key column of this table
+/// refers to the primary key of `P`
+pub trait BelongsTo<P: HasPrimaryKey>: SqlTable {
+    fn foreign_key() -> &'static str;
+
+    fn parent_key(&self) -> P::PrimaryKey;
+}
+
+/// `#[sql(has_many = "C", foreign_key = "...")]`, where the foreign key column of `C`
+/// refers to the primary key of this table
+pub trait HasMany<C: SqlTable>: HasPrimaryKey {
+    fn foreign_key() -> &'static str;
+
+    fn child_key(child: &C) -> Self::PrimaryKey;
+}
+
+/// `#[sql(has_many_through = "U", through = "J", foreign_key = "...", target_key = "...")]`,
+/// where the join table `J` has the foreign key column referring to the primary key of this table
+/// and the target key column referring to the primary key of `U`
+pub trait HasManyThrough<U: HasPrimaryKey>: HasPrimaryKey {
+    type Through: SqlTable;
+
+    fn foreign_key() -> &'static str;
+
+    fn target_key() -> &'static str;
+}
+
+// `table.column IN (:column_0, :column_1, ...)`
+fn in_keys<V: SqlValue<K>, K>(
+    dialect: &dyn Dialect,
+    table_name: &str,
+    column: &str,
+    keys: Vec<K>,
+) -> (Vec<String>, Vec<(String, V)>) {
+    let names = (0..keys.len())
+        .map(|i| format!("{}_{}", column, i))
+        .collect::<Vec<_>>();
+    let cond = format!(
+        "{}.{} IN ({})",
+        dialect.quote_qualified(table_name),
+        dialect.quote_identifier(column),
+        names
+            .iter()
+            .map(|name| format!(":{}", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    (
+        vec![cond],
+        names
+            .into_iter()
+            .zip(keys.into_iter().map(SqlValue::serialize))
+            .collect(),
+    )
+}
+
+// `table.column IN (:column_0, :column_1, ...)` on the table of T
+pub(crate) fn filter_in_keys<T: SqlTable, K>(
+    dialect: &dyn Dialect,
+    column: &str,
+    keys: Vec<K>,
+) -> QueryBuilder<T::ValueType>
+where
+    T::ValueType: SqlValue<K>,
+{
+    QueryBuilder::new().wheres(in_keys(
+        dialect,
+        &SqlTable::table_name(std::marker::PhantomData::<T>),
+        column,
+        keys,
+    ))
+}
+
+// Records of U joined with the join table rows whose foreign key is one of `owner_keys`
+pub(crate) fn filter_through<T, U, V>(
+    dialect: &dyn Dialect,
+    owner_keys: Vec<T::PrimaryKey>,
+) -> QueryBuilder<V>
+where
+    T: HasManyThrough<U>,
+    U: HasPrimaryKey,
+    V: SqlValue<T::PrimaryKey>,
+{
+    let join_table = SqlTable::table_name(std::marker::PhantomData::<T::Through>);
+
+    QueryBuilder::new()
+        .table(SqlTable::table_name(std::marker::PhantomData::<U>))
+        .inner_join(
+            join_table.clone(),
+            (single_primary_key::<U>(), T::target_key()),
+        )
+        .wheres(in_keys(
+            dialect,
+            &join_table,
+            <T as HasManyThrough<U>>::foreign_key(),
+            owner_keys,
+        ))
+}
+
+// `INSERT INTO join_table (foreign_key, target_key) VALUES (:foreign_key, :target_key_0), ...`
+pub(crate) fn attach_query<T, U, V>(
+    dialect: &dyn Dialect,
+    owner_key: T::PrimaryKey,
+    target_keys: Vec<U::PrimaryKey>,
+) -> (String, Params<V>)
+where
+    T: HasManyThrough<U>,
+    U: HasPrimaryKey,
+    V: SqlValue<T::PrimaryKey> + SqlValue<U::PrimaryKey>,
+{
+    let foreign_key = <T as HasManyThrough<U>>::foreign_key();
+    let target_key = T::target_key();
+
+    let mut binds = vec![(foreign_key.to_string(), SqlValue::serialize(owner_key))];
+    let mut rows = vec![];
+    for (i, key) in target_keys.into_iter().enumerate() {
+        let name = format!("{}_{}", target_key, i);
+        rows.push(format!("(:{}, :{})", foreign_key, name));
+        binds.push((name, SqlValue::serialize(key)));
+    }
+
+    let query = format!(
+        "INSERT INTO {} ({}, {}) VALUES {}",
+        dialect.quote_qualified(&SqlTable::table_name(
+            std::marker::PhantomData::<T::Through>
+        )),
+        dialect.quote_identifier(foreign_key),
+        dialect.quote_identifier(target_key),
+        rows.join(", ")
+    );
+
+    bind_placeholders(dialect, query, binds)
+}
+
+// `DELETE FROM join_table WHERE join_table.foreign_key = :foreign_key AND join_table.target_key IN (...)`
+pub(crate) fn detach_query<T, U, V>(
+    dialect: &dyn Dialect,
+    owner_key: T::PrimaryKey,
+    target_keys: Vec<U::PrimaryKey>,
+) -> (String, Params<V>)
+where
+    T: HasManyThrough<U>,
+    U: HasPrimaryKey,
+    V: SqlValue<T::PrimaryKey> + SqlValue<U::PrimaryKey>,
+{
+    let join_table = SqlTable::table_name(std::marker::PhantomData::<T::Through>);
+    let foreign_key = <T as HasManyThrough<U>>::foreign_key();
+
+    let (mut conds, mut binds) = in_keys(dialect, &join_table, T::target_key(), target_keys);
+    conds.insert(
+        0,
+        format!(
+            "{}.{} = :{}",
+            dialect.quote_qualified(&join_table),
+            dialect.quote_identifier(foreign_key),
+            foreign_key
+        ),
+    );
+    binds.insert(0, (foreign_key.to_string(), SqlValue::serialize(owner_key)));
+
+    let query = format!(
+        "DELETE FROM {} WHERE {}",
+        dialect.quote_qualified(&join_table),
+        conds.join(" AND ")
+    );
+
+    bind_placeholders(dialect, query, binds)
+}
+
+// `SELECT join_table.target_key FROM join_table WHERE join_table.foreign_key = :foreign_key`
+pub(crate) fn attached_keys_query<T, U, V>(
+    dialect: &dyn Dialect,
+    owner_key: T::PrimaryKey,
+) -> (String, Params<V>)
+where
+    T: HasManyThrough<U>,
+    U: HasPrimaryKey,
+    V: SqlValue<T::PrimaryKey>,
+{
+    let join_table = SqlTable::table_name(std::marker::PhantomData::<T::Through>);
+
+    QueryBuilder::new()
+        .table(join_table.clone())
+        .selects(vec![format!(
+            "{}.{} AS {}",
+            dialect.quote_qualified(&join_table),
+            dialect.quote_identifier(T::target_key()),
+            dialect.quote_identifier(RELATED_KEY)
+        )])
+        .wheres(in_keys(
+            dialect,
+            &join_table,
+            <T as HasManyThrough<U>>::foreign_key(),
+            vec![owner_key],
+        ))
+        .build_for(dialect)
+}
+
+// Column alias of the key which relates a loaded record to another record
+const RELATED_KEY: &str = "debil_related_key";
+
+// A key selected as RELATED_KEY
+pub(crate) struct RelatedKey<K, V>(pub K, std::marker::PhantomData<V>);
+
+impl<K, V: Clone + SqlValue<K>> SqlMapper for RelatedKey<K, V> {
+    type ValueType = V;
+
+    fn map_from_sql(mut values: std::collections::HashMap<String, V>) -> Self {
+        RelatedKey(
+            values.remove(RELATED_KEY).unwrap().deserialize(),
+            std::marker::PhantomData,
+        )
+    }
+}
+
+// A record selected together with RELATED_KEY
+struct Related<K, U> {
+    key: K,
+    record: U,
+}
+
+impl<K, U> SqlMapper for Related<K, U>
+where
+    U: SqlMapper,
+    U::ValueType: SqlValue<K>,
+{
+    type ValueType = U::ValueType;
+
+    fn map_from_sql(mut values: std::collections::HashMap<String, Self::ValueType>) -> Self {
+        let key = values.remove(RELATED_KEY).unwrap().deserialize();
+
+        Related {
+            key,
+            record: U::map_from_sql(values),
+        }
+    }
+}
+
+fn unique_keys<K: PartialEq>(keys: impl Iterator<Item = K>) -> Vec<K> {
+    let mut result = Vec::new();
+    for key in keys {
+        if !result.contains(&key) {
+            result.push(key);
+        }
+    }
+
+    result
+}
+
+/// Records to be loaded together with their related records, created by `SqlConn::eager_load`
+pub struct EagerLoad<'a, C: ?Sized, V, T> {
+    conn: &'a mut C,
+    builder: QueryBuilder<V>,
+    _table: std::marker::PhantomData<T>,
+}
+
+impl<'a, C, V, T> EagerLoad<'a, C, V, T>
+where
+    C: SqlConn<V> + ?Sized + Send,
+    V: 'static + Sync + Send,
+    T: SqlTable<ValueType = V> + Sync + Send,
+{
+    pub(crate) fn new(conn: &'a mut C, builder: QueryBuilder<V>) -> Self {
+        EagerLoad {
+            conn,
+            builder,
+            _table: std::marker::PhantomData,
+        }
+    }
+
+    /// Loads the records paired with their children, which are looked up by a query
+    /// per `max_placeholders()` parents
+    pub async fn with<Ch>(self) -> Result<Vec<(T, Vec<Ch>)>, C::Error>
+    where
+        T: HasMany<Ch>,
+        T::PrimaryKey: PartialEq + Clone,
+        Ch: SqlTable<ValueType = V> + Sync + Send,
+        V: SqlValue<T::PrimaryKey>,
+    {
+        let parents = self.conn.load::<T>(self.builder).await?;
+        if parents.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys = parents.iter().map(|p| p.primary_key()).collect::<Vec<_>>();
+        let mut children = vec![];
+        for chunk in unique_keys(keys.iter().cloned()).chunks(self.conn.max_placeholders()) {
+            let builder = filter_in_keys::<Ch, _>(
+                self.conn.dialect(),
+                <T as HasMany<Ch>>::foreign_key(),
+                chunk.to_vec(),
+            );
+            children.extend(self.conn.load::<Ch>(builder).await?);
+        }
+
+        let mut result = parents
+            .into_iter()
+            .map(|parent| (parent, vec![]))
+            .collect::<Vec<_>>();
+        for child in children {
+            let key = T::child_key(&child);
+            if let Some(i) = keys.iter().position(|k| k == &key) {
+                result[i].1.push(child);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Loads the records paired with the ones related through the join table,
+    /// which are looked up by a query per `max_placeholders()` owners
+    pub async fn with_through<U>(self) -> Result<Vec<(T, Vec<U>)>, C::Error>
+    where
+        T: HasManyThrough<U>,
+        T::PrimaryKey: PartialEq + Clone + Send + Sync,
+        U: HasPrimaryKey<ValueType = V> + Sync + Send,
+        V: SqlValue<T::PrimaryKey>,
+    {
+        let owners = self.conn.load::<T>(self.builder).await?;
+        if owners.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys = owners.iter().map(|o| o.primary_key()).collect::<Vec<_>>();
+        let mut related = vec![];
+        for chunk in unique_keys(keys.iter().cloned()).chunks(self.conn.max_placeholders()) {
+            let dialect = self.conn.dialect();
+            let builder =
+                filter_through::<T, U, V>(dialect, chunk.to_vec()).selects(vec![format!(
+                    "{}.{} AS {}",
+                    dialect.quote_qualified(&SqlTable::table_name(
+                        std::marker::PhantomData::<T::Through>
+                    )),
+                    dialect.quote_identifier(<T as HasManyThrough<U>>::foreign_key()),
+                    dialect.quote_identifier(RELATED_KEY)
+                )]);
+            related.extend(
+                self.conn
+                    .load2::<U, Related<T::PrimaryKey, U>>(builder)
+                    .await?,
+            );
+        }
+
+        let mut result = owners
+            .into_iter()
+            .map(|owner| (owner, vec![]))
+            .collect::<Vec<_>>();
+        for Related { key, record } in related {
+            if let Some(i) = keys.iter().position(|k| k == &key) {
+                result[i].1.push(record);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Loads the records paired with the one each of them belongs to,
+    /// which are looked up by a query per `max_placeholders()` parents
+    pub async fn with_parent<P>(self) -> Result<Vec<(T, Option<P>)>, C::Error>
+    where
+        T: BelongsTo<P>,
+        P: HasPrimaryKey<ValueType = V> + Clone + Sync + Send,
+        P::PrimaryKey: PartialEq + Clone,
+        V: SqlValue<P::PrimaryKey>,
+    {
+        let children = self.conn.load::<T>(self.builder).await?;
+        if children.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys = unique_keys(children.iter().map(|c| c.parent_key()));
+        let mut parents = vec![];
+        for chunk in keys.chunks(self.conn.max_placeholders()) {
+            let builder = filter_in_keys::<P, _>(
+                self.conn.dialect(),
+                &single_primary_key::<P>(),
+                chunk.to_vec(),
+            );
+            parents.extend(self.conn.load::<P>(builder).await?);
+        }
+
+        Ok(children
+            .into_iter()
+            .map(|child| {
+                let key = child.parent_key();
+                let parent = parents.iter().find(|p| p.primary_key() == key).cloned();
+
+                (child, parent)
+            })
+            .collect())
+    }
+}
+
+pub(crate) fn single_primary_key<T: SqlTable>() -> String {
+    let mut keys = SqlTable::primary_key_columns(std::marker::PhantomData::<T>);
+    if keys.len() != 1 {
+        panic!(
+            "relation: {} must have a single primary key",
+            SqlTable::table_name(std::marker::PhantomData::<T>)
+        );
+    }
+
+    keys.remove(0)
+}
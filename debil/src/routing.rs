@@ -0,0 +1,372 @@
+use crate::{Dialect, Params, Row, SqlConn, SqlMapper, SqlTable, Statement};
+use async_trait::async_trait;
+use std::time::{Duration, Instant};
+
+struct Replica<R> {
+    conn: R,
+    // when the replica last failed its health check, or None if it is up
+    down_since: Option<Instant>,
+}
+
+/// A connection which splits reads and writes between a primary and its replicas.
+///
+/// Plain SELECTs go to the replicas in round-robin, and everything else goes to the primary:
+/// `sql_exec`, batches, transaction control, locking reads such as `SELECT ... FOR UPDATE`,
+/// and queries with RETURNING. The default methods follow from this, e.g. `load` and `first`
+/// read from a replica while `create`, `save` and `create_table` write to the primary.
+/// `create_returning`, `update_returning` and `delete_returning` run wholly on the primary,
+/// including the SELECT which reads the record back without RETURNING.
+/// Migrations of a backend such as `mysql::DebilConn::migrate` run on `primary_mut()`.
+///
+/// When a read fails on a replica, the replica is checked with `SELECT 1`. A replica which
+/// fails the check is skipped for `retry_interval` and the read is retried on the next one,
+/// falling back to the primary when no replica is up. A replica which passes it returns
+/// the error of the query.
+///
+/// Reads in a transaction go to the primary, which runs the transaction. Replicas lag behind
+/// the primary, so by default a read right after a write outside of a transaction may not see
+/// it. See `read_your_writes`.
+pub struct RoutingConn<P, R = P> {
+    primary: P,
+    replicas: Vec<Replica<R>>,
+    next: usize,
+    retry_interval: Duration,
+    read_your_writes: Option<Duration>,
+    in_transaction: bool,
+    wrote_in_transaction: bool,
+    pinned_until: Option<Instant>,
+}
+
+impl<P, R> RoutingConn<P, R> {
+    pub fn new(primary: P, replicas: Vec<R>) -> Self {
+        RoutingConn {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|conn| Replica {
+                    conn,
+                    down_since: None,
+                })
+                .collect(),
+            next: 0,
+            retry_interval: Duration::from_secs(5),
+            read_your_writes: None,
+            in_transaction: false,
+            wrote_in_transaction: false,
+            pinned_until: None,
+        }
+    }
+
+    /// Sends reads to the primary for `window` after a write outside of a transaction or
+    /// the commit of a transaction which wrote. `window` should cover the replication lag.
+    pub fn read_your_writes(mut self, window: Duration) -> Self {
+        self.read_your_writes = Some(window);
+
+        self
+    }
+
+    /// How long a replica which failed its health check is skipped, 5 seconds by default
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+
+        self
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn primary_mut(&mut self) -> &mut P {
+        &mut self.primary
+    }
+
+    /// The `n`th replica, in the order passed to `new`
+    pub fn replica(&self, n: usize) -> &R {
+        &self.replicas[n].conn
+    }
+
+    /// Number of replicas which are not skipped for a failed health check
+    pub fn replicas_up(&self) -> usize {
+        self.replicas
+            .iter()
+            .filter(|r| r.down_since.is_none())
+            .count()
+    }
+
+    /// Whether reads go to the primary for `read_your_writes`
+    pub fn is_pinned(&self) -> bool {
+        self.wrote_in_transaction || self.pinned_until.is_some_and(|t| Instant::now() < t)
+    }
+
+    fn record_write(&mut self) {
+        let window = match self.read_your_writes {
+            Some(window) => window,
+            None => return,
+        };
+
+        if self.in_transaction {
+            self.wrote_in_transaction = true;
+        } else {
+            self.pin(window);
+        }
+    }
+
+    // Option::is_none_or needs Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    fn pin(&mut self, window: Duration) {
+        let until = Instant::now() + window;
+        if self.pinned_until.map_or(true, |t| t < until) {
+            self.pinned_until = Some(until);
+        }
+    }
+
+    fn end_transaction(&mut self, committed: bool) {
+        if let (true, true, Some(window)) =
+            (committed, self.wrote_in_transaction, self.read_your_writes)
+        {
+            self.pin(window);
+        }
+        self.in_transaction = false;
+        self.wrote_in_transaction = false;
+    }
+}
+
+impl<P, R> RoutingConn<P, R> {
+    // Runs the health check on the replica, and marks it up or down by the result
+    async fn check<V>(&mut self, n: usize) -> bool
+    where
+        V: 'static + Sync + Send + Clone,
+        R: SqlConn<V> + Send,
+    {
+        let up = self.replicas[n]
+            .conn
+            .sql_query::<Row<V>>("SELECT 1".to_string(), Params::new())
+            .await
+            .is_ok();
+        self.replicas[n].down_since = if up { None } else { Some(Instant::now()) };
+
+        up
+    }
+
+    /// Runs the health check on every replica now, including the ones which are skipped.
+    /// Returns the number of replicas which are up.
+    pub async fn check_replicas<V>(&mut self) -> usize
+    where
+        V: 'static + Sync + Send + Clone,
+        R: SqlConn<V> + Send,
+    {
+        for n in 0..self.replicas.len() {
+            self.check::<V>(n).await;
+        }
+
+        self.replicas_up()
+    }
+
+    // The next replica in round-robin which is up, rechecking the ones which have been
+    // skipped for `retry_interval`. None if no replica is up.
+    async fn next_replica<V>(&mut self) -> Option<usize>
+    where
+        V: 'static + Sync + Send + Clone,
+        R: SqlConn<V> + Send,
+    {
+        for _ in 0..self.replicas.len() {
+            let n = self.next % self.replicas.len();
+            self.next = self.next.wrapping_add(1);
+
+            let up = match self.replicas[n].down_since {
+                None => true,
+                Some(since) if since.elapsed() >= self.retry_interval => self.check::<V>(n).await,
+                Some(_) => false,
+            };
+            if up {
+                return Some(n);
+            }
+        }
+
+        None
+    }
+}
+
+// Only plain SELECTs may go to a replica. Locking reads need the primary, and so does a query
+// which writes, such as `WITH ... DELETE` or `INSERT ... RETURNING` run with `sql_query`.
+fn is_read_only(query: &str) -> bool {
+    let words = query
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_ascii_uppercase())
+        .collect::<Vec<_>>();
+    let has = |keyword: &str| words.iter().any(|w| w == keyword);
+    let has_sequence = |keywords: &[&str]| {
+        words
+            .windows(keywords.len())
+            .any(|ws| ws.iter().zip(keywords.iter()).all(|(w, k)| w == k))
+    };
+
+    match words.first().map(String::as_str) {
+        Some("SELECT") => {}
+        Some("WITH")
+            if !["INSERT", "UPDATE", "DELETE", "MERGE"]
+                .iter()
+                .any(|k| has(k)) => {}
+        _ => return false,
+    }
+
+    !has("RETURNING")
+        && !has_sequence(&["FOR", "UPDATE"])
+        && !has_sequence(&["FOR", "SHARE"])
+        && !has_sequence(&["FOR", "NO", "KEY", "UPDATE"])
+        && !has_sequence(&["FOR", "KEY", "SHARE"])
+        && !has_sequence(&["LOCK", "IN", "SHARE", "MODE"])
+}
+
+#[async_trait]
+impl<V, P, R> SqlConn<V> for RoutingConn<P, R>
+where
+    V: 'static + Sync + Send + Clone,
+    P: SqlConn<V> + Send,
+    R: SqlConn<V, Error = P::Error> + Send,
+{
+    type Error = P::Error;
+
+    fn dialect(&self) -> &dyn Dialect {
+        self.primary.dialect()
+    }
+
+    fn max_placeholders(&self) -> usize {
+        self.primary.max_placeholders()
+    }
+
+    fn max_rows_per_statement(&self) -> usize {
+        self.primary.max_rows_per_statement()
+    }
+
+    async fn sql_exec(&mut self, query: String, params: Params<V>) -> Result<u64, Self::Error> {
+        let result = self.primary.sql_exec(query, params).await;
+        self.record_write();
+
+        result
+    }
+
+    async fn sql_query<T: SqlMapper<ValueType = V> + Sync + Send>(
+        &mut self,
+        query: String,
+        params: Params<V>,
+    ) -> Result<Vec<T>, Self::Error> {
+        if !is_read_only(&query) {
+            let result = self.primary.sql_query::<T>(query, params).await;
+            self.record_write();
+
+            return result;
+        }
+        // a transaction only exists on the primary
+        if self.in_transaction || self.is_pinned() {
+            return self.primary.sql_query::<T>(query, params).await;
+        }
+
+        while let Some(n) = self.next_replica::<V>().await {
+            match self.replicas[n]
+                .conn
+                .sql_query::<T>(query.clone(), Params(params.0.clone()))
+                .await
+            {
+                Ok(rows) => return Ok(rows),
+                Err(err) => {
+                    // the replica is up, so the query itself failed
+                    if self.check::<V>(n).await {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+
+        self.primary.sql_query::<T>(query, params).await
+    }
+
+    async fn sql_batch_exec(
+        &mut self,
+        query: String,
+        params: Vec<Params<V>>,
+    ) -> Result<(), Self::Error> {
+        let result = self.primary.sql_batch_exec(query, params).await;
+        self.record_write();
+
+        result
+    }
+
+    // The fallbacks without RETURNING read the record back after writing it, e.g. with
+    // `LAST_INSERT_ID()`, which only the connection which wrote can do without lag
+    async fn create_returning<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<T, Self::Error> {
+        let result = self.primary.create_returning(data).await;
+        self.record_write();
+
+        result
+    }
+
+    async fn update_returning<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<T, Self::Error> {
+        let result = self.primary.update_returning(data).await;
+        self.record_write();
+
+        result
+    }
+
+    async fn delete_returning<T: SqlTable<ValueType = V> + Sync + Send>(
+        &mut self,
+        data: T,
+    ) -> Result<T, Self::Error> {
+        let result = self.primary.delete_returning(data).await;
+        self.record_write();
+
+        result
+    }
+
+    // Statements are prepared on the primary, and run wherever their query is routed
+    async fn prepare(&mut self, query: String) -> Result<Statement, Self::Error> {
+        self.primary.prepare(query).await
+    }
+
+    async fn start_transaction(&mut self) -> Result<(), Self::Error> {
+        self.primary.start_transaction().await?;
+        self.in_transaction = true;
+
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        let result = self.primary.commit().await;
+        self.end_transaction(result.is_ok());
+
+        result
+    }
+
+    async fn rollback(&mut self) -> Result<(), Self::Error> {
+        let result = self.primary.rollback().await;
+        self.end_transaction(false);
+
+        result
+    }
+}
+
+#[test]
+fn route_read_only_queries() {
+    assert!(is_read_only("SELECT * FROM user WHERE user.id = :id"));
+    assert!(is_read_only(
+        "WITH t AS (SELECT id FROM a) SELECT * FROM t ORDER BY updated_at"
+    ));
+    assert!(is_read_only("select for_update from t"));
+
+    assert!(!is_read_only("INSERT INTO user (id) VALUES (:id)"));
+    assert!(!is_read_only("SELECT * FROM user FOR UPDATE"));
+    assert!(!is_read_only("SELECT * FROM user LOCK IN SHARE MODE"));
+    assert!(!is_read_only(
+        "WITH t AS (DELETE FROM a RETURNING id) SELECT * FROM t"
+    ));
+    assert!(!is_read_only(
+        "INSERT INTO user (name) VALUES (:name) RETURNING id"
+    ));
+}
@@ -1,8 +1,13 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate as debil;
-use crate::{HasNotFound, SqlConn, SqlValue};
+use crate::hook::{query_span, traced, traced_stream};
+use crate::{
+    Dialect, HasNotFound, ManageConnection, Pool, PoolConfig, Row, SqlConn, SqlLiteral, SqlNull,
+    SqlValue, SqliteDialect,
+};
 use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 
 #[derive(Clone)]
 pub struct SqliteValue(rusqlite::types::Value);
@@ -13,6 +18,59 @@ impl rusqlite::ToSql for SqliteValue {
     }
 }
 
+impl SqlNull for SqliteValue {
+    fn is_null(&self) -> bool {
+        self.0 == rusqlite::types::Value::Null
+    }
+}
+
+impl SqlLiteral for SqliteValue {
+    fn to_sql_literal(&self) -> String {
+        use rusqlite::types::Value;
+
+        match &self.0 {
+            Value::Null => "NULL".to_string(),
+            Value::Integer(v) => v.to_string(),
+            Value::Real(v) => v.to_string(),
+            Value::Text(v) => crate::types::quote_string(v),
+            Value::Blob(v) => format!(
+                "X'{}'",
+                v.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+            ),
+        }
+    }
+}
+
+// Records of the SQLite backend can be tested on the memory backend as they are
+#[cfg(feature = "memory")]
+impl crate::memory::AsMemoryValue for SqliteValue {
+    fn to_memory_value(&self) -> crate::memory::MemoryValue {
+        use crate::memory::MemoryValue;
+        use rusqlite::types::Value;
+
+        match &self.0 {
+            Value::Null => MemoryValue::Null,
+            Value::Integer(v) => MemoryValue::Integer(*v),
+            Value::Real(v) => MemoryValue::Real(*v),
+            Value::Text(v) => MemoryValue::Text(v.clone()),
+            Value::Blob(v) => MemoryValue::Blob(v.clone()),
+        }
+    }
+
+    fn from_memory_value(value: crate::memory::MemoryValue) -> Self {
+        use crate::memory::MemoryValue;
+        use rusqlite::types::Value;
+
+        SqliteValue(match value {
+            MemoryValue::Null => Value::Null,
+            MemoryValue::Integer(v) => Value::Integer(v),
+            MemoryValue::Real(v) => Value::Real(v),
+            MemoryValue::Text(v) => Value::Text(v),
+            MemoryValue::Blob(v) => Value::Blob(v),
+        })
+    }
+}
+
 impl SqlValue<()> for SqliteValue {
     fn column_type(_: std::marker::PhantomData<()>, _size: i32) -> String {
         "NULL".to_string()
@@ -22,9 +80,7 @@ impl SqlValue<()> for SqliteValue {
         SqliteValue(rusqlite::types::Value::Null)
     }
 
-    fn deserialize(self) -> () {
-        ()
-    }
+    fn deserialize(self) {}
 }
 
 impl SqlValue<i64> for SqliteValue {
@@ -95,16 +151,81 @@ impl SqlValue<Vec<u8>> for SqliteValue {
     }
 }
 
-fn to_params(params: &debil::Params<SqliteValue>) -> Vec<(&str, &dyn rusqlite::ToSql)> {
-    if params.0.len() == 0 {
-        vec![]
-    } else {
-        params
-            .0
-            .iter()
-            .map(|(k, v)| (k.as_str(), &v.0 as &dyn rusqlite::ToSql))
-            .collect::<Vec<_>>()
+// rusqlite looks up named parameters including their prefix, while debil generates bare names
+fn to_params(params: &debil::Params<SqliteValue>) -> Vec<(String, &dyn rusqlite::ToSql)> {
+    params
+        .0
+        .iter()
+        .map(|(k, v)| {
+            (
+                if k.starts_with(':') {
+                    k.clone()
+                } else {
+                    format!(":{}", k)
+                },
+                &v.0 as &dyn rusqlite::ToSql,
+            )
+        })
+        .collect::<Vec<_>>()
+}
+
+fn as_named_params<'a>(
+    params: &'a [(String, &'a dyn rusqlite::ToSql)],
+) -> Vec<(&'a str, &'a dyn rusqlite::ToSql)> {
+    params.iter().map(|(k, v)| (k.as_str(), *v)).collect()
+}
+
+fn execute(
+    conn: &rusqlite::Connection,
+    query: &str,
+    params: &debil::Params<SqliteValue>,
+) -> rusqlite::Result<usize> {
+    let params = to_params(params);
+    conn.prepare_cached(query)?
+        .execute(as_named_params(&params).as_slice())
+}
+
+// Passes each row to `f`, which the caller maps into a record. Reading stops when `f` returns false.
+fn for_each_row(
+    conn: &rusqlite::Connection,
+    query: &str,
+    params: &debil::Params<SqliteValue>,
+    mut f: impl FnMut(Row<SqliteValue>) -> bool,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached(query)?;
+    let columns = stmt
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Arc<[String]>>();
+    let params = to_params(params);
+    let mut rows = stmt.query(as_named_params(&params).as_slice())?;
+
+    while let Some(row) = rows.next()? {
+        let values = (0..columns.len())
+            .map(|i| row.get(i).map(SqliteValue))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if !f(Row::new(columns.clone(), values)) {
+            break;
+        }
     }
+
+    Ok(())
+}
+
+fn query_rows(
+    conn: &rusqlite::Connection,
+    query: &str,
+    params: &debil::Params<SqliteValue>,
+) -> rusqlite::Result<Vec<Row<SqliteValue>>> {
+    let mut vs = Vec::new();
+    for_each_row(conn, query, params, |m| {
+        vs.push(m);
+        true
+    })?;
+
+    Ok(vs)
 }
 
 #[derive(Debug)]
@@ -112,12 +233,19 @@ pub enum Error {
     NotFoundError,
     SqliteError(rusqlite::Error),
     TokioError(tokio::task::JoinError),
+    /// The background thread of `AsyncDebilConn` has stopped
+    ConnectionClosed,
+    Unsupported(debil::Unsupported),
 }
 
 impl HasNotFound for Error {
     fn not_found() -> Self {
         Self::NotFoundError
     }
+
+    fn unsupported(err: debil::Unsupported) -> Self {
+        Self::Unsupported(err)
+    }
 }
 
 pub struct DebilConn {
@@ -128,57 +256,83 @@ impl DebilConn {
     pub fn new(conn: rusqlite::Connection) -> Self {
         Self { conn }
     }
+
+    /// Capacity of the LRU cache of prepared statements, which is 16 by default
+    pub fn set_statement_cache_capacity(&self, capacity: usize) {
+        self.conn.set_prepared_statement_cache_capacity(capacity);
+    }
 }
 
 // This impl uses tokio::task::block_in_place, which could lead to a problem in some specific situations;
+// it panics on a current-thread runtime for example. AsyncDebilConn has no such restriction.
+//
+// sql_query_stream is left to the default, which collects every row before the first one is
+// streamed: the rows of rusqlite borrow the statement, which can't be kept across polls here.
+// AsyncDebilConn reads the rows on demand instead.
 #[async_trait]
 impl SqlConn<SqliteValue> for DebilConn {
     type Error = Error;
 
+    fn dialect(&self) -> &dyn Dialect {
+        &SqliteDialect
+    }
+
     async fn sql_exec(
         &mut self,
         query: String,
         params: debil::Params<SqliteValue>,
     ) -> Result<u64, Error> {
-        let rows = tokio::task::block_in_place(move || {
-            self.conn
-                .execute(query.as_str(), to_params(&params).as_slice())
+        let conn = &mut self.conn;
+        let rows = traced(query_span("sqlite", &query), async move {
+            tokio::task::block_in_place(|| execute(conn, &query, &params))
+                .map_err(Error::SqliteError)
         })
-        .map_err(|err| Error::SqliteError(err))?;
+        .await?;
 
         Ok(rows as u64)
     }
 
+    async fn prepare(&mut self, query: String) -> Result<debil::Statement, Self::Error> {
+        tokio::task::block_in_place(|| self.conn.prepare_cached(&query).map(|_| ()))
+            .map_err(Error::SqliteError)?;
+
+        Ok(debil::Statement::new(query))
+    }
+
     async fn sql_query<T: debil::SqlMapper<ValueType = SqliteValue> + Sync + Send>(
         &mut self,
         query: String,
         params: debil::Params<SqliteValue>,
     ) -> Result<Vec<T>, Self::Error> {
-        let vs = tokio::task::block_in_place(move || {
-            let mut stmt = self.conn.prepare(query.as_str())?;
-            let mut rows = stmt.query(to_params(&params).as_slice())?;
-
-            let mut vs = Vec::new();
-            while let Some(row) = rows.next()? {
-                let stmt = row.as_ref();
-
-                // TODO: cache names
-                let mut m = HashMap::new();
-                for name in stmt.column_names() {
-                    m.insert(
-                        name.to_string(),
-                        SqliteValue(row.get(stmt.column_index(name)?)?),
-                    );
-                }
+        let conn = &mut self.conn;
+        let rows = traced(query_span("sqlite", &query), async move {
+            tokio::task::block_in_place(|| query_rows(conn, &query, &params))
+                .map_err(Error::SqliteError)
+        })
+        .await?;
 
-                vs.push(debil::map_from_sql::<T>(m));
-            }
+        Ok(rows.into_iter().map(debil::map_from_row::<T>).collect())
+    }
 
-            Ok(vs)
-        })
-        .map_err(|err| Error::SqliteError(err))?;
+    async fn start_transaction(&mut self) -> Result<(), Self::Error> {
+        self.sql_exec("BEGIN".to_string(), debil::Params::new())
+            .await?;
+
+        Ok(())
+    }
 
-        Ok(vs)
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        self.sql_exec("COMMIT".to_string(), debil::Params::new())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), Self::Error> {
+        self.sql_exec("ROLLBACK".to_string(), debil::Params::new())
+            .await?;
+
+        Ok(())
     }
 
     async fn sql_batch_exec(
@@ -194,4 +348,321 @@ impl SqlConn<SqliteValue> for DebilConn {
     }
 }
 
+type Command = Box<dyn FnOnce(&mut rusqlite::Connection) + Send>;
+
+// Rows read ahead of the consumer of AsyncDebilConn::sql_query_stream.
+// The background thread waits while the buffer is full.
+const STREAM_BUFFER: usize = 64;
+
+/// A connection owned by a dedicated background thread, which runs the queries sent through
+/// a channel. Unlike `DebilConn`, this works on any tokio runtime flavor without blocking
+/// a worker thread. Clones share the same connection, but while a clone has a transaction open,
+/// the statements of the other clones wait until it is committed or rolled back.
+pub struct AsyncDebilConn {
+    sender: std::sync::mpsc::Sender<Command>,
+    transaction_lock: Arc<tokio::sync::Mutex<()>>,
+    // Held from start_transaction until commit or rollback
+    transaction: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl Clone for AsyncDebilConn {
+    fn clone(&self) -> Self {
+        AsyncDebilConn {
+            sender: self.sender.clone(),
+            transaction_lock: self.transaction_lock.clone(),
+            transaction: None,
+        }
+    }
+}
+
+impl Drop for AsyncDebilConn {
+    // The connection outlives this clone, so its transaction must not leak into the others
+    fn drop(&mut self) {
+        if self.transaction.is_some() {
+            let _ = self.sender.send(Box::new(|conn| {
+                let _ = conn.execute_batch("ROLLBACK");
+            }));
+        }
+    }
+}
+
+impl AsyncDebilConn {
+    /// Moves the connection to a new background thread, which stops when every clone is dropped
+    pub fn new(mut conn: rusqlite::Connection) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel::<Command>();
+        std::thread::Builder::new()
+            .name("debil-sqlite".to_string())
+            .spawn(move || {
+                for command in receiver {
+                    command(&mut conn);
+                }
+            })
+            .expect("failed to spawn a thread for the sqlite connection");
+
+        AsyncDebilConn {
+            sender,
+            transaction_lock: Arc::new(tokio::sync::Mutex::new(())),
+            transaction: None,
+        }
+    }
+
+    pub async fn open(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let conn = tokio::task::spawn_blocking(move || rusqlite::Connection::open(path))
+            .await
+            .map_err(Error::TokioError)?
+            .map_err(Error::SqliteError)?;
+
+        Ok(AsyncDebilConn::new(conn))
+    }
+
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Ok(AsyncDebilConn::new(
+            rusqlite::Connection::open_in_memory().map_err(Error::SqliteError)?,
+        ))
+    }
+
+    /// Capacity of the LRU cache of prepared statements, which is 16 by default
+    pub async fn set_statement_cache_capacity(&self, capacity: usize) -> Result<(), Error> {
+        self.call(move |conn| {
+            conn.set_prepared_statement_cache_capacity(capacity);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Runs `f` with the connection on the background thread
+    pub async fn call<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut rusqlite::Connection) -> rusqlite::Result<R> + Send + 'static,
+    ) -> Result<R, Error> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.send(Box::new(move |conn| {
+            // the caller may have gone away, in which case nobody needs the result
+            let _ = sender.send(f(conn));
+        }))
+        .await?;
+
+        receiver
+            .await
+            .map_err(|_| Error::ConnectionClosed)?
+            .map_err(Error::SqliteError)
+    }
+
+    // Commands run in the order they are sent, so the lock is only held while sending
+    async fn send(&self, command: Command) -> Result<(), Error> {
+        let _guard = match self.transaction {
+            Some(_) => None,
+            None => Some(self.transaction_lock.lock().await),
+        };
+
+        self.sender
+            .send(command)
+            .map_err(|_| Error::ConnectionClosed)
+    }
+}
+
+#[async_trait]
+impl SqlConn<SqliteValue> for AsyncDebilConn {
+    type Error = Error;
+
+    fn dialect(&self) -> &dyn Dialect {
+        &SqliteDialect
+    }
+
+    async fn sql_exec(
+        &mut self,
+        query: String,
+        params: debil::Params<SqliteValue>,
+    ) -> Result<u64, Error> {
+        let rows = traced(
+            query_span("sqlite", &query),
+            self.call(move |conn| execute(conn, &query, &params)),
+        )
+        .await?;
+
+        Ok(rows as u64)
+    }
+
+    async fn prepare(&mut self, query: String) -> Result<debil::Statement, Self::Error> {
+        let statement = debil::Statement::new(query.clone());
+        self.call(move |conn| conn.prepare_cached(&query).map(|_| ()))
+            .await?;
+
+        Ok(statement)
+    }
+
+    async fn sql_query<T: debil::SqlMapper<ValueType = SqliteValue> + Sync + Send>(
+        &mut self,
+        query: String,
+        params: debil::Params<SqliteValue>,
+    ) -> Result<Vec<T>, Self::Error> {
+        let rows = traced(
+            query_span("sqlite", &query),
+            self.call(move |conn| query_rows(conn, &query, &params)),
+        )
+        .await?;
+
+        Ok(rows.into_iter().map(debil::map_from_row::<T>).collect())
+    }
+
+    // The background thread sends rows through a bounded channel as they are read, so it holds
+    // the connection until the stream is exhausted or dropped.
+    fn sql_query_stream<'a, T: debil::SqlMapper<ValueType = SqliteValue> + Sync + Send + 'a>(
+        &'a mut self,
+        query: String,
+        params: debil::Params<SqliteValue>,
+    ) -> BoxStream<'a, Result<T, Self::Error>> {
+        let span = query_span("sqlite", &query);
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        let command: Command = Box::new(move |conn| {
+            // blocking_send fails when the stream is dropped, and then reading stops
+            let result = for_each_row(conn, &query, &params, |m| {
+                sender.blocking_send(Ok(m)).is_ok()
+            });
+            if let Err(err) = result {
+                let _ = sender.blocking_send(Err(err));
+            }
+        });
+        let sent = self.send(command);
+        let rows = futures::stream::once(async move { sent.await.map(|()| receiver) })
+            .map_ok(|receiver| {
+                futures::stream::unfold(receiver, |mut receiver| async move {
+                    receiver.recv().await.map(|row| (row, receiver))
+                })
+                .map(|row| {
+                    row.map(debil::map_from_row::<T>)
+                        .map_err(Error::SqliteError)
+                })
+            })
+            .try_flatten()
+            .boxed();
+
+        traced_stream(span, rows)
+    }
+
+    // The guard is taken before BEGIN, so that statements of the other clones queued before
+    // it don't wait for this transaction, and those queued after it don't run inside of it
+    async fn start_transaction(&mut self) -> Result<(), Self::Error> {
+        let acquired = self.transaction.is_none();
+        if acquired {
+            self.transaction = Some(self.transaction_lock.clone().lock_owned().await);
+        }
+
+        let result = self
+            .sql_exec("BEGIN".to_string(), debil::Params::new())
+            .await;
+        // A nested BEGIN fails, but keeps the transaction already open by this clone
+        if result.is_err() && acquired {
+            self.transaction = None;
+        }
+        result?;
+
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        let result = self
+            .sql_exec("COMMIT".to_string(), debil::Params::new())
+            .await;
+        // A failed COMMIT may leave the transaction open, to be rolled back
+        if self.call(|conn| Ok(conn.is_autocommit())).await? {
+            self.transaction = None;
+        }
+        result?;
+
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), Self::Error> {
+        let result = self
+            .sql_exec("ROLLBACK".to_string(), debil::Params::new())
+            .await;
+        self.transaction = None;
+        result?;
+
+        Ok(())
+    }
+
+    async fn sql_batch_exec(
+        &mut self,
+        query: String,
+        params_vec: Vec<debil::Params<SqliteValue>>,
+    ) -> Result<(), Self::Error> {
+        traced(
+            query_span("sqlite", &query),
+            self.call(move |conn| {
+                for params in params_vec {
+                    execute(conn, &query, &params)?;
+                }
+
+                Ok(())
+            }),
+        )
+        .await
+    }
+}
+
+/// Opens connections to a database file in WAL mode, so that readers don't wait for a writer.
+/// The connections are `DebilConn`s, so `load_stream` on them collects every row first.
+pub struct ConnectionManager {
+    path: std::path::PathBuf,
+    busy_timeout: std::time::Duration,
+    statement_cache_size: Option<usize>,
+}
+
+#[async_trait]
+impl ManageConnection for ConnectionManager {
+    type Connection = DebilConn;
+    type Error = Error;
+
+    async fn connect(&self) -> Result<DebilConn, Error> {
+        let path = self.path.clone();
+        let busy_timeout = self.busy_timeout;
+        let statement_cache_size = self.statement_cache_size;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = rusqlite::Connection::open(path)?;
+            // a writer waits for another writer instead of failing with SQLITE_BUSY
+            conn.busy_timeout(busy_timeout)?;
+            conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+            if let Some(size) = statement_cache_size {
+                conn.set_prepared_statement_cache_capacity(size);
+            }
+
+            Ok(DebilConn::new(conn))
+        })
+        .await
+        .map_err(Error::TokioError)?
+        .map_err(Error::SqliteError)
+    }
+
+    async fn is_valid(&self, conn: DebilConn) -> Result<DebilConn, Error> {
+        tokio::task::spawn_blocking(move || {
+            conn.conn.query_row("SELECT 1", [], |_| Ok(()))?;
+
+            Ok(conn)
+        })
+        .await
+        .map_err(Error::TokioError)?
+        .map_err(Error::SqliteError)
+    }
+}
+
+pub type DebilPool = Pool<ConnectionManager>;
+
+impl Pool<ConnectionManager> {
+    /// Pool of connections to the database file at `path`, which is created if missing
+    pub fn open(path: impl Into<std::path::PathBuf>, config: PoolConfig) -> DebilPool {
+        Pool::new(
+            ConnectionManager {
+                path: path.into(),
+                busy_timeout: std::time::Duration::from_secs(5),
+                statement_cache_size: config.statement_cache_size,
+            },
+            config,
+        )
+    }
+}
+
 pub type DefaultSqlValue = SqliteValue;
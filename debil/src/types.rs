@@ -1,27 +1,41 @@
-#[derive(Clone, Debug, PartialEq)]
+use crate::{Dialect, GenericDialect, Unsupported};
+
+/// A query and the values of its placeholders
+pub type QueryWithParams<V> = (String, Vec<(String, V)>);
+
+#[derive(Clone, Debug, PartialEq, Default)]
 pub struct FieldAttribute {
     pub size: Option<i32>,
     pub unique: Option<bool>,
     pub not_null: Option<bool>,
+    pub auto_increment: Option<bool>,
 }
 
-impl Default for FieldAttribute {
-    fn default() -> Self {
-        FieldAttribute {
-            size: None,
-            unique: None,
-            not_null: None,
-        }
-    }
+pub fn create_column_query(
+    column_name: String,
+    column_type: String,
+    attr: FieldAttribute,
+) -> String {
+    create_column_query_for(&GenericDialect, column_name, column_type, attr)
 }
 
-pub fn create_column_query(
+pub fn create_column_query_for(
+    dialect: &dyn Dialect,
     column_name: String,
     column_type: String,
     attr: FieldAttribute,
 ) -> String {
+    let column_type = if attr.auto_increment.unwrap_or(false) {
+        dialect.auto_increment(&column_type)
+    } else {
+        column_type
+    };
+
     [
-        &[column_name.as_str(), column_type.as_str()],
+        &[
+            dialect.quote_identifier(&column_name).as_str(),
+            column_type.as_str(),
+        ],
         vec![
             if attr.unique.unwrap_or(false) {
                 Some("UNIQUE")
@@ -43,9 +57,100 @@ pub fn create_column_query(
     .join(" ")
 }
 
+/// A row of a query result. The column names are computed once per statement and shared
+/// by all of its rows, and values are looked up by index or by name without building a HashMap.
+#[derive(Clone, Debug)]
+pub struct Row<V> {
+    columns: std::sync::Arc<[String]>,
+    // a value becomes None when it is taken
+    values: Vec<Option<V>>,
+}
+
+// A Row maps to itself, e.g. to read the rows of EXPLAIN whose columns differ between databases
+impl<V: Clone> SqlMapper for Row<V> {
+    type ValueType = V;
+
+    fn map_from_sql(values: std::collections::HashMap<String, V>) -> Self {
+        let (columns, values): (Vec<_>, Vec<_>) = values.into_iter().unzip();
+
+        Row::new(columns.into(), values)
+    }
+
+    fn map_from_row(row: Row<V>) -> Self {
+        row
+    }
+}
+
+impl<V> Row<V> {
+    pub fn new(columns: std::sync::Arc<[String]>, values: Vec<V>) -> Row<V> {
+        assert_eq!(
+            columns.len(),
+            values.len(),
+            "Row::new: the number of columns and values differ"
+        );
+
+        Row {
+            columns,
+            values: values.into_iter().map(Some).collect(),
+        }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Index of the first column named `name`
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&V> {
+        self.values.get(idx).and_then(Option::as_ref)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&V> {
+        self.index_of(name).and_then(|idx| self.get(idx))
+    }
+
+    /// Moves the value out of the row, which `get` no longer returns
+    pub fn take(&mut self, idx: usize) -> Option<V> {
+        self.values.get_mut(idx).and_then(Option::take)
+    }
+
+    pub fn take_by_name(&mut self, name: &str) -> Option<V> {
+        self.index_of(name).and_then(|idx| self.take(idx))
+    }
+
+    /// Values keyed by column names, where the first one wins among columns of the same name
+    pub fn into_map(self) -> std::collections::HashMap<String, V> {
+        let mut result = std::collections::HashMap::with_capacity(self.values.len());
+        for (column, value) in self.columns.iter().zip(self.values) {
+            if let Some(value) = value {
+                result.entry(column.clone()).or_insert(value);
+            }
+        }
+
+        result
+    }
+}
+
 pub trait SqlMapper: Sized {
     type ValueType: Clone;
     fn map_from_sql(_: std::collections::HashMap<String, Self::ValueType>) -> Self;
+
+    /// Maps a row directly, which backends use instead of `map_from_sql`.
+    /// `#[derive(Table)]` overrides this to take values by name without building a HashMap.
+    fn map_from_row(row: Row<Self::ValueType>) -> Self {
+        Self::map_from_sql(row.into_map())
+    }
 }
 
 pub trait SqlTable: SqlMapper {
@@ -66,23 +171,12 @@ pub trait SqlTable: SqlMapper {
         index_name: &'static str,
         index_keys: Vec<&'static str>,
     ) -> String {
-        let schema = SqlTable::schema_of(ty);
-        let table_name = SqlTable::table_name(ty);
-        // search if specified keys exist
-        for key in index_keys.iter() {
-            if !schema
-                .iter()
-                .map(|(column_name, _, _)| column_name.as_str())
-                .collect::<Vec<&str>>()
-                .contains(key)
-            {
-                panic!("index: column {} is not field of {}", key, table_name)
-            }
-        }
+        check_index_keys(ty, &index_keys);
+
         format!(
             "CREATE INDEX IF NOT EXISTS {} ON {}({});",
             index_name,
-            table_name,
+            SqlTable::table_name(ty),
             index_keys.join(","),
         )
     }
@@ -92,81 +186,201 @@ pub trait SqlTable: SqlMapper {
         index_name: &'static str,
         index_keys: Vec<&'static str>,
     ) -> String {
-        let schema = SqlTable::schema_of(ty);
-        let table_name = SqlTable::table_name(ty);
-        // search if specified keys exist
-        for key in index_keys.iter() {
-            if !schema
-                .iter()
-                .map(|(column_name, _, _)| column_name.as_str())
-                .collect::<Vec<&str>>()
-                .contains(key)
-            {
-                panic!("index: column {} is not field of {}", key, table_name)
-            }
-        }
+        check_index_keys(ty, &index_keys);
 
         format!(
             "CREATE UNIQUE INDEX IF NOT EXISTS {} ON {}({});",
             index_name,
-            table_name,
+            SqlTable::table_name(ty),
             index_keys.join(","),
         )
     }
 
+    fn create_index_query_for(
+        ty: std::marker::PhantomData<Self>,
+        dialect: &dyn Dialect,
+        index_name: &'static str,
+        index_keys: Vec<&'static str>,
+        unique: bool,
+    ) -> String {
+        check_index_keys(ty, &index_keys);
+
+        dialect.create_index(&SqlTable::table_name(ty), index_name, &index_keys, unique)
+    }
+
     fn create_table_query(ty: std::marker::PhantomData<Self>) -> String {
-        let schema = SqlTable::schema_of(ty);
+        create_table_query_with(
+            ty,
+            &GenericDialect,
+            SqlTable::constraint_primary_key_query(ty),
+        )
+    }
+
+    /// `constraint_primary_key_query` for the dialect. The constraint is named `<table>_pkey`,
+    /// as constraint names must be unique per schema in PostgreSQL.
+    fn constraint_primary_key_query_for(
+        ty: std::marker::PhantomData<Self>,
+        dialect: &dyn Dialect,
+    ) -> String {
+        let table_name = SqlTable::table_name(ty);
+        let table_name = table_name.rsplit('.').next().unwrap_or_default();
 
         format!(
-            "CREATE TABLE IF NOT EXISTS {} ({}, {})",
-            SqlTable::table_name(ty),
-            schema
-                .into_iter()
-                .map(|(name, typ, attr)| create_column_query(name, typ, attr))
+            "CONSTRAINT {} PRIMARY KEY({})",
+            dialect.quote_identifier(&format!("{}_pkey", table_name)),
+            SqlTable::primary_key_columns(ty)
+                .iter()
+                .map(|k| dialect.quote_identifier(k))
                 .collect::<Vec<_>>()
-                .as_slice()
-                .join(", "),
-            SqlTable::constraint_primary_key_query(ty),
+                .join(","),
+        )
+    }
+
+    fn create_table_query_for(ty: std::marker::PhantomData<Self>, dialect: &dyn Dialect) -> String {
+        create_table_query_with(
+            ty,
+            dialect,
+            SqlTable::constraint_primary_key_query_for(ty, dialect),
         )
     }
 
     fn insert_query_with_params(self) -> (String, Vec<(String, Self::ValueType)>) {
-        let pairs = self.map_to_sql();
-        let keys = pairs.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        self.insert_query_with_params_for(&GenericDialect)
+    }
+
+    fn insert_query_with_params_for(
+        self,
+        dialect: &dyn Dialect,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        insert_query::<Self>(dialect, self.map_to_sql())
+    }
+
+    /// INSERT without auto increment columns, so that the database generates their values
+    fn insert_generated_query_with_params(
+        self,
+        dialect: &dyn Dialect,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        let generated = auto_increment_columns::<Self>();
+
+        insert_query::<Self>(
+            dialect,
+            self.map_to_sql()
+                .into_iter()
+                .filter(|(k, _)| !generated.contains(k))
+                .collect(),
+        )
+    }
+
+    fn upsert_query_with_params(
+        self,
+        dialect: &dyn Dialect,
+        update_columns: Vec<String>,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        let (insert_query, pairs) = self.insert_query_with_params_for(dialect);
+        let upsert_clause = dialect.upsert_clause(
+            &Self::primary_key_columns(std::marker::PhantomData::<Self>),
+            &update_columns,
+        );
+
+        (with_clause(insert_query, upsert_clause), pairs)
+    }
+
+    /// `insert_generated_query_with_params` with RETURNING every column,
+    /// so that values generated by the database can be read back
+    fn insert_returning_query_with_params(
+        self,
+        dialect: &dyn Dialect,
+    ) -> Result<QueryWithParams<Self::ValueType>, Unsupported> {
+        with_returning::<Self>(dialect, self.insert_generated_query_with_params(dialect))
+    }
+
+    // Placeholders are suffixed with the row index, e.g. `:name_0, :name_1, ...`
+    fn insert_all_query_with_params(
+        datas: Vec<Self>,
+        dialect: &dyn Dialect,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        let mut keys = Vec::new();
+        let mut rows = Vec::new();
+        let mut params = Vec::new();
+
+        for (i, data) in datas.into_iter().enumerate() {
+            let pairs = data.map_to_sql();
+            if keys.is_empty() {
+                keys = pairs
+                    .iter()
+                    .map(|(k, _)| dialect.quote_identifier(k))
+                    .collect::<Vec<_>>();
+            }
+
+            let mut placeholders = Vec::new();
+            for (k, v) in pairs {
+                let name = format!("{}_{}", k, i);
+                placeholders.push(dialect.placeholder(&name, params.len()));
+                params.push((name, v));
+            }
+            rows.push(format!("({})", placeholders.join(", ")));
+        }
 
         (
             format!(
-                "INSERT INTO {} ({}) VALUES ({})",
-                Self::table_name(std::marker::PhantomData::<Self>),
-                keys.iter()
-                    .map(|k| k.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", "),
-                keys.iter()
-                    .map(|s| format!(":{}", s))
-                    .collect::<Vec<_>>()
-                    .as_slice()
-                    .join(", "),
+                "INSERT INTO {} ({}) VALUES {}",
+                dialect.quote_qualified(&Self::table_name(std::marker::PhantomData::<Self>)),
+                keys.join(", "),
+                rows.join(", "),
             ),
-            pairs,
+            params,
         )
     }
 
+    fn upsert_all_query_with_params(
+        datas: Vec<Self>,
+        dialect: &dyn Dialect,
+        update_columns: Vec<String>,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        let (insert_query, params) = Self::insert_all_query_with_params(datas, dialect);
+        let upsert_clause = dialect.upsert_clause(
+            &Self::primary_key_columns(std::marker::PhantomData::<Self>),
+            &update_columns,
+        );
+
+        (with_clause(insert_query, upsert_clause), params)
+    }
+
     fn update_query_with_params(self) -> (String, Vec<(String, Self::ValueType)>) {
+        self.update_query_with_params_for(&GenericDialect)
+    }
+
+    fn update_query_with_params_for(
+        self,
+        dialect: &dyn Dialect,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        let table_name = Self::table_name(std::marker::PhantomData::<Self>);
         let pairs = self.map_to_sql();
-        let keys = pairs.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        let placeholder = |name: &str| {
+            let index = pairs
+                .iter()
+                .position(|(k, _)| k == name)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "update_query_with_params: primary key `{}` is not a column of `{}`",
+                        name, table_name
+                    )
+                });
+            dialect.placeholder(name, index)
+        };
         let primary_keys_cond = Self::primary_key_columns(std::marker::PhantomData::<Self>)
             .into_iter()
-            .map(|v| format!("{} = :{}", v, v))
+            .map(|v| format!("{} = {}", dialect.quote_identifier(&v), placeholder(&v)))
             .collect::<Vec<_>>()
             .join(" and ");
 
         (
             format!(
                 "UPDATE {} SET {} WHERE {}",
-                Self::table_name(std::marker::PhantomData::<Self>),
-                keys.iter()
-                    .map(|k| format!("{} = :{}", k, k))
+                dialect.quote_qualified(&table_name),
+                pairs
+                    .iter()
+                    .map(|(k, _)| format!("{} = {}", dialect.quote_identifier(k), placeholder(k)))
                     .collect::<Vec<_>>()
                     .join(", "),
                 primary_keys_cond
@@ -174,6 +388,144 @@ pub trait SqlTable: SqlMapper {
             pairs,
         )
     }
+
+    fn update_returning_query_with_params(
+        self,
+        dialect: &dyn Dialect,
+    ) -> Result<QueryWithParams<Self::ValueType>, Unsupported> {
+        with_returning::<Self>(dialect, self.update_query_with_params_for(dialect))
+    }
+
+    /// Deletes the record with the same primary key
+    fn delete_query_with_params(
+        self,
+        dialect: &dyn Dialect,
+    ) -> (String, Vec<(String, Self::ValueType)>) {
+        let primary_keys = Self::primary_key_columns(std::marker::PhantomData::<Self>);
+        let pairs = self
+            .map_to_sql()
+            .into_iter()
+            .filter(|(k, _)| primary_keys.contains(k))
+            .collect::<Vec<_>>();
+
+        (
+            format!(
+                "DELETE FROM {} WHERE {}",
+                dialect.quote_qualified(&Self::table_name(std::marker::PhantomData::<Self>)),
+                pairs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (k, _))| format!(
+                        "{} = {}",
+                        dialect.quote_identifier(k),
+                        dialect.placeholder(k, i)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            ),
+            pairs,
+        )
+    }
+
+    fn delete_returning_query_with_params(
+        self,
+        dialect: &dyn Dialect,
+    ) -> Result<QueryWithParams<Self::ValueType>, Unsupported> {
+        with_returning::<Self>(dialect, self.delete_query_with_params(dialect))
+    }
+}
+
+fn create_table_query_with<T: SqlTable>(
+    ty: std::marker::PhantomData<T>,
+    dialect: &dyn Dialect,
+    constraint_primary_key: String,
+) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({}, {})",
+        dialect.quote_qualified(&SqlTable::table_name(ty)),
+        SqlTable::schema_of(ty)
+            .into_iter()
+            .map(|(name, typ, attr)| create_column_query_for(dialect, name, typ, attr))
+            .collect::<Vec<_>>()
+            .as_slice()
+            .join(", "),
+        constraint_primary_key,
+    )
+}
+
+fn insert_query<T: SqlTable>(
+    dialect: &dyn Dialect,
+    pairs: Vec<(String, T::ValueType)>,
+) -> (String, Vec<(String, T::ValueType)>) {
+    (
+        format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            dialect.quote_qualified(&SqlTable::table_name(std::marker::PhantomData::<T>)),
+            pairs
+                .iter()
+                .map(|(k, _)| dialect.quote_identifier(k))
+                .collect::<Vec<_>>()
+                .join(", "),
+            pairs
+                .iter()
+                .enumerate()
+                .map(|(i, (k, _))| dialect.placeholder(k, i))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        pairs,
+    )
+}
+
+// Appends the clause to the query unless it is empty
+fn with_clause(query: String, clause: String) -> String {
+    if clause.is_empty() {
+        query
+    } else {
+        format!("{} {}", query, clause)
+    }
+}
+
+// Appends RETURNING of every column of T
+fn with_returning<T: SqlTable>(
+    dialect: &dyn Dialect,
+    (query, pairs): (String, Vec<(String, T::ValueType)>),
+) -> Result<QueryWithParams<T::ValueType>, Unsupported> {
+    if !dialect.supports_returning() {
+        return Err(Unsupported {
+            dialect: dialect.name(),
+            feature: "RETURNING".to_string(),
+        });
+    }
+
+    let columns = SqlTable::schema_of(std::marker::PhantomData::<T>)
+        .into_iter()
+        .map(|(name, _, _)| name)
+        .collect::<Vec<_>>();
+
+    Ok((
+        format!("{} {}", query, dialect.returning_clause(&columns)),
+        pairs,
+    ))
+}
+
+// search if specified keys exist
+fn check_index_keys<T: SqlTable>(ty: std::marker::PhantomData<T>, index_keys: &[&str]) {
+    let schema = SqlTable::schema_of(ty);
+    for key in index_keys.iter() {
+        if !schema
+            .iter()
+            .map(|(column_name, _, _)| column_name.as_str())
+            .collect::<Vec<&str>>()
+            .contains(key)
+        {
+            panic!(
+                "index: column {} is not field of {}",
+                key,
+                SqlTable::table_name(ty)
+            )
+        }
+    }
 }
 
 pub fn table_name<T: SqlTable>() -> String {
@@ -202,10 +554,34 @@ pub fn create_unique_index_query<T: SqlTable>(
     SqlTable::create_unique_index_query(std::marker::PhantomData::<T>, index_name, index_keys)
 }
 
+/// Columns which are not part of the primary key, i.e. the ones `SqlConn::save` updates on conflict
+pub fn non_primary_key_columns<T: SqlTable>() -> Vec<String> {
+    let primary_keys = primary_key_columns::<T>();
+
+    schema_of::<T>()
+        .into_iter()
+        .map(|(column_name, _, _)| column_name)
+        .filter(|column_name| !primary_keys.contains(column_name))
+        .collect()
+}
+
+/// Columns declared with `#[sql(auto_increment = true)]`
+pub fn auto_increment_columns<T: SqlTable>() -> Vec<String> {
+    schema_of::<T>()
+        .into_iter()
+        .filter(|(_, _, attr)| attr.auto_increment.unwrap_or(false))
+        .map(|(name, _, _)| name)
+        .collect()
+}
+
 pub fn map_from_sql<T: SqlMapper>(h: std::collections::HashMap<String, T::ValueType>) -> T {
     SqlMapper::map_from_sql(h)
 }
 
+pub fn map_from_row<T: SqlMapper>(row: Row<T::ValueType>) -> T {
+    SqlMapper::map_from_row(row)
+}
+
 pub fn create_table_query<T: SqlTable>() -> String {
     SqlTable::create_table_query(std::marker::PhantomData::<T>)
 }
@@ -217,3 +593,19 @@ pub trait SqlValue<Type> {
     fn serialize(_: Type) -> Self;
     fn deserialize(self) -> Type;
 }
+
+/// Values which can be SQL NULL, used to tell a table which an outer join found no row for
+pub trait SqlNull {
+    fn is_null(&self) -> bool;
+}
+
+/// Values which can be written as an SQL literal, used to show the params of a logged query
+pub trait SqlLiteral {
+    fn to_sql_literal(&self) -> String;
+}
+
+// Quotes a string literal, doubling the quotes inside it
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "memory"))]
+pub(crate) fn quote_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
@@ -0,0 +1,155 @@
+use crate::Ordering;
+
+/// A window function call such as `ROW_NUMBER() OVER (PARTITION BY ... ORDER BY ...) AS alias`.
+/// This converts into `String`, so it can be passed to `QueryBuilder::selects`.
+#[derive(Clone, Debug)]
+pub struct WindowExpr {
+    function: String,
+    partitions: Vec<String>,
+    orders: Vec<(String, Ordering)>,
+    frame: Option<String>,
+    alias: Option<String>,
+}
+
+impl WindowExpr {
+    pub fn new(function: impl Into<String>) -> WindowExpr {
+        WindowExpr {
+            function: function.into(),
+            partitions: vec![],
+            orders: vec![],
+            frame: None,
+            alias: None,
+        }
+    }
+
+    pub fn row_number() -> WindowExpr {
+        WindowExpr::new("ROW_NUMBER()")
+    }
+
+    pub fn rank() -> WindowExpr {
+        WindowExpr::new("RANK()")
+    }
+
+    pub fn dense_rank() -> WindowExpr {
+        WindowExpr::new("DENSE_RANK()")
+    }
+
+    pub fn lag(expr: impl Into<String>, offset: i32) -> WindowExpr {
+        WindowExpr::new(format!("LAG({}, {})", expr.into(), offset))
+    }
+
+    pub fn lead(expr: impl Into<String>, offset: i32) -> WindowExpr {
+        WindowExpr::new(format!("LEAD({}, {})", expr.into(), offset))
+    }
+
+    pub fn sum(expr: impl Into<String>) -> WindowExpr {
+        WindowExpr::new(format!("SUM({})", expr.into()))
+    }
+
+    pub fn count(expr: impl Into<String>) -> WindowExpr {
+        WindowExpr::new(format!("COUNT({})", expr.into()))
+    }
+
+    pub fn partition_by<S: Into<String>>(mut self, fields: Vec<S>) -> WindowExpr {
+        self.partitions
+            .append(&mut fields.into_iter().map(|v| v.into()).collect::<Vec<_>>());
+
+        self
+    }
+
+    pub fn order_by(mut self, column_name: impl Into<String>, ordering: Ordering) -> WindowExpr {
+        self.orders.push((column_name.into(), ordering));
+
+        self
+    }
+
+    /// Frame clause, e.g. `ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW`
+    pub fn frame(mut self, frame: impl Into<String>) -> WindowExpr {
+        self.frame = Some(frame.into());
+
+        self
+    }
+
+    pub fn alias(mut self, alias: impl Into<String>) -> WindowExpr {
+        self.alias = Some(alias.into());
+
+        self
+    }
+}
+
+impl std::fmt::Display for WindowExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let window = [
+            if !self.partitions.is_empty() {
+                format!("PARTITION BY {}", self.partitions.join(", "))
+            } else {
+                String::new()
+            },
+            if !self.orders.is_empty() {
+                format!(
+                    "ORDER BY {}",
+                    self.orders
+                        .iter()
+                        .map(|(k, o)| format!("{} {}", k, o))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            } else {
+                String::new()
+            },
+            self.frame.clone().unwrap_or_default(),
+        ]
+        .iter()
+        .filter(|s| !s.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+        write!(f, "{} OVER ({})", self.function, window)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", alias)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<WindowExpr> for String {
+    fn from(expr: WindowExpr) -> String {
+        expr.to_string()
+    }
+}
+
+#[test]
+fn window_expr_to_string() {
+    assert_eq!(
+        WindowExpr::row_number()
+            .partition_by(vec!["user_id"])
+            .order_by("created_at", Ordering::Descending)
+            .alias("rn")
+            .to_string(),
+        "ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY created_at DESC) AS rn"
+    );
+    assert_eq!(
+        WindowExpr::sum("amount")
+            .order_by("day", Ordering::Ascending)
+            .frame("ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW")
+            .to_string(),
+        "SUM(amount) OVER (ORDER BY day ASC ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW)"
+    );
+    assert_eq!(WindowExpr::rank().to_string(), "RANK() OVER ()");
+    assert_eq!(
+        crate::QueryBuilder::<()>::new()
+            .table("sales")
+            .selects(vec![
+                "day".to_string(),
+                WindowExpr::lag("amount", 1)
+                    .order_by("day", Ordering::Ascending)
+                    .alias("previous")
+                    .into(),
+            ])
+            .build()
+            .0,
+        "SELECT day, LAG(amount, 1) OVER (ORDER BY day ASC) AS previous FROM sales"
+    );
+}
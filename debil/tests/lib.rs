@@ -40,6 +40,31 @@ struct Ex1 {
     pk: i32,
 }
 
+// A key type which is not PartialEq
+#[derive(Clone)]
+struct Token(Vec<u8>);
+
+impl SqlValue<Token> for Binary {
+    fn column_type(_: std::marker::PhantomData<Token>, _: i32) -> String {
+        "blob".to_string()
+    }
+
+    fn serialize(v: Token) -> Self {
+        Binary(v.0)
+    }
+    fn deserialize(self) -> Token {
+        Token(self.0)
+    }
+}
+
+// The primary key only needs to be PartialEq for relations
+#[derive(Table)]
+#[sql(table_name = "session", sql_type = "Binary", primary_key = "token")]
+struct Session {
+    token: Token,
+    user_id: i32,
+}
+
 #[test]
 fn it_derives_sql_table() {
     let ex1 = Ex1 {
@@ -124,10 +149,160 @@ fn it_derives_sql_table() {
 }
 
 #[test]
+fn it_maps_from_row() {
+    let columns = vec!["pk", "field1", "aaaa", "pk"]
+        .into_iter()
+        .map(String::from)
+        .collect::<std::sync::Arc<[String]>>();
+    let row = Row::new(
+        columns,
+        vec![
+            SqlValue::serialize(200),
+            SqlValue::serialize("piyo".to_string()),
+            SqlValue::serialize(-10000),
+            SqlValue::serialize(300),
+        ],
+    );
+
+    assert_eq!(row.get(1), Some(&SqlValue::serialize("piyo".to_string())));
+    assert_eq!(row.get(4), None);
+    assert_eq!(row.get_by_name("pk"), Some(&SqlValue::serialize(200)));
+    assert_eq!(row.get_by_name("missing"), None);
+    assert_eq!(
+        row.clone().into_map().get("pk"),
+        Some(&SqlValue::serialize(200))
+    );
+
+    let expected = Ex1 {
+        field1: "piyo".to_string(),
+        aaaa: -10000,
+        pk: 200,
+    };
+    assert_eq!(map_from_row::<Ex1>(row.clone()), expected);
+
+    let mut row = row;
+    assert_eq!(row.take(0), Some(SqlValue::serialize(200)));
+    assert_eq!(row.get(0), None);
+    assert_eq!(row.take_by_name("pk"), None);
+}
+
+#[test]
+fn it_derives_sql_table_without_relations() {
+    let session = Session {
+        token: Token(vec![1]),
+        user_id: 2,
+    };
+    assert_eq!(primary_key_columns::<Session>(), vec!["token"]);
+    assert_eq!(session.primary_key().0, vec![1]);
+    assert_eq!(
+        session.map_to_sql()[0],
+        ("token".to_string(), Binary(vec![1]))
+    );
+}
+
+#[test]
+fn upsert_query() {
+    let ex1 = Ex1 {
+        field1: "aaa".to_string(),
+        aaaa: 10,
+        pk: 1,
+    };
+
+    assert_eq!(non_primary_key_columns::<Ex1>(), vec!["field1", "aaaa"]);
+    assert_eq!(
+        ex1.clone()
+            .upsert_query_with_params(
+                &MySqlDialect,
+                non_primary_key_columns::<Ex1>()
+            )
+            .0,
+        "INSERT INTO `ex_1` (`field1`, `aaaa`, `pk`) VALUES (:field1, :aaaa, :pk) ON DUPLICATE KEY UPDATE `field1` = VALUES(`field1`), `aaaa` = VALUES(`aaaa`)"
+    );
+    assert_eq!(
+        ex1.clone()
+            .upsert_query_with_params(&MySqlDialect, vec![])
+            .0,
+        "INSERT INTO `ex_1` (`field1`, `aaaa`, `pk`) VALUES (:field1, :aaaa, :pk) ON DUPLICATE KEY UPDATE `pk` = `pk`"
+    );
+    assert_eq!(
+        ex1.clone()
+            .upsert_query_with_params(&SqliteDialect, vec!["aaaa".to_string()])
+            .0,
+        r#"INSERT INTO "ex_1" ("field1", "aaaa", "pk") VALUES (:field1, :aaaa, :pk) ON CONFLICT("pk") DO UPDATE SET "aaaa" = excluded."aaaa""#
+    );
+    assert_eq!(
+        ex1.upsert_query_with_params(&SqliteDialect, vec![]).0,
+        r#"INSERT INTO "ex_1" ("field1", "aaaa", "pk") VALUES (:field1, :aaaa, :pk) ON CONFLICT("pk") DO NOTHING"#
+    );
+}
+
+#[test]
+fn insert_all_query() {
+    let datas = vec![
+        Ex1 {
+            field1: "aaa".to_string(),
+            aaaa: 10,
+            pk: 1,
+        },
+        Ex1 {
+            field1: "bbb".to_string(),
+            aaaa: 20,
+            pk: 2,
+        },
+    ];
+
+    let (query, params) = SqlTable::insert_all_query_with_params(datas.clone(), &GenericDialect);
+    assert_eq!(
+        query,
+        "INSERT INTO ex_1 (field1, aaaa, pk) VALUES (:field1_0, :aaaa_0, :pk_0), (:field1_1, :aaaa_1, :pk_1)"
+    );
+    assert_eq!(
+        params.into_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+        vec!["field1_0", "aaaa_0", "pk_0", "field1_1", "aaaa_1", "pk_1"]
+    );
+
+    assert_eq!(
+        Ex1::upsert_all_query_with_params(datas, &SqliteDialect, non_primary_key_columns::<Ex1>())
+            .0,
+        r#"INSERT INTO "ex_1" ("field1", "aaaa", "pk") VALUES (:field1_0, :aaaa_0, :pk_0), (:field1_1, :aaaa_1, :pk_1) ON CONFLICT("pk") DO UPDATE SET "field1" = excluded."field1", "aaaa" = excluded."aaaa""#
+    );
+}
+
+#[test]
+#[allow(non_snake_case)]
 fn Ex1_accessor() {
+    assert_eq!(accessor!(&GenericDialect, Ex1::field1), "ex_1.field1");
+    assert_eq!(accessor!(&SqliteDialect, Ex1::field1), r#""ex_1"."field1""#);
+    assert_eq!(accessor!(&MySqlDialect, Ex1::field1), "`ex_1`.`field1`");
     assert_eq!(accessor!(Ex1::field1), "ex_1.field1");
 }
 
+#[test]
+fn reserved_word_columns() {
+    #[derive(Table, Accessor, PartialEq, Debug, Clone)]
+    #[sql(table_name = "order", sql_type = "Binary", primary_key = "key")]
+    struct Order {
+        key: i32,
+        r#type: String,
+    }
+
+    assert_eq!(Order::r#type(), "type");
+    assert_eq!(
+        SqlTable::create_table_query_for(std::marker::PhantomData::<Order>, &MySqlDialect),
+        "CREATE TABLE IF NOT EXISTS `order` (`key` int, `type` varchar(0), CONSTRAINT `order_pkey` PRIMARY KEY(`key`))"
+    );
+    assert_eq!(
+        Order {
+            key: 1,
+            r#type: "a".to_string()
+        }
+        .update_query_with_params_for(&SqliteDialect)
+        .0,
+        r#"UPDATE "order" SET "key" = :key, "type" = :type WHERE "key" = :key"#
+    );
+    assert_eq!(accessor!(&MySqlDialect, Order::r#type), "`order`.`type`");
+}
+
 #[test]
 fn composite_primary_key() {
     #[derive(Table, PartialEq, Debug, Clone)]
@@ -157,6 +332,43 @@ fn composite_primary_key() {
     assert_eq!(primary_key_columns::<Ex3>(), vec!["pk", "pk2"]);
 }
 
+#[test]
+#[should_panic(expected = "primary key `pk` is not a column of `ex_1`")]
+fn update_query_primary_key_not_found() {
+    // a hand-written table whose columns leave out its primary key
+    struct Ex6 {
+        aaaa: i32,
+    }
+
+    impl SqlMapper for Ex6 {
+        type ValueType = Binary;
+
+        fn map_from_sql(_: std::collections::HashMap<String, Binary>) -> Self {
+            unimplemented!()
+        }
+    }
+
+    impl SqlTable for Ex6 {
+        fn table_name(_: std::marker::PhantomData<Self>) -> String {
+            "ex_1".to_string()
+        }
+
+        fn schema_of(_: std::marker::PhantomData<Self>) -> Vec<(String, String, FieldAttribute)> {
+            vec![]
+        }
+
+        fn primary_key_columns(_: std::marker::PhantomData<Self>) -> Vec<String> {
+            vec!["pk".to_string()]
+        }
+
+        fn map_to_sql(self) -> Vec<(String, Binary)> {
+            vec![("aaaa".to_string(), SqlValue::serialize(self.aaaa))]
+        }
+    }
+
+    Ex6 { aaaa: 1 }.update_query_with_params_for(&PostgresDialect);
+}
+
 #[test]
 fn add_index() {
     #[derive(Table, PartialEq, Debug, Clone)]
@@ -195,12 +407,14 @@ fn add_index_key_not_found() {
     create_index_query::<Ex5>("hoge", vec!["field5"]);
 }
 
+#[allow(dead_code)]
 #[derive(Accessor)]
 struct Foo {
     hoge: i32,
     piyo: String,
 }
 
+#[allow(dead_code)]
 impl Foo {
     pub fn new() -> Foo {
         Foo {
@@ -216,3 +430,269 @@ fn test_accessor() {
     assert_eq!(Foo::piyo(), "piyo");
     assert_eq!(accessor_name!(Foo::hoge), "hoge");
 }
+
+#[tokio::test]
+async fn mock_conn() {
+    use debil::mock::{Error, MockConn};
+
+    let ex1 = Ex1 {
+        field1: "aaa".to_string(),
+        aaaa: 10,
+        pk: 1,
+    };
+    // the first response matching a query wins, so UPDATE and DELETE with `pk = :pk`
+    // are not answered with the records
+    let mut conn = MockConn::new()
+        .affects("UPDATE ex_1", 1)
+        .fails("DELETE FROM ex_1", "foreign key constraint failed")
+        .returns_records("pk = :pk", vec![ex1.clone()]);
+
+    let found = conn
+        .first::<Ex1>(QueryBuilder::new().wheres((
+            vec!["pk = :pk"],
+            vec![("pk".to_string(), SqlValue::serialize(1))],
+        )))
+        .await
+        .unwrap();
+    assert_eq!(found, ex1);
+    assert_eq!(conn.update_returning(ex1.clone()).await.unwrap(), ex1);
+    assert_eq!(
+        conn.delete::<Ex1>(ex1.clone()).await,
+        Err(Error::Scripted("foreign key constraint failed".to_string()))
+    );
+    assert_eq!(
+        conn.first::<Ex1>(QueryBuilder::new()).await,
+        Err(Error::NotFoundError)
+    );
+
+    conn.assert_executed("UPDATE ex_1 SET");
+    conn.assert_executed_times("SELECT", 3);
+    conn.assert_not_executed("INSERT INTO ex_1");
+    let update = conn.executed_matching("UPDATE ex_1").pop().unwrap();
+    assert_eq!(
+        update
+            .param("aaaa")
+            .cloned()
+            .map(SqlValue::<i32>::deserialize),
+        Some(10)
+    );
+
+    conn.clear();
+    assert!(conn.executed().is_empty());
+}
+
+#[test]
+#[should_panic(expected = "no query matching `INSERT INTO ex_1` was executed")]
+fn mock_conn_assert_executed() {
+    debil::mock::MockConn::<Binary>::new().assert_executed("INSERT INTO ex_1");
+}
+
+#[tokio::test]
+async fn mock_conn_returning_fallback() {
+    use debil::mock::{Error, MockConn};
+
+    #[derive(Table, PartialEq, Debug, Clone)]
+    #[sql(table_name = "post", sql_type = "Binary", primary_key = "id")]
+    struct Post {
+        #[sql(auto_increment = true)]
+        id: i32,
+        title: String,
+    }
+
+    let post = Post {
+        id: 1,
+        title: "foo".to_string(),
+    };
+    let mut conn = MockConn::new().returns_records("SELECT", vec![post.clone()]);
+
+    // the SELECT before the DELETE runs in the same transaction
+    assert_eq!(conn.delete_returning(post.clone()).await.unwrap(), post);
+    assert_eq!(
+        conn.executed()
+            .iter()
+            .map(|e| e.query.split(' ').next().unwrap())
+            .collect::<Vec<_>>(),
+        vec!["BEGIN", "SELECT", "DELETE", "COMMIT"]
+    );
+
+    // the generic dialect can read back neither RETURNING nor the generated id
+    assert!(matches!(
+        conn.create_returning(post).await,
+        Err(Error::Unsupported(_))
+    ));
+}
+
+#[test]
+fn has_not_found_without_unsupported() {
+    // an error type written before `HasNotFound::unsupported` existed
+    #[derive(Debug, PartialEq)]
+    struct NotFound;
+
+    impl HasNotFound for NotFound {
+        fn not_found() -> Self {
+            NotFound
+        }
+    }
+
+    assert_eq!(
+        NotFound::unsupported(Unsupported {
+            dialect: "generic",
+            feature: "RETURNING".to_string(),
+        }),
+        NotFound
+    );
+}
+
+#[tokio::test]
+async fn routing_conn() {
+    use debil::mock::MockConn;
+
+    let ex1 = Ex1 {
+        field1: "aaa".to_string(),
+        aaaa: 10,
+        pk: 1,
+    };
+    let mut conn = RoutingConn::new(
+        MockConn::<Binary>::new(),
+        vec![MockConn::new(), MockConn::new()],
+    );
+
+    conn.create_table::<Ex1>().await.unwrap();
+    conn.save(ex1.clone()).await.unwrap();
+    for _ in 0..3 {
+        conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+    }
+    conn.sql_query::<Row<Binary>>("SELECT * FROM ex_1 FOR UPDATE".to_string(), Params::new())
+        .await
+        .unwrap();
+
+    // reads go to the replicas in turn, and writes and locking reads to the primary
+    conn.primary()
+        .assert_executed("CREATE TABLE IF NOT EXISTS ex_1");
+    conn.primary().assert_executed("INSERT INTO ex_1");
+    conn.primary().assert_executed_times("SELECT", 1);
+    conn.replica(0).assert_executed_times("SELECT", 2);
+    conn.replica(1).assert_executed_times("SELECT", 1);
+    conn.replica(0).assert_not_executed("INSERT INTO ex_1");
+}
+
+#[tokio::test]
+async fn routing_conn_health_check() {
+    use debil::mock::{Error, MockConn};
+
+    // replica 0 is down, and replica 1 fails only the queries on ex_1
+    let mut conn = RoutingConn::new(
+        MockConn::<Binary>::new(),
+        vec![
+            MockConn::new().fails("SELECT", "connection refused"),
+            MockConn::new().fails("FROM ex_1", "no such table"),
+        ],
+    );
+
+    assert_eq!(
+        conn.load::<Ex1>(QueryBuilder::new()).await,
+        Err(Error::Scripted("no such table".to_string()))
+    );
+    assert_eq!(conn.replicas_up(), 1);
+    conn.replica(0).assert_executed_times("SELECT 1", 1);
+    conn.replica(1).assert_executed_times("SELECT 1", 1);
+
+    // replica 0 is skipped until the retry interval passes
+    conn.sql_query::<Row<Binary>>("SELECT * FROM ex_2".to_string(), Params::new())
+        .await
+        .unwrap();
+    conn.sql_query::<Row<Binary>>("SELECT * FROM ex_2".to_string(), Params::new())
+        .await
+        .unwrap();
+    conn.replica(0).assert_executed_times("SELECT", 2);
+    conn.replica(1).assert_executed_times("FROM ex_2", 2);
+
+    // without a replica which is up, reads go to the primary
+    let mut conn = RoutingConn::new(
+        MockConn::<Binary>::new(),
+        vec![MockConn::new().fails("SELECT", "connection refused")],
+    )
+    .retry_interval(std::time::Duration::from_secs(0));
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+    conn.primary().assert_executed_times("FROM ex_1", 1);
+    assert_eq!(conn.check_replicas::<Binary>().await, 0);
+}
+
+#[tokio::test]
+async fn routing_conn_returning() {
+    use debil::mock::MockConn;
+
+    #[derive(Table, PartialEq, Debug, Clone)]
+    #[sql(table_name = "post", sql_type = "Binary", primary_key = "id")]
+    struct Post {
+        #[sql(auto_increment = true)]
+        id: i32,
+        title: String,
+    }
+
+    let post = Post {
+        id: 1,
+        title: "foo".to_string(),
+    };
+    let mut conn = RoutingConn::new(
+        MockConn::new()
+            .with_dialect(MySqlDialect)
+            .returns_records("SELECT", vec![post.clone()]),
+        vec![MockConn::new().with_dialect(MySqlDialect)],
+    );
+
+    // MySQL reads the record back with a SELECT, which must see the write
+    assert_eq!(conn.create_returning(post.clone()).await.unwrap(), post);
+    conn.primary().assert_executed("LAST_INSERT_ID()");
+    assert_eq!(conn.update_returning(post.clone()).await.unwrap(), post);
+    assert_eq!(conn.delete_returning(post.clone()).await.unwrap(), post);
+    conn.primary().assert_executed_times("SELECT", 3);
+    conn.replica(0).assert_not_executed("SELECT");
+}
+
+#[tokio::test]
+async fn routing_conn_read_your_writes() {
+    use debil::mock::MockConn;
+    use std::time::Duration;
+
+    let ex1 = Ex1 {
+        field1: "aaa".to_string(),
+        aaaa: 10,
+        pk: 1,
+    };
+
+    let mut conn = RoutingConn::new(MockConn::<Binary>::new(), vec![MockConn::new()])
+        .read_your_writes(Duration::from_secs(60));
+    assert!(!conn.is_pinned());
+    conn.save(ex1.clone()).await.unwrap();
+    assert!(conn.is_pinned());
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+    conn.primary().assert_executed_times("FROM ex_1", 1);
+    conn.replica(0).assert_not_executed("FROM ex_1");
+
+    // with no window, reads go to the primary only until the transaction ends
+    let mut conn = RoutingConn::new(MockConn::<Binary>::new(), vec![MockConn::new()])
+        .read_your_writes(Duration::from_secs(0));
+    conn.save(ex1.clone()).await.unwrap();
+    assert!(!conn.is_pinned());
+    conn.start_transaction().await.unwrap();
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+    conn.save(ex1.clone()).await.unwrap();
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+    conn.commit().await.unwrap();
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+
+    conn.primary().assert_executed_times("SELECT", 2);
+    conn.replica(0).assert_executed_times("SELECT", 1);
+    conn.primary().assert_executed("COMMIT");
+
+    // reads in a transaction go to the primary even without read_your_writes
+    let mut conn = RoutingConn::new(MockConn::<Binary>::new(), vec![MockConn::new()]);
+    conn.start_transaction().await.unwrap();
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+    conn.rollback().await.unwrap();
+    conn.load::<Ex1>(QueryBuilder::new()).await.unwrap();
+
+    conn.primary().assert_executed_times("SELECT", 1);
+    conn.replica(0).assert_executed_times("SELECT", 1);
+}
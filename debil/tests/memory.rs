@@ -0,0 +1,247 @@
+#[cfg(feature = "memory")]
+mod tests {
+    use debil::memory::*;
+    use debil::*;
+
+    #[derive(Table, Accessor, PartialEq, Debug, Clone, Default)]
+    #[sql(table_name = "user", primary_key = "id")]
+    struct User {
+        id: i64,
+        #[sql(size = 50, unique = true, not_null = true)]
+        name: String,
+        age: i64,
+    }
+
+    fn user(id: i64, age: i64) -> User {
+        User {
+            id,
+            name: format!("user-{}", id),
+            age,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_save_and_load() -> Result<(), Error> {
+        let mut conn = MemoryConn::new();
+        conn.create_table::<User>().await?;
+
+        conn.create_all(vec![user(1, 30), user(2, 20), user(3, 40)])
+            .await?;
+        conn.save(User {
+            age: 25,
+            ..user(2, 0)
+        })
+        .await?;
+        assert_eq!(
+            conn.update_returning(User {
+                age: 35,
+                ..user(3, 0)
+            })
+            .await?,
+            user(3, 35)
+        );
+
+        let loaded = conn
+            .load::<User>(
+                QueryBuilder::new()
+                    .wheres((
+                        vec![format!("{} > :age", accessor!(User::age))],
+                        vec![("age".to_string(), SqlValue::serialize(20i64))],
+                    ))
+                    .order_by(accessor!(User::age), Ordering::Descending)
+                    .limit(2),
+            )
+            .await?;
+        assert_eq!(loaded, vec![user(3, 35), user(1, 30)]);
+
+        assert_eq!(
+            conn.first::<User>(QueryBuilder::new().wheres(record_expr!(User, { id: 2 })))
+                .await?,
+            user(2, 25)
+        );
+        assert_eq!(
+            conn.first::<User>(QueryBuilder::new().wheres(record_expr!(User, { id: 9 })))
+                .await,
+            Err(Error::NotFoundError)
+        );
+
+        assert_eq!(conn.delete(user(1, 30)).await?, 1);
+        assert_eq!(conn.rows("user")?.len(), 2);
+        assert_eq!(
+            conn.rows("user")?[0].get("age"),
+            Some(&MemoryValue::Integer(25))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_constraints_and_transactions() -> Result<(), Error> {
+        let mut conn = MemoryConn::new();
+        conn.create_table::<User>().await?;
+        conn.create(user(1, 20)).await?;
+
+        assert!(matches!(
+            conn.create(User {
+                id: 2,
+                ..user(1, 20)
+            })
+            .await,
+            Err(Error::ConstraintViolation(_))
+        ));
+        assert!(matches!(
+            conn.create(user(1, 30)).await,
+            Err(Error::ConstraintViolation(_))
+        ));
+
+        // clones share the tables, but wait for the transaction of another clone
+        let mut other = conn.clone();
+        conn.start_transaction().await?;
+        conn.create(user(2, 20)).await?;
+        let handle = tokio::spawn(async move {
+            other.create(user(3, 30)).await?;
+            other.load::<User>(QueryBuilder::new()).await
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(conn.load::<User>(QueryBuilder::new()).await?.len(), 2);
+        conn.rollback().await?;
+        assert_eq!(handle.await.unwrap()?, vec![user(1, 20), user(3, 30)]);
+
+        // dropping a clone rolls back its transaction
+        let mut other = conn.clone();
+        other.start_transaction().await?;
+        other.create(user(2, 20)).await?;
+        drop(other);
+        assert_eq!(conn.rows("user")?.len(), 2);
+
+        assert!(matches!(
+            conn.load::<User>(QueryBuilder::new().group_by(vec!["age"]))
+                .await,
+            Err(Error::Unsupported(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_returning() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        struct Post {
+            #[sql(auto_increment = true)]
+            id: i64,
+            title: String,
+        }
+
+        let mut conn = MemoryConn::new();
+        conn.create_table::<Post>().await?;
+
+        let first = conn
+            .create_returning(Post {
+                id: 0,
+                title: "first".to_string(),
+            })
+            .await?;
+        let second = conn
+            .create_returning(Post {
+                id: 0,
+                title: "second".to_string(),
+            })
+            .await?;
+        assert_eq!((first.id, second.id), (1, 2));
+
+        let deleted = conn.delete_returning(first.clone()).await?;
+        assert_eq!(deleted, first);
+        assert_eq!(conn.load::<Post>(QueryBuilder::new()).await?, vec![second]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_joins_and_relations() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        #[sql(belongs_to = "User", foreign_key = "user_id")]
+        struct Post {
+            id: i64,
+            user_id: i64,
+            name: String,
+        }
+
+        let mut conn = MemoryConn::new();
+        conn.create_table::<User>().await?;
+        conn.create_table::<Post>().await?;
+
+        let users = vec![user(1, 20), user(2, 30)];
+        let posts = vec![(10, 1), (11, 1)]
+            .into_iter()
+            .map(|(id, user_id)| Post {
+                id,
+                user_id,
+                name: format!("post-{}", id),
+            })
+            .collect::<Vec<_>>();
+        conn.create_all(users.clone()).await?;
+        conn.create_all(posts.clone()).await?;
+
+        let loaded = conn
+            .load_joined::<(User, Option<Post>)>(
+                QueryBuilder::new()
+                    .left_join(table_name::<Post>(), ("id", "user_id"))
+                    .order_by("user.id", Ordering::Ascending)
+                    .order_by("post.id", Ordering::Descending),
+            )
+            .await?;
+        assert_eq!(
+            loaded,
+            vec![
+                (users[0].clone(), Some(posts[1].clone())),
+                (users[0].clone(), Some(posts[0].clone())),
+                (users[1].clone(), None),
+            ]
+        );
+
+        let loaded = conn
+            .eager_load::<Post>(QueryBuilder::new())
+            .with_parent::<User>()
+            .await?;
+        assert_eq!(
+            loaded
+                .into_iter()
+                .map(|(p, u)| (p.id, u.map(|u| u.id)))
+                .collect::<Vec<_>>(),
+            vec![(10, Some(1)), (11, Some(1))]
+        );
+
+        Ok(())
+    }
+
+    // Records of another backend run on the memory backend as they are
+    #[cfg(feature = "sqlite")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_value() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(
+            table_name = "item",
+            sql_type = "debil::sqlite::SqliteValue",
+            primary_key = "id"
+        )]
+        struct Item {
+            id: i64,
+            price: f64,
+            image: Vec<u8>,
+        }
+
+        let mut conn = MemoryConn::<debil::sqlite::SqliteValue>::new();
+        conn.create_table::<Item>().await?;
+        let item = Item {
+            id: 1,
+            price: 1.5,
+            image: vec![0, 1],
+        };
+        conn.save(item.clone()).await?;
+        assert_eq!(conn.load::<Item>(QueryBuilder::new()).await?, vec![item]);
+
+        Ok(())
+    }
+}
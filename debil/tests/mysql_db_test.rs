@@ -195,12 +195,93 @@ mod tests {
             ]
         );
 
+        // MySQL has no RETURNING, so the record is selected by its primary key instead
+        let updated = conn
+            .update_returning(User {
+                age: 21,
+                ..user.clone()
+            })
+            .await?;
+        assert_eq!(updated.age, 21);
+        let deleted = conn.delete_returning(updated.clone()).await?;
+        assert_eq!(deleted, updated);
+        let created = conn.create_returning(deleted).await?;
+        assert_eq!(created, updated);
+
+        // rows are streamed from the server
+        {
+            use futures::stream::TryStreamExt;
+
+            let users = conn
+                .load_stream::<User>(QueryBuilder::new())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(users, conn.load::<User>(QueryBuilder::new()).await?);
+        }
+
+        let user_count = conn.load::<User>(QueryBuilder::new()).await?.len();
+
+        // query plan of a builder, and slow queries with their plans
+        let plan = QueryBuilder::new()
+            .table("user")
+            .wheres((
+                vec!["user_id = :user_id"],
+                vec![(
+                    "user_id".to_string(),
+                    SqlValue::serialize("user-1".to_string()),
+                )],
+            ))
+            .explain(&mut conn)
+            .await?;
+        assert_eq!(plan.len(), 1);
+        assert!(plan[0].get_by_name("key").is_some());
+
+        let mut conn = Instrumented::new(conn)
+            .slow_query_threshold(std::time::Duration::from_secs(0))
+            .explain_slow_queries();
+        assert_eq!(
+            conn.load::<User>(QueryBuilder::new()).await?.len(),
+            user_count
+        );
+        let conn = conn.into_inner();
+
         // check thread safety
         async fn conn_load(mut conn: DebilConn) {
             conn.load::<User>(QueryBuilder::new()).await.unwrap();
         }
         tokio::spawn(conn_load(conn)).await.unwrap();
 
+        // pooled connections
+        let pool = DebilPool::from_opts(
+            OptsBuilder::default()
+                .ip_or_hostname("127.0.0.1")
+                .user(Some("root"))
+                .pass(Some("password"))
+                .db_name(Some("db"))
+                .prefer_socket(Some(false))
+                .into(),
+            PoolConfig {
+                max_size: 2,
+                ..PoolConfig::default()
+            },
+        );
+        let handles = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let mut conn = pool.get().await?;
+                    conn.load::<User>(QueryBuilder::new()).await
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.await.unwrap()?.len(), user_count);
+        }
+        let metrics = pool.metrics();
+        assert_eq!(metrics.checkouts, 4);
+        assert!(metrics.created <= 2);
+        pool.close().await?;
+
         Ok(())
     }
 }
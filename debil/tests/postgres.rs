@@ -0,0 +1,114 @@
+#[cfg(feature = "postgres")]
+mod tests {
+    use debil::postgres::*;
+    use debil::*;
+
+    #[derive(Table, Accessor, PartialEq, Debug, Clone, Default)]
+    #[sql(table_name = "user", primary_key = "id")]
+    struct User {
+        #[sql(auto_increment = true)]
+        id: i64,
+        #[sql(size = 50, not_null = true)]
+        name: String,
+        age: Option<i32>,
+        active: bool,
+    }
+
+    fn user() -> User {
+        User {
+            id: 1,
+            name: "foo".to_string(),
+            age: Some(20),
+            active: true,
+        }
+    }
+
+    #[test]
+    fn create_table_query() {
+        assert_eq!(
+            SqlTable::create_table_query_for(std::marker::PhantomData::<User>, &PostgresDialect),
+            r#"CREATE TABLE IF NOT EXISTS "user" ("id" BIGINT GENERATED BY DEFAULT AS IDENTITY, "name" VARCHAR(50) NOT NULL, "age" INTEGER, "active" BOOLEAN, CONSTRAINT "user_pkey" PRIMARY KEY("id"))"#
+        );
+        assert_eq!(
+            SqlTable::create_index_query_for(
+                std::marker::PhantomData::<User>,
+                &PostgresDialect,
+                "user_name",
+                vec!["name"],
+                true
+            ),
+            r#"CREATE UNIQUE INDEX IF NOT EXISTS "user_name" ON "user" ("name")"#
+        );
+    }
+
+    #[test]
+    fn insert_and_update_queries() {
+        let (query, params) = user().insert_query_with_params_for(&PostgresDialect);
+        assert_eq!(
+            query,
+            r#"INSERT INTO "user" ("id", "name", "age", "active") VALUES ($1, $2, $3, $4)"#
+        );
+        assert_eq!(
+            params.into_iter().map(|(_, v)| v).collect::<Vec<_>>(),
+            vec![
+                PostgresValue::BigInt(1),
+                PostgresValue::Text("foo".to_string()),
+                PostgresValue::Int(20),
+                PostgresValue::Bool(true),
+            ]
+        );
+
+        assert_eq!(
+            user().update_query_with_params_for(&PostgresDialect).0,
+            r#"UPDATE "user" SET "id" = $1, "name" = $2, "age" = $3, "active" = $4 WHERE "id" = $1"#
+        );
+        assert_eq!(
+            user()
+                .upsert_query_with_params(&PostgresDialect, vec!["name".to_string()])
+                .0,
+            r#"INSERT INTO "user" ("id", "name", "age", "active") VALUES ($1, $2, $3, $4) ON CONFLICT("id") DO UPDATE SET "name" = excluded."name""#
+        );
+        assert_eq!(
+            user()
+                .insert_returning_query_with_params(&PostgresDialect)
+                .unwrap()
+                .0,
+            r#"INSERT INTO "user" ("name", "age", "active") VALUES ($1, $2, $3) RETURNING "id", "name", "age", "active""#
+        );
+        assert_eq!(
+            user()
+                .update_returning_query_with_params(&PostgresDialect)
+                .unwrap()
+                .0,
+            r#"UPDATE "user" SET "id" = $1, "name" = $2, "age" = $3, "active" = $4 WHERE "id" = $1 RETURNING "id", "name", "age", "active""#
+        );
+        assert_eq!(
+            user()
+                .delete_returning_query_with_params(&PostgresDialect)
+                .unwrap()
+                .0,
+            r#"DELETE FROM "user" WHERE "id" = $1 RETURNING "id", "name", "age", "active""#
+        );
+        assert_eq!(
+            User::insert_all_query_with_params(vec![user(), user()], &PostgresDialect).0,
+            r#"INSERT INTO "user" ("id", "name", "age", "active") VALUES ($1, $2, $3, $4), ($5, $6, $7, $8)"#
+        );
+    }
+
+    #[test]
+    fn query_builder() {
+        let (query, params) = QueryBuilder::<PostgresValue>::new()
+            .table(table_name::<User>())
+            .wheres(record_expr!(&PostgresDialect, User, { name: "foo".to_string(), active: true }))
+            .filter("user.created_at::date = CURRENT_DATE")
+            .order_by("id", Ordering::Descending)
+            .limit(10)
+            .offset(20)
+            .build_for(&PostgresDialect);
+        assert_eq!(
+            query,
+            r#"SELECT * FROM "user" WHERE "user"."name" = $1 AND "user"."active" = $2 AND user.created_at::date = CURRENT_DATE ORDER BY id DESC LIMIT 10 OFFSET 20"#
+        );
+        assert_eq!(params.0.len(), 2);
+    }
+}
@@ -4,6 +4,33 @@ mod tests {
     use debil::sqlite::*;
     use debil::*;
 
+    #[derive(Table, Accessor, PartialEq, Debug, Clone)]
+    #[sql(table_name = "user", primary_key = "id")]
+    struct User {
+        id: i64,
+        name: String,
+        age: i64,
+    }
+
+    fn user(id: i64) -> User {
+        User {
+            id,
+            name: format!("user-{}", id),
+            age: 20 + id,
+        }
+    }
+
+    // Creates the user table on `conn` with `user(id)` for each of `ids`
+    async fn with_users<C: SqlConn<SqliteValue> + Send>(
+        mut conn: C,
+        ids: impl Iterator<Item = i64>,
+    ) -> Result<C, C::Error> {
+        conn.create_table::<User>().await?;
+        conn.create_all(ids.map(user).collect()).await?;
+
+        Ok(conn)
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_create_table() -> Result<(), Error> {
         #[derive(Table, PartialEq, Debug)]
@@ -46,6 +73,1206 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_save() -> Result<(), Error> {
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<User>().await?;
+
+        let mut user = User {
+            id: 1,
+            name: "foo".to_string(),
+            age: 20,
+        };
+        conn.save(user.clone()).await?;
+        assert_eq!(
+            conn.load::<User>(QueryBuilder::new()).await?,
+            vec![user.clone()]
+        );
+
+        // saving the same record again must not fail, even if nothing has changed
+        conn.save(user.clone()).await?;
+
+        user.age = 21;
+        conn.save(user.clone()).await?;
+        assert_eq!(
+            conn.load::<User>(QueryBuilder::new()).await?,
+            vec![user.clone()]
+        );
+
+        // only the given columns are updated on conflict
+        conn.upsert(
+            User {
+                id: 1,
+                name: "bar".to_string(),
+                age: 30,
+            },
+            vec![accessor_name!(User::age).to_string()],
+        )
+        .await?;
+        assert_eq!(
+            conn.first::<User>(QueryBuilder::new()).await?,
+            User {
+                id: 1,
+                name: "foo".to_string(),
+                age: 30,
+            }
+        );
+
+        // no columns to update leaves the row untouched
+        conn.upsert(
+            User {
+                id: 1,
+                name: "baz".to_string(),
+                age: 40,
+            },
+            vec![],
+        )
+        .await?;
+        assert_eq!(conn.first::<User>(QueryBuilder::new()).await?.age, 30);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_create_all_and_save_all() -> Result<(), Error> {
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<User>().await?;
+
+        // more rows than fit into a single statement
+        let users = (0..2500)
+            .map(|i| User {
+                id: i,
+                name: format!("user-{}", i),
+                age: 20,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(conn.create_all(users.clone()).await?, 2500);
+        assert_eq!(conn.load::<User>(QueryBuilder::new()).await?, users);
+
+        // a failing chunk rolls back the whole insertion
+        let result = conn
+            .create_all(vec![
+                User {
+                    id: 5000,
+                    name: "new".to_string(),
+                    age: 30,
+                },
+                users[0].clone(),
+            ])
+            .await;
+        assert!(result.is_err());
+        assert_eq!(conn.load::<User>(QueryBuilder::new()).await?.len(), 2500);
+
+        conn.save_all(vec![
+            User {
+                id: 0,
+                name: "updated".to_string(),
+                age: 40,
+            },
+            User {
+                id: 5000,
+                name: "new".to_string(),
+                age: 30,
+            },
+        ])
+        .await?;
+        let result = conn.load::<User>(QueryBuilder::new()).await?;
+        assert_eq!(result.len(), 2501);
+        assert_eq!(
+            result[0],
+            User {
+                id: 0,
+                name: "updated".to_string(),
+                age: 40,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_eager_load_many_records() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "author", primary_key = "id")]
+        #[sql(has_many = "Post", foreign_key = "author_id")]
+        struct Author {
+            id: i64,
+            name: String,
+        }
+
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        #[sql(belongs_to = "Author", foreign_key = "author_id")]
+        struct Post {
+            id: i64,
+            author_id: i64,
+            title: String,
+        }
+
+        // number of params of each query with an IN list
+        #[derive(Clone, Default)]
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<usize>>>);
+
+        impl QueryHook<SqliteValue, Error> for Recorder {
+            fn before_query(&self, query: &str, params: &Params<SqliteValue>) {
+                if query.contains(" IN (") {
+                    self.0.lock().unwrap().push(params.0.len());
+                }
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut conn = Instrumented::new(DebilConn::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ))
+        .hook(recorder.clone());
+        conn.create_table::<Author>().await?;
+        conn.create_table::<Post>().await?;
+
+        // more keys than the placeholders of a single query
+        conn.create_all(
+            (0..2500)
+                .map(|id| Author {
+                    id,
+                    name: format!("author-{}", id),
+                })
+                .collect(),
+        )
+        .await?;
+        conn.create_all(
+            (0..2500)
+                .map(|id| Post {
+                    id,
+                    author_id: 2499 - id,
+                    title: format!("post-{}", id),
+                })
+                .collect(),
+        )
+        .await?;
+
+        let authors = conn
+            .eager_load::<Author>(QueryBuilder::new().order_by("id", Ordering::Ascending))
+            .with::<Post>()
+            .await?;
+        assert_eq!(authors.len(), 2500);
+        assert!(authors
+            .iter()
+            .all(|(author, posts)| posts.len() == 1 && posts[0].id == 2499 - author.id));
+        assert_eq!(*recorder.0.lock().unwrap(), vec![999, 999, 502]);
+
+        let posts = conn
+            .eager_load::<Post>(QueryBuilder::new())
+            .with_parent::<Author>()
+            .await?;
+        assert_eq!(posts.len(), 2500);
+        assert!(posts.iter().all(|(post, author)| matches!(
+            author,
+            Some(author) if author.id == post.author_id
+        )));
+        assert_eq!(recorder.0.lock().unwrap().len(), 6);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_subquery() -> Result<(), Error> {
+        let mut conn = with_users(
+            DebilConn::new(rusqlite::Connection::open_in_memory().unwrap()),
+            0..10,
+        )
+        .await?;
+
+        let result = conn
+            .load::<User>(
+                QueryBuilder::new()
+                    .wheres((
+                        vec!["user.age < :age"],
+                        vec![("age".to_string(), SqlValue::serialize(25_i64))],
+                    ))
+                    .filter_in(
+                        "user.id",
+                        QueryBuilder::new()
+                            .table("user")
+                            .selects(vec!["user.id"])
+                            .wheres((
+                                vec!["user.age > :age"],
+                                vec![("age".to_string(), SqlValue::serialize(22_i64))],
+                            )),
+                    ),
+            )
+            .await?;
+        assert_eq!(
+            result.into_iter().map(|u| u.id).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_self_join_with_alias() -> Result<(), Error> {
+        let mut conn = with_users(
+            DebilConn::new(rusqlite::Connection::open_in_memory().unwrap()),
+            0..5,
+        )
+        .await?;
+
+        // users who have someone exactly one year older
+        let result = conn
+            .load::<User>(
+                QueryBuilder::new()
+                    .table_as(table_name::<User>(), "u")
+                    .join_as(
+                        JoinType::Inner,
+                        table_name::<User>(),
+                        "older",
+                        "older.age = u.age + 1",
+                    )
+                    .order_by("u.id", Ordering::Ascending),
+            )
+            .await?;
+        assert_eq!(
+            result.into_iter().map(|u| u.id).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compound_query() -> Result<(), Error> {
+        let mut conn = with_users(
+            DebilConn::new(rusqlite::Connection::open_in_memory().unwrap()),
+            0..10,
+        )
+        .await?;
+
+        let users_by_age = |cond: &str, age: i64| {
+            QueryBuilder::new().table(table_name::<User>()).wheres((
+                vec![format!("age {} :age", cond)],
+                vec![("age".to_string(), SqlValue::serialize(age))],
+            ))
+        };
+        let (query, params) = users_by_age("<", 22)
+            .union(users_by_age(">", 27))
+            .except(users_by_age("=", 29))
+            .order_by("id", Ordering::Descending)
+            .build();
+
+        let result = conn.sql_query::<User>(query, params).await?;
+        assert_eq!(
+            result.into_iter().map(|u| u.id).collect::<Vec<_>>(),
+            vec![8, 1, 0]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_recursive_cte() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "category", primary_key = "id")]
+        struct Category {
+            id: i64,
+            // 0 for the root
+            parent_id: i64,
+            name: String,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<Category>().await?;
+        conn.create_all(
+            vec![
+                (1, 0, "root"),
+                (2, 1, "a"),
+                (3, 2, "a-1"),
+                (4, 0, "other"),
+                (5, 4, "b"),
+            ]
+            .into_iter()
+            .map(|(id, parent_id, name)| Category {
+                id,
+                parent_id,
+                name: name.to_string(),
+            })
+            .collect(),
+        )
+        .await?;
+
+        let descendants = conn
+            .load::<Category>(
+                QueryBuilder::new()
+                    .with_recursive(
+                        "tree",
+                        QueryBuilder::new()
+                            .table("category")
+                            .selects(vec!["id", "parent_id", "name"])
+                            .wheres((
+                                vec!["category.id = :id"],
+                                vec![("id".to_string(), SqlValue::serialize(1_i64))],
+                            )),
+                        QueryBuilder::new()
+                            .table("category")
+                            .selects(vec!["category.id", "category.parent_id", "category.name"])
+                            .inner_join("tree", ("parent_id", "id")),
+                    )
+                    .table_as("tree", table_name::<Category>())
+                    .order_by("category.id", Ordering::Ascending),
+            )
+            .await?;
+        assert_eq!(
+            descendants.into_iter().map(|c| c.name).collect::<Vec<_>>(),
+            vec!["root", "a", "a-1"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_window_function() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "ranked_user", primary_key = "id")]
+        struct RankedUser {
+            id: i64,
+            age_rank: i64,
+            total_age: i64,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<User>().await?;
+        conn.create_all(
+            vec![(1, 30), (2, 20), (3, 30), (4, 10)]
+                .into_iter()
+                .map(|(id, age)| User { age, ..user(id) })
+                .collect(),
+        )
+        .await?;
+
+        let (query, params) = QueryBuilder::new()
+            .table(table_name::<User>())
+            .selects(vec![
+                "id".to_string(),
+                WindowExpr::rank()
+                    .order_by("age", Ordering::Descending)
+                    .alias("age_rank")
+                    .into(),
+                WindowExpr::sum("age")
+                    .order_by("id", Ordering::Ascending)
+                    .alias("total_age")
+                    .into(),
+            ])
+            .order_by("id", Ordering::Ascending)
+            .build();
+        let result = conn.sql_query::<RankedUser>(query, params).await?;
+        assert_eq!(
+            result
+                .into_iter()
+                .map(|r| (r.id, r.age_rank, r.total_age))
+                .collect::<Vec<_>>(),
+            vec![(1, 1, 30), (2, 3, 50), (3, 1, 80), (4, 4, 90)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reserved_words() -> Result<(), Error> {
+        #[derive(Table, Accessor, PartialEq, Debug, Clone, Default)]
+        #[sql(table_name = "order", primary_key = "key")]
+        struct Order {
+            key: i64,
+            group: String,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<Order>().await?;
+        conn.save(Order {
+            key: 1,
+            group: "a".to_string(),
+        })
+        .await?;
+        conn.save(Order {
+            key: 1,
+            group: "b".to_string(),
+        })
+        .await?;
+
+        let builder = QueryBuilder::new().wheres(record_expr!(
+            conn.dialect(),
+            Order,
+            { group: "b".to_string() }
+        ));
+        let result = conn.first::<Order>(builder).await?;
+        assert_eq!(
+            result,
+            Order {
+                key: 1,
+                group: "b".to_string(),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_returning() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        struct Post {
+            #[sql(auto_increment = true)]
+            id: i64,
+            title: String,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<Post>().await?;
+
+        let first = conn
+            .create_returning(Post {
+                id: 0,
+                title: "first".to_string(),
+            })
+            .await?;
+        let second = conn
+            .create_returning(Post {
+                id: 0,
+                title: "second".to_string(),
+            })
+            .await?;
+        assert_eq!((first.id, second.id), (1, 2));
+
+        let updated = conn
+            .update_returning(Post {
+                title: "updated".to_string(),
+                ..first
+            })
+            .await?;
+        assert_eq!(
+            updated,
+            Post {
+                id: 1,
+                title: "updated".to_string(),
+            }
+        );
+
+        let deleted = conn.delete_returning(second.clone()).await?;
+        assert_eq!(deleted, second);
+        assert_eq!(conn.load::<Post>(QueryBuilder::new()).await?, vec![updated]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_relations() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "author", primary_key = "id")]
+        #[sql(has_many = "Post", foreign_key = "author_id")]
+        struct Author {
+            id: i64,
+            name: String,
+        }
+
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        #[sql(belongs_to = "Author", foreign_key = "author_id")]
+        #[sql(has_many = "Comment", foreign_key = "post_id")]
+        struct Post {
+            id: i64,
+            author_id: i64,
+            title: String,
+        }
+
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "comment", primary_key = "id")]
+        #[sql(belongs_to = "Post", foreign_key = "post_id")]
+        struct Comment {
+            id: i64,
+            post_id: i64,
+            body: String,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<Author>().await?;
+        conn.create_table::<Post>().await?;
+        conn.create_table::<Comment>().await?;
+
+        let authors = (1..=3)
+            .map(|id| Author {
+                id,
+                name: format!("author-{}", id),
+            })
+            .collect::<Vec<_>>();
+        let posts = vec![(1, 1), (2, 1), (3, 2)]
+            .into_iter()
+            .map(|(id, author_id)| Post {
+                id,
+                author_id,
+                title: format!("post-{}", id),
+            })
+            .collect::<Vec<_>>();
+        let comments = vec![(1, 1), (2, 3), (3, 1)]
+            .into_iter()
+            .map(|(id, post_id)| Comment {
+                id,
+                post_id,
+                body: format!("comment-{}", id),
+            })
+            .collect::<Vec<_>>();
+        conn.create_all(authors.clone()).await?;
+        conn.create_all(posts.clone()).await?;
+        conn.create_all(comments.clone()).await?;
+
+        let loaded = conn
+            .eager_load::<Author>(QueryBuilder::new().order_by("id", Ordering::Ascending))
+            .with::<Post>()
+            .await?;
+        assert_eq!(
+            loaded,
+            vec![
+                (authors[0].clone(), vec![posts[0].clone(), posts[1].clone()]),
+                (authors[1].clone(), vec![posts[2].clone()]),
+                (authors[2].clone(), vec![]),
+            ]
+        );
+
+        let loaded = conn
+            .eager_load::<Post>(QueryBuilder::new().filter("post.id <> 2"))
+            .with::<Comment>()
+            .await?;
+        assert_eq!(
+            loaded,
+            vec![
+                (
+                    posts[0].clone(),
+                    vec![comments[0].clone(), comments[2].clone()]
+                ),
+                (posts[2].clone(), vec![comments[1].clone()]),
+            ]
+        );
+
+        let loaded = conn
+            .eager_load::<Post>(QueryBuilder::new())
+            .with_parent::<Author>()
+            .await?;
+        assert_eq!(
+            loaded
+                .into_iter()
+                .map(|(p, a)| (p.id, a.map(|a| a.id)))
+                .collect::<Vec<_>>(),
+            vec![(1, Some(1)), (2, Some(1)), (3, Some(2))]
+        );
+
+        assert_eq!(
+            conn.load_parent::<Comment, Post>(&comments[1]).await?,
+            posts[2]
+        );
+        assert_eq!(
+            conn.load_children::<Post, Comment>(&posts[0]).await?,
+            vec![comments[0].clone(), comments[2].clone()]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_many_to_many() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        #[sql(
+            has_many_through = "Tag",
+            through = "PostTag",
+            foreign_key = "post_id",
+            target_key = "tag_id"
+        )]
+        struct Post {
+            id: i64,
+            title: String,
+        }
+
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "tag", primary_key = "id")]
+        struct Tag {
+            id: i64,
+            name: String,
+        }
+
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post_tag", primary_key = "post_id,tag_id")]
+        struct PostTag {
+            post_id: i64,
+            tag_id: i64,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<Post>().await?;
+        conn.create_table::<Tag>().await?;
+        conn.create_table::<PostTag>().await?;
+
+        let posts = (1..=3)
+            .map(|id| Post {
+                id,
+                title: format!("post-{}", id),
+            })
+            .collect::<Vec<_>>();
+        let tags = (1..=3)
+            .map(|id| Tag {
+                id,
+                name: format!("tag-{}", id),
+            })
+            .collect::<Vec<_>>();
+        conn.create_all(posts.clone()).await?;
+        conn.create_all(tags.clone()).await?;
+
+        assert_eq!(conn.attach::<Post, Tag>(&posts[0], vec![1, 2]).await?, 2);
+        assert_eq!(conn.attach::<Post, Tag>(&posts[1], vec![2]).await?, 1);
+        assert_eq!(conn.attach::<Post, Tag>(&posts[2], vec![]).await?, 0);
+
+        let loaded = conn
+            .eager_load::<Post>(QueryBuilder::new().order_by("id", Ordering::Ascending))
+            .with_through::<Tag>()
+            .await?;
+        assert_eq!(
+            loaded,
+            vec![
+                (posts[0].clone(), vec![tags[0].clone(), tags[1].clone()]),
+                (posts[1].clone(), vec![tags[1].clone()]),
+                (posts[2].clone(), vec![]),
+            ]
+        );
+
+        assert_eq!(conn.detach::<Post, Tag>(&posts[0], vec![1, 3]).await?, 1);
+        assert_eq!(
+            conn.load_through::<Post, Tag>(&posts[0]).await?,
+            vec![tags[1].clone()]
+        );
+
+        // tag 2 is kept, tag 1 and 3 are attached
+        assert_eq!(
+            conn.sync::<Post, Tag>(&posts[0], vec![3, 2, 1, 3]).await?,
+            2
+        );
+        // tag 1 and 2 are detached
+        assert_eq!(conn.sync::<Post, Tag>(&posts[0], vec![3]).await?, 2);
+        assert_eq!(
+            conn.load_through::<Post, Tag>(&posts[0]).await?,
+            vec![tags[2].clone()]
+        );
+        assert_eq!(
+            conn.load_through::<Post, Tag>(&posts[1]).await?,
+            vec![tags[1].clone()]
+        );
+
+        // a failing INSERT rolls back the DELETE in the same sync
+        conn.sql_exec(
+            "CREATE TRIGGER reject_tag BEFORE INSERT ON post_tag WHEN NEW.tag_id = 99 BEGIN SELECT RAISE(ABORT, 'rejected'); END"
+                .to_string(),
+            Params::new(),
+        )
+        .await?;
+        assert!(conn.sync::<Post, Tag>(&posts[0], vec![99]).await.is_err());
+        assert_eq!(
+            conn.load_through::<Post, Tag>(&posts[0]).await?,
+            vec![tags[2].clone()]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_load_joined() -> Result<(), Error> {
+        #[derive(Table, PartialEq, Debug, Clone)]
+        #[sql(table_name = "post", primary_key = "id")]
+        struct Post {
+            id: i64,
+            user_id: i64,
+            name: String,
+        }
+
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.create_table::<User>().await?;
+        conn.create_table::<Post>().await?;
+
+        let users = (1..=2).map(user).collect::<Vec<_>>();
+        let posts = vec![(10, 1), (11, 1)]
+            .into_iter()
+            .map(|(id, user_id)| Post {
+                id,
+                user_id,
+                name: format!("post-{}", id),
+            })
+            .collect::<Vec<_>>();
+        conn.create_all(users.clone()).await?;
+        conn.create_all(posts.clone()).await?;
+
+        // both tables have id and name columns
+        let loaded = conn
+            .load_joined::<(User, Post)>(
+                QueryBuilder::new()
+                    .inner_join(table_name::<Post>(), ("id", "user_id"))
+                    .order_by("post.id", Ordering::Ascending),
+            )
+            .await?;
+        assert_eq!(
+            loaded,
+            vec![
+                (users[0].clone(), posts[0].clone()),
+                (users[0].clone(), posts[1].clone()),
+            ]
+        );
+
+        let loaded = conn
+            .load_joined::<(User, Option<Post>)>(
+                QueryBuilder::new()
+                    .left_join(table_name::<Post>(), ("id", "user_id"))
+                    .order_by("user.id", Ordering::Ascending)
+                    .order_by("post.id", Ordering::Ascending),
+            )
+            .await?;
+        assert_eq!(
+            loaded,
+            vec![
+                (users[0].clone(), Some(posts[0].clone())),
+                (users[0].clone(), Some(posts[1].clone())),
+                (users[1].clone(), None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pool() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("debil-pool-{}.db", std::process::id()));
+        let pool = DebilPool::open(
+            &path,
+            PoolConfig {
+                max_size: 2,
+                ..PoolConfig::default()
+            },
+        );
+
+        pool.get().await?.create_table::<User>().await?;
+        let handles = (0..8)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let mut conn = pool.get().await?;
+                    conn.create(User {
+                        id: i,
+                        name: format!("user-{}", i),
+                        age: 20,
+                    })
+                    .await
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.await.unwrap()?;
+        }
+
+        let mut conn = pool.get().await?;
+        assert_eq!(conn.load::<User>(QueryBuilder::new()).await?.len(), 8);
+        let metrics = pool.metrics();
+        assert_eq!(metrics.checkouts, 10);
+        assert!(metrics.created <= 2);
+        assert_eq!(metrics.in_use, 1);
+        assert_eq!(metrics.connections, metrics.created as usize);
+        drop(conn);
+        assert_eq!(pool.metrics().in_use, 0);
+
+        // every idle connection expires at once
+        let pool = DebilPool::open(
+            &path,
+            PoolConfig {
+                idle_timeout: Some(std::time::Duration::from_secs(0)),
+                ..PoolConfig::default()
+            },
+        );
+        drop(pool.get().await?);
+        drop(pool.get().await?);
+        let metrics = pool.metrics();
+        assert_eq!((metrics.created, metrics.closed_idle), (2, 2));
+        assert_eq!(metrics.connections, 0);
+
+        // a connection returned in a transaction is closed instead of being reused
+        let pool = DebilPool::open(&path, PoolConfig::default());
+        let mut conn = pool.get().await?;
+        conn.start_transaction().await?;
+        conn.create(User {
+            id: 100,
+            name: "user-100".to_string(),
+            age: 20,
+        })
+        .await?;
+        drop(conn);
+        let metrics = pool.metrics();
+        assert_eq!((metrics.discarded, metrics.idle), (1, 0));
+        let mut conn = pool.get().await?;
+        assert_eq!(conn.load::<User>(QueryBuilder::new()).await?.len(), 8);
+        conn.start_transaction().await?;
+        conn.commit().await?;
+        drop(conn);
+        assert_eq!(pool.metrics().idle, 1);
+
+        // so is a connection whose stream of rows was dropped before its end
+        let mut conn = pool.get().await?;
+        {
+            use futures::stream::StreamExt;
+
+            let mut users = conn.load_stream::<User>(QueryBuilder::new());
+            users.next().await.unwrap()?;
+        }
+        drop(conn);
+        assert_eq!(pool.metrics().discarded, 2);
+
+        for suffix in &["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        Ok(())
+    }
+
+    // runs on a current-thread runtime, where DebilConn would panic
+    #[tokio::test]
+    async fn test_async_conn() -> Result<(), Error> {
+        let mut conn = AsyncDebilConn::open_in_memory()?;
+        conn.create_table::<User>().await?;
+
+        let handles = (0..4)
+            .map(|i| {
+                let mut conn = conn.clone();
+                tokio::spawn(async move { conn.create(user(i)).await })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.await.unwrap()?;
+        }
+        conn.create_all((4..10).map(user).collect()).await?;
+
+        let users = conn
+            .load::<User>(QueryBuilder::new().filter("age >= 25"))
+            .await?;
+        assert_eq!(users.len(), 5);
+        assert_eq!(
+            conn.call(
+                |conn| conn.query_row("SELECT COUNT(*) FROM user", [], |row| row.get::<_, i64>(0))
+            )
+            .await?,
+            10
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_conn_transactions() -> Result<(), Error> {
+        async fn count(conn: &AsyncDebilConn) -> Result<i64, Error> {
+            conn.call(|conn| conn.query_row("SELECT COUNT(*) FROM user", [], |row| row.get(0)))
+                .await
+        }
+
+        let mut conn = AsyncDebilConn::open_in_memory()?;
+        conn.create_table::<User>().await?;
+
+        conn.start_transaction().await?;
+        conn.save(user(0)).await?;
+
+        // create_all of another clone waits for the transaction instead of joining it
+        let mut other = conn.clone();
+        let handle =
+            tokio::spawn(async move { other.create_all((1..4).map(user).collect()).await });
+        tokio::task::yield_now().await;
+        assert_eq!(count(&conn).await?, 1);
+
+        conn.rollback().await?;
+        handle.await.unwrap()?;
+        assert_eq!(count(&conn).await?, 3);
+
+        // dropping a clone rolls back its transaction
+        let mut other = conn.clone();
+        other.start_transaction().await?;
+        other.save(user(0)).await?;
+        drop(other);
+        assert_eq!(count(&conn).await?, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_stream() -> Result<(), Error> {
+        use futures::stream::{StreamExt, TryStreamExt};
+
+        let mut conn = AsyncDebilConn::open_in_memory()?;
+        conn.create_table::<User>().await?;
+        conn.create_all(
+            (0..1000)
+                .map(|i| User {
+                    id: i,
+                    name: format!("user-{}", i),
+                    age: i % 50,
+                })
+                .collect(),
+        )
+        .await?;
+
+        let total_age = conn
+            .load_stream::<User>(QueryBuilder::new())
+            .try_fold(0, |sum, user| async move { Ok(sum + user.age) })
+            .await?;
+        assert_eq!(total_age, 20 * 1225);
+
+        // dropping a stream halfway releases the connection
+        let first = conn
+            .load_stream::<User>(QueryBuilder::new().order_by("id", Ordering::Ascending))
+            .take(3)
+            .map_ok(|user| user.id)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(first, vec![0, 1, 2]);
+        assert_eq!(
+            conn.first::<User>(QueryBuilder::new().filter("id = 999"))
+                .await?
+                .name,
+            "user-999"
+        );
+
+        let err = conn
+            .sql_query_stream::<User>("SELECT * FROM missing".to_string(), Params::new())
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(matches!(err, Err(Error::SqliteError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_prepared_statement() -> Result<(), Error> {
+        let mut conn = DebilConn::new(rusqlite::Connection::open_in_memory().unwrap());
+        conn.set_statement_cache_capacity(4);
+        conn.create_table::<User>().await?;
+
+        let insert = conn
+            .prepare("INSERT INTO user (id, name, age) VALUES (:id, :name, :age)".to_string())
+            .await?;
+        for i in 0..10 {
+            conn.exec_statement(
+                &insert,
+                Params(vec![
+                    ("id".to_string(), SqlValue::serialize(i)),
+                    (
+                        "name".to_string(),
+                        SqlValue::serialize(format!("user-{}", i)),
+                    ),
+                    ("age".to_string(), SqlValue::serialize(20 + i)),
+                ]),
+            )
+            .await?;
+        }
+
+        let select = conn
+            .prepare("SELECT * FROM user WHERE age >= :age".to_string())
+            .await?;
+        let users = conn
+            .query_statement::<User>(
+                &select,
+                Params(vec![("age".to_string(), SqlValue::serialize(25_i64))]),
+            )
+            .await?;
+        assert_eq!(users.len(), 5);
+
+        assert!(conn.prepare("SELEC * FROM user".to_string()).await.is_err());
+
+        let mut conn = AsyncDebilConn::open_in_memory()?;
+        conn.set_statement_cache_capacity(4).await?;
+        assert!(matches!(
+            conn.prepare("SELECT * FROM missing".to_string()).await,
+            Err(Error::SqliteError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_query_hook() -> Result<(), Error> {
+        use futures::stream::TryStreamExt;
+
+        // (query, number of params, rows, failed) of each finished query
+        type Events = Vec<(String, usize, Option<u64>, bool)>;
+
+        #[derive(Clone, Default)]
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Events>>);
+
+        impl QueryHook<SqliteValue, Error> for Recorder {
+            fn after_query(&self, event: &QueryEvent<'_, SqliteValue, Error>) {
+                self.0.lock().unwrap().push((
+                    event.query.to_string(),
+                    event.params.0.len(),
+                    event.rows,
+                    event.error.is_some(),
+                ));
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut conn = Instrumented::new(DebilConn::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ))
+        .hook(recorder.clone());
+        conn.create_table::<User>().await?;
+
+        conn.start_transaction().await?;
+        conn.create(User {
+            id: 1,
+            name: "foo".to_string(),
+            age: 20,
+        })
+        .await?;
+        conn.commit().await?;
+        assert_eq!(conn.load::<User>(QueryBuilder::new()).await?.len(), 1);
+        assert!(conn
+            .sql_exec("SELECT * FROM missing".to_string(), Params::new())
+            .await
+            .is_err());
+
+        // streams are reported with the rows read, and without rows when dropped before their end
+        let users: Vec<User> = conn
+            .load_stream::<User>(QueryBuilder::new())
+            .try_collect()
+            .await?;
+        assert_eq!(users.len(), 1);
+        let mut users = conn.load_stream::<User>(QueryBuilder::new());
+        assert!(users.try_next().await?.is_some());
+        drop(users);
+
+        let events = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            events
+                .iter()
+                .map(|(_, params, rows, failed)| (*params, *rows, *failed))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, Some(0), false),
+                (0, None, false),
+                (3, Some(1), false),
+                (0, None, false),
+                (0, Some(1), false),
+                (0, None, true),
+                (0, Some(1), false),
+                (0, None, false),
+            ]
+        );
+        assert_eq!(events[1].0, "BEGIN");
+        assert!(events[2].0.starts_with("INSERT INTO"));
+        assert_eq!(events[3].0, "COMMIT");
+
+        Ok(())
+    }
+
+    // Without tracing, slow queries still reach the hooks
+    #[cfg(not(feature = "tracing"))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_slow_query_without_tracing() -> Result<(), Error> {
+        #[derive(Clone, Default)]
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+        impl QueryHook<SqliteValue, Error> for Recorder {
+            fn slow_query(&self, query: &SlowQuery<'_, SqliteValue>) {
+                self.0.lock().unwrap().push(query.statement.clone());
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut conn = Instrumented::new(DebilConn::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ))
+        .hook(recorder.clone())
+        .slow_query_threshold(std::time::Duration::from_secs(0));
+        conn.create_table::<User>().await?;
+        conn.create(user(1)).await?;
+
+        let slow = recorder.0.lock().unwrap().clone();
+        assert_eq!(slow.len(), 2);
+        assert!(slow[0].starts_with("CREATE TABLE"));
+        assert!(slow[1].starts_with("INSERT INTO"));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_slow_query() -> Result<(), Error> {
+        // (statement, details of the plan) of each slow query
+        type Slow = Vec<(String, Option<Vec<String>>)>;
+
+        #[derive(Clone, Default)]
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Slow>>);
+
+        impl QueryHook<SqliteValue, Error> for Recorder {
+            fn slow_query(&self, query: &SlowQuery<'_, SqliteValue>) {
+                let plan = query.plan.as_ref().map(|rows| {
+                    rows.iter()
+                        .map(|row| {
+                            SqlValue::deserialize(row.get_by_name("detail").unwrap().clone())
+                        })
+                        .collect()
+                });
+                self.0.lock().unwrap().push((query.statement.clone(), plan));
+            }
+        }
+
+        let recorder = Recorder::default();
+        // every query is slow with a zero threshold
+        let mut conn = Instrumented::new(DebilConn::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ))
+        .hook(recorder.clone())
+        .slow_query_threshold(std::time::Duration::from_secs(0))
+        .explain_slow_queries();
+        conn.create_table::<User>().await?;
+        conn.create(User {
+            id: 1,
+            name: "it's".to_string(),
+            age: 20,
+        })
+        .await?;
+        conn.first::<User>(QueryBuilder::new().wheres((
+            vec!["id = :id"],
+            vec![("id".to_string(), SqlValue::serialize(1_i64))],
+        )))
+        .await?;
+
+        let slow = recorder.0.lock().unwrap().clone();
+        assert_eq!(slow.len(), 3);
+        // CREATE TABLE has an empty query plan
+        assert_eq!(slow[0].1, Some(vec![]));
+        assert!(slow[1].0.ends_with("VALUES (1, 'it''s', 20)"));
+        assert!(slow[2].0.contains("id = 1"));
+        assert_eq!(
+            slow[2].1,
+            Some(vec![
+                "SEARCH user USING INTEGER PRIMARY KEY (rowid=?)".to_string()
+            ])
+        );
+
+        let plan = QueryBuilder::new()
+            .table("user")
+            .wheres((
+                vec!["age > :age"],
+                vec![("age".to_string(), SqlValue::serialize(10_i64))],
+            ))
+            .explain(conn.get_mut())
+            .await?;
+        assert_eq!(plan.len(), 1);
+        assert_eq!(
+            plan[0]
+                .get_by_name("detail")
+                .cloned()
+                .map(SqlValue::<String>::deserialize),
+            Some("SCAN user".to_string())
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_create_and_drop_index() -> Result<()> {
         let conn = rusqlite::Connection::open_in_memory()?;
@@ -59,16 +1286,22 @@ mod tests {
         )?;
 
         // test: create_index should be executed
-        conn.execute(&create_index("person", "name", &["name"]), [])?;
+        conn.execute(
+            &create_index(&SqliteDialect, "person", "name", &["name"]),
+            [],
+        )?;
 
         // test: create_index should be executed even if the index already exists
-        conn.execute(&create_index("person", "name", &["name"]), [])?;
+        conn.execute(
+            &create_index(&SqliteDialect, "person", "name", &["name"]),
+            [],
+        )?;
 
         // test: drop_index should be executed
-        conn.execute(&drop_index("person", "name"), [])?;
+        conn.execute(&drop_index(&SqliteDialect, "person", "name"), [])?;
 
         // test: drop_index should be executed even if the index does not exists
-        conn.execute(&drop_index("person", "name"), [])?;
+        conn.execute(&drop_index(&SqliteDialect, "person", "name"), [])?;
 
         Ok(())
     }